@@ -11,7 +11,7 @@ statements=(
 
     "cargo +nightly test --no-default-features --features nightly"
 
-    "cargo +nightly miri test --no-default-features --features nightly -- --nocapture"
+    "MIRIFLAGS=-Zmiri-disable-isolation cargo +nightly miri test --no-default-features --features nightly -- --nocapture"
 
     ".github/scripts/concurrent_test.sh"
 
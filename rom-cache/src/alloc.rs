@@ -0,0 +1,48 @@
+//! Attribution of the memory allocated by a [`Cache`](crate::Cache), see [`CacheBuilder::alloc_hook()`](crate::CacheBuilder::alloc_hook()).
+
+use std::alloc::Layout;
+use std::sync::Arc;
+
+/// Observe the allocations owned by a [`Cache`](crate::Cache), e.g. to attribute memory to the subsystem using it.
+///
+/// Reported are the `CacheGroup`s and `CacheLine`s allocated with the cache, named by the cache type,
+/// and each `Cacheable` boxed into a `CacheLine`, named by the `Cacheable` type,
+/// until evicted or moved out (e.g. into the write buffer). Zero-sized ones are not reported.
+/// The heap memory owned by a `Cacheable` itself is not, see [`Cacheable::weight()`](crate::Cacheable::weight()).
+///
+/// The hook is called with the lock of a `CacheGroup` held, so it must not access the cache.
+pub trait AllocHook: Send + Sync {
+    /// `layout` is allocated for `type_name`.
+    fn alloc(&self, type_name: &'static str, layout: Layout);
+    /// `layout` allocated for `type_name` is released.
+    fn dealloc(&self, type_name: &'static str, layout: Layout);
+}
+
+/// An [`AllocHook`] shared by the clones of a `Config`.
+#[derive(Clone)]
+pub(crate) struct SharedAllocHook(pub(crate) Arc<dyn AllocHook>);
+
+impl std::fmt::Debug for SharedAllocHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedAllocHook")
+    }
+}
+
+impl SharedAllocHook {
+    /// Report the allocation of `type_name` changing from `from` to `to`.
+    pub(crate) fn realloc(
+        &self,
+        from: Option<(&'static str, Layout)>,
+        to: Option<(&'static str, Layout)>,
+    ) {
+        if from == to {
+            return;
+        }
+        if let Some((type_name, layout)) = from {
+            self.0.dealloc(type_name, layout);
+        }
+        if let Some((type_name, layout)) = to {
+            self.0.alloc(type_name, layout);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Extract cached values in `axum` handlers. Enabled by feature `axum`.
+//!
+//! Put the [`Cache`] in the state of the `Router`, or in a state it can be extracted from by `FromRef`,
+//! then take [`CachedRef<T>`] parameters in the handlers:
+//!
+//! ```ignore
+//! # use rom_cache::{axum::CachedRef, Cache};
+//! async fn handler(config: CachedRef<Config>) -> String {
+//!     config.name.clone()
+//! }
+//! let app = Router::new().route("/", get(handler)).with_state(Cache::<16, 4>::default());
+//! ```
+//!
+//! A `Cache` of another shape is extracted by `CachedRef<T, G, L>`.
+
+use crate::cache::{Cache, Cacheable, OwnedCacheRef};
+use crate::global::{GROUPS, LINES};
+use crate::CacheError;
+use axum_core::extract::{FromRef, FromRequestParts};
+use axum_core::response::{IntoResponse, Response};
+use http::header::RETRY_AFTER;
+use http::request::Parts;
+use http::StatusCode;
+use std::ops::Deref;
+
+/// An extractor retrieving T by [`Cache::get_owned()`] from the `Cache<G, L>` in the state,
+/// rejecting the request with [`CacheRejection`] if it fails.
+pub struct CachedRef<T: Cacheable, const G: usize = GROUPS, const L: usize = LINES>(
+    pub OwnedCacheRef<T, G, L>,
+);
+
+impl<T: Cacheable, const G: usize, const L: usize> Deref for CachedRef<T, G, L> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<S, T, const G: usize, const L: usize> FromRequestParts<S> for CachedRef<T, G, L>
+where
+    S: Send + Sync,
+    Cache<G, L>: FromRef<S>,
+    T: Cacheable + Send + Sync + Default,
+{
+    type Rejection = CacheRejection;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Cache::<G, L>::from_ref(state)
+            .get_owned()
+            .map(CachedRef)
+            .map_err(CacheRejection)
+    }
+}
+
+/// The rejection of [`CachedRef`]: `503 Service Unavailable` with `Retry-After: 1`
+/// for [`CacheError::Busy`], [`CacheError::Locked`], [`CacheError::RateLimited`] and [`CacheError::CircuitOpen`],
+/// which go away by retrying,
+/// or `500 Internal Server Error` for the others.
+#[derive(Debug)]
+pub struct CacheRejection(pub CacheError);
+
+impl IntoResponse for CacheRejection {
+    fn into_response(self) -> Response {
+        match self.0 {
+            CacheError::Busy
+            | CacheError::Locked
+            | CacheError::RateLimited { .. }
+            | CacheError::CircuitOpen { .. } => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(RETRY_AFTER, "1")],
+                self.0.to_string(),
+            )
+                .into_response(),
+            e => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+}
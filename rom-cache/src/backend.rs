@@ -0,0 +1,200 @@
+//! Byte-level storage shared by the `Cacheable`s of a [`Cache`](crate::Cache), see [`Backend`].
+//!
+//! A `Cacheable` with a [`Cacheable::KEY`] only (de)serializes itself, and is loaded from and stored to
+//! the `Backend` of the cache under its key, so that the same types can be cached against files in tests
+//! and a database in production:
+//!
+//! ```ignore
+//! # use rom_cache::{backend::Dir, Cache, Cacheable};
+//! impl Cacheable for Settings {
+//!     const KEY: Option<&'static str> = Some("settings");
+//!     fn encode(&self) -> std::io::Result<Vec<u8>> { serde_json::to_vec(self).map_err(Into::into) }
+//!     fn decode(bytes: &[u8]) -> std::io::Result<Self> { serde_json::from_slice(bytes).map_err(Into::into) }
+//! }
+//! let cache: Cache<4, 2> = Cache::with_backend(Dir::new("/var/lib/app"));
+//! ```
+
+use crate::cache::Cacheable;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Store the bytes of the `Cacheable`s with a [`Cacheable::KEY`], see [`CacheBuilder::backend()`](crate::CacheBuilder::backend()).
+///
+/// It is called from the threads accessing the cache and the ones writing back in background,
+/// possibly with the lock of a `CacheGroup` held while evicting, so it must not access the cache.
+pub trait Backend: Send + Sync {
+    /// The bytes stored under `key`, None if nothing is.
+    fn load(&self, key: &str) -> std::io::Result<Option<Vec<u8>>>;
+    /// Store `bytes` under `key`, replacing the ones stored before.
+    fn store(&self, key: &str, bytes: &[u8]) -> std::io::Result<()>;
+}
+
+/// Store the bytes of several `Cacheable`s at once, e.g. in one transaction, see
+/// [`CacheBuilder::store_batch()`](crate::CacheBuilder::store_batch()).
+///
+/// It is called with the locks of all `CacheGroup`s held, so it must not access the cache.
+pub trait StoreBatch: Send + Sync {
+    /// Store every `(key, bytes)` of `entries`, all of them or none if possible.
+    fn store_batch(&self, entries: &[(&str, &[u8])]) -> std::io::Result<()>;
+}
+
+impl<B: StoreBatch + ?Sized> StoreBatch for Arc<B> {
+    fn store_batch(&self, entries: &[(&str, &[u8])]) -> std::io::Result<()> {
+        B::store_batch(self, entries)
+    }
+}
+
+impl<B: Backend + ?Sized> Backend for Arc<B> {
+    fn load(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        B::load(self, key)
+    }
+
+    fn store(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        B::store(self, key, bytes)
+    }
+}
+
+/// A [`Backend`] storing each key as a file in a directory, created on first store.
+/// A key with `/`, e.g. a namespaced one (see [`Cache::scope()`](crate::Cache::scope())), is stored in a subdirectory.
+#[derive(Debug, Clone)]
+pub struct Dir {
+    path: PathBuf,
+}
+
+impl Dir {
+    /// Store the files in `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The file of `key`, failing with `ErrorKind::InvalidInput` if it would be outside the directory,
+    /// e.g. an absolute one or containing `..`.
+    fn file(&self, key: &str) -> std::io::Result<PathBuf> {
+        let relative = Path::new(key);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            let msg = format!("the key {key:?} is outside the directory");
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+        Ok(self.path.join(relative))
+    }
+}
+
+impl Backend for Dir {
+    fn load(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.file(key)?) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn store(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let file = self.file(key)?;
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // replaced at once, so that a failed write never leaves a truncated file
+        let tmp = self.file(&format!("{key}.tmp"))?;
+        std::fs::write(&tmp, bytes)?;
+        std::fs::rename(tmp, file)
+    }
+}
+
+/// A [`Backend`] keeping the bytes in memory, e.g. in tests, which stores a batch under a single lock.
+/// Share it by `Arc<Memory>` to inspect the stored bytes.
+#[derive(Debug, Default)]
+pub struct Memory {
+    entries: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl Memory {
+    /// The bytes stored under `key`.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.lock().get(key).cloned()
+    }
+
+    /// Store `bytes` under `key`, e.g. to seed the storage.
+    pub fn insert(&self, key: &str, bytes: impl Into<Vec<u8>>) {
+        self.lock().insert(key.to_string(), bytes.into());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<u8>>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Backend for Memory {
+    fn load(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(self.get(key))
+    }
+
+    fn store(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        self.insert(key, bytes);
+        Ok(())
+    }
+}
+
+impl StoreBatch for Memory {
+    fn store_batch(&self, entries: &[(&str, &[u8])]) -> std::io::Result<()> {
+        let mut stored = self.lock();
+        for (key, bytes) in entries {
+            stored.insert(key.to_string(), bytes.to_vec());
+        }
+        Ok(())
+    }
+}
+
+/// A [`Backend`] shared by the clones of a `Config`.
+#[derive(Clone)]
+pub(crate) struct SharedBackend(pub(crate) Arc<dyn Backend>);
+
+impl std::fmt::Debug for SharedBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedBackend")
+    }
+}
+
+/// A [`StoreBatch`] shared by the clones of a `Config`.
+#[derive(Clone)]
+pub(crate) struct SharedStoreBatch(pub(crate) Arc<dyn StoreBatch>);
+
+impl std::fmt::Debug for SharedStoreBatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedStoreBatch")
+    }
+}
+
+/// Load T from `backend` if T has a [`Cacheable::KEY`], by [`Cacheable::load()`] otherwise.
+pub(crate) fn load<T: Cacheable>(backend: Option<&SharedBackend>) -> std::io::Result<T> {
+    let (Some(backend), Some(key)) = (backend, T::KEY) else {
+        return T::load();
+    };
+    let key = crate::namespace::key(key);
+    match backend.0.load(&key)? {
+        Some(bytes) => T::decode(&bytes),
+        None => Err(Error::new(
+            ErrorKind::NotFound,
+            format!("nothing stored under {key}"),
+        )),
+    }
+}
+
+/// Store `value` to `backend` if T has a [`Cacheable::KEY`], return None otherwise.
+pub(crate) fn store<T: Cacheable>(
+    value: &T,
+    backend: Option<&SharedBackend>,
+) -> Option<std::io::Result<()>> {
+    let (Some(backend), Some(key)) = (backend, T::KEY) else {
+        return None;
+    };
+    Some(
+        value
+            .encode()
+            .and_then(|bytes| backend.0.store(&crate::namespace::key(key), &bytes)),
+    )
+}
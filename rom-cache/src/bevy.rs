@@ -0,0 +1,211 @@
+//! Access the cache from Bevy systems. Enabled by feature `bevy`.
+//!
+//! A [`Cache`] is a `Resource`, and the system parameters [`Cached<T>`] and [`CachedMut<T>`] retrieve T through it:
+//!
+//! ```ignore
+//! # use rom_cache::{bevy::{Cached, CachedMut}, Cache};
+//! fn apply_settings(settings: Cached<Settings>) {
+//!     if settings.is_changed() {
+//!         let settings = settings.get().unwrap();
+//!         // ...
+//!     }
+//! }
+//! world.insert_resource(Cache::<16, 4>::default());
+//! ```
+//!
+//! `CachedMut` accesses the `Cache` mutably for the scheduler, so that it never runs in parallel
+//! with other systems using the same `Cache`, and its `CacheMut`s never fail with `CacheError::Locked`
+//! because of them. A `Cache` of another shape is accessed by `Cached<T, G, L>` and `CachedMut<T, G, L>`.
+
+use crate::cache::{Cache, CacheMut, CacheRef, Cacheable};
+use crate::global::{GROUPS, LINES};
+use crate::CacheResult;
+use bevy_ecs::change_detection::Tick;
+use bevy_ecs::query::FilteredAccessSet;
+use bevy_ecs::resource::Resource;
+use bevy_ecs::system::{
+    Local, ReadOnlySystemParam, Res, ResMut, SystemMeta, SystemParam, SystemParamValidationError,
+};
+use bevy_ecs::world::unsafe_world_cell::UnsafeWorldCell;
+use bevy_ecs::world::World;
+use std::marker::PhantomData;
+use std::sync::mpsc::Receiver;
+
+impl<const G: usize, const L: usize> Resource for Cache<G, L> {}
+
+/// The modifications and loads of T observed by a system, see [`Cache::watch()`].
+pub struct Changes<T> {
+    watch: Option<Receiver<()>>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for Changes<T> {
+    fn default() -> Self {
+        Self {
+            watch: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: Cacheable + Send + Sync> Changes<T> {
+    /// Whether T was loaded or modified since last polled, true the first time.
+    fn poll<const G: usize, const L: usize>(&mut self, cache: &Cache<G, L>) -> bool {
+        match &self.watch {
+            Some(watch) => watch.try_iter().count() > 0,
+            None => {
+                self.watch = Some(cache.watch::<T>());
+                true
+            }
+        }
+    }
+}
+
+/// A system parameter retrieving T from the `Cache<G, L>` resource.
+pub struct Cached<
+    'w,
+    's,
+    T: Cacheable + Send + Sync + Default,
+    const G: usize = GROUPS,
+    const L: usize = LINES,
+> {
+    cache: Res<'w, Cache<G, L>>,
+    changed: bool,
+    _changes: PhantomData<&'s Changes<T>>,
+}
+
+impl<T: Cacheable + Send + Sync + Default, const G: usize, const L: usize> Cached<'_, '_, T, G, L> {
+    /// Retrieve T, see [`Cache::get()`].
+    pub fn get(&self) -> CacheResult<CacheRef<'_, T>> {
+        self.cache.get()
+    }
+
+    /// Whether T was loaded or modified since the system last ran, including by the system itself,
+    /// or the system runs for the first time.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// The cache.
+    pub fn cache(&self) -> &Cache<G, L> {
+        &self.cache
+    }
+}
+
+/// A system parameter retrieving T mutably from the `Cache<G, L>` resource.
+pub struct CachedMut<
+    'w,
+    's,
+    T: Cacheable + Send + Sync + Default,
+    const G: usize = GROUPS,
+    const L: usize = LINES,
+> {
+    cache: ResMut<'w, Cache<G, L>>,
+    changed: bool,
+    _changes: PhantomData<&'s Changes<T>>,
+}
+
+impl<T: Cacheable + Send + Sync + Default, const G: usize, const L: usize>
+    CachedMut<'_, '_, T, G, L>
+{
+    /// Retrieve T, see [`Cache::get()`].
+    pub fn get(&self) -> CacheResult<CacheRef<'_, T>> {
+        self.cache.get()
+    }
+
+    /// Retrieve T mutably, see [`Cache::get_mut()`].
+    pub fn get_mut(&mut self) -> CacheResult<CacheMut<'_, T>> {
+        // through `Deref`, the resource itself is not changed
+        (*self.cache).get_mut()
+    }
+
+    /// Whether T was loaded or modified since the system last ran, including by the system itself,
+    /// or the system runs for the first time.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// The cache.
+    pub fn cache(&self) -> &Cache<G, L> {
+        &self.cache
+    }
+}
+
+/// Implement `SystemParam` for `$param` by delegating to `($res, Local<Changes<T>>)`.
+macro_rules! impl_system_param {
+    ($param:ident, $res:ident) => {
+        // SAFETY: delegated to `($res, Local)`, whose accesses are the only ones used
+        unsafe impl<T, const G: usize, const L: usize> SystemParam for $param<'_, '_, T, G, L>
+        where
+            T: Cacheable + Send + Sync + Default,
+        {
+            type State =
+                <($res<'static, Cache<G, L>>, Local<'static, Changes<T>>) as SystemParam>::State;
+            type Item<'w, 's> = $param<'w, 's, T, G, L>;
+
+            fn init_state(world: &mut World) -> Self::State {
+                <($res<'_, Cache<G, L>>, Local<'_, Changes<T>>)>::init_state(world)
+            }
+
+            fn init_access(
+                state: &Self::State,
+                system_meta: &mut SystemMeta,
+                component_access_set: &mut FilteredAccessSet,
+                world: &mut World,
+            ) {
+                <($res<'_, Cache<G, L>>, Local<'_, Changes<T>>)>::init_access(
+                    state,
+                    system_meta,
+                    component_access_set,
+                    world,
+                );
+            }
+
+            unsafe fn validate_param(
+                state: &mut Self::State,
+                system_meta: &SystemMeta,
+                world: UnsafeWorldCell<'_>,
+            ) -> Result<(), SystemParamValidationError> {
+                // SAFETY: guaranteed by the caller
+                unsafe {
+                    <($res<'_, Cache<G, L>>, Local<'_, Changes<T>>)>::validate_param(
+                        state,
+                        system_meta,
+                        world,
+                    )
+                }
+            }
+
+            unsafe fn get_param<'w, 's>(
+                state: &'s mut Self::State,
+                system_meta: &SystemMeta,
+                world: UnsafeWorldCell<'w>,
+                change_tick: Tick,
+            ) -> Self::Item<'w, 's> {
+                // SAFETY: guaranteed by the caller
+                let (cache, mut changes) = unsafe {
+                    <($res<'_, Cache<G, L>>, Local<'_, Changes<T>>)>::get_param(
+                        state,
+                        system_meta,
+                        world,
+                        change_tick,
+                    )
+                };
+                $param {
+                    changed: changes.poll(&cache),
+                    cache,
+                    _changes: PhantomData,
+                }
+            }
+        }
+    };
+}
+
+impl_system_param!(Cached, Res);
+impl_system_param!(CachedMut, ResMut);
+
+// SAFETY: only reads the `Cache` resource, the `Local` is system state
+unsafe impl<T, const G: usize, const L: usize> ReadOnlySystemParam for Cached<'_, '_, T, G, L> where
+    T: Cacheable + Send + Sync + Default
+{
+}
@@ -0,0 +1,366 @@
+//! Builder for [`Cache`].
+
+use crate::alloc::{AllocHook, SharedAllocHook};
+use crate::backend::{Backend, SharedBackend, SharedStoreBatch, StoreBatch};
+use crate::cache::Cache;
+use crate::namespace::Quota;
+use std::time::Duration;
+
+/// A builder to configure a [`Cache`].
+///
+/// ```ignore
+/// # use rom_cache::Cache;
+/// let cache: Cache<8, 2> = Cache::builder().read_only(true).build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct CacheBuilder<const G: usize, const L: usize> {
+    config: Config,
+}
+
+impl<const G: usize, const L: usize> Default for CacheBuilder<G, L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const G: usize, const L: usize> CacheBuilder<G, L> {
+    /// Create a builder with the default configuration.
+    pub fn new() -> Self {
+        Self {
+            config: Config::default(),
+        }
+    }
+
+    /// Make the cache read only (i.e. a true ROM cache).
+    /// - `Cache::get_mut()` always returns `CacheError::ReadOnly`.
+    /// - No `CacheLine` will ever be dirty, so [`Cacheable::store()`](crate::Cacheable::store()) is never called.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.config.read_only = read_only;
+        self
+    }
+
+    /// Write back the least recently used dirty `CacheLine`s proactively
+    /// once more than `max_dirty` `CacheLine`s are dirty,
+    /// so that evictions are less likely to pay for a synchronous [`Cacheable::store()`](crate::Cacheable::store()).
+    ///
+    /// The check happens on each `Cache::get()` and `Cache::get_mut()`, and never fails them:
+    /// a failed write-back is logged and reported to the handler registered by [`Cache::on_store_error()`],
+    /// and the `CacheLine` stays dirty until written back by a later check, the eviction or the drop.
+    pub fn max_dirty(mut self, max_dirty: usize) -> Self {
+        self.config.max_dirty = Some(max_dirty);
+        self
+    }
+
+    /// Expire the cached `Cacheable`s after `ttl` since loaded.
+    /// An expired `CacheLine` is written back if dirty, and loaded again when accessed.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.config.ttl = Some(ttl);
+        self
+    }
+
+    /// Shorten the TTL (see [`CacheBuilder::ttl()`] and [`Cacheable::EXPIRE_AFTER`](crate::Cacheable::EXPIRE_AFTER))
+    /// of each loaded `Cacheable` by a pseudo-random part of up to `fraction`, e.g. `0.1` for up to 10%,
+    /// so that the `Cacheable`s loaded together do not expire at once and stampede the storage.
+    /// The TTL stays an upper bound.
+    pub fn ttl_jitter(mut self, fraction: f64) -> Self {
+        self.config.ttl_jitter = Some(fraction.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Keep serving an expired (see [`CacheBuilder::ttl()`]) `Cacheable`
+    /// while it is loaded again in a background thread.
+    /// The fresh one is swapped in when accessed after ready, unless the `CacheLine` is dirty or being used.
+    pub fn stale_while_revalidate(mut self, enable: bool) -> Self {
+        self.config.stale_while_revalidate = enable;
+        self
+    }
+
+    /// Load a `Cacheable` again in a background thread, if accessed within the last `fraction` of its TTL
+    /// (see [`CacheBuilder::ttl()`]), so that it never actually expires under load.
+    /// e.g. `0.2` for the last 20%. The fresh one is swapped in as [`CacheBuilder::stale_while_revalidate()`] does.
+    pub fn refresh_ahead(mut self, fraction: f64) -> Self {
+        self.config.refresh_ahead = Some(fraction.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Remember a failed [`Cacheable::load()`](crate::Cacheable::load()) for `negative_ttl`,
+    /// during which loading the same `Cacheable` fails immediately without calling it,
+    /// so that a missing or corrupt storage is not hammered on every access.
+    pub fn negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.config.negative_ttl = Some(negative_ttl);
+        self
+    }
+
+    /// Load at most `per_second` `Cacheable`s on cache misses, in bursts of at most `burst`, by a token bucket,
+    /// so that a miss storm does not overwhelm the storage. A `Cacheable` can be limited on its own by
+    /// [`Cacheable::LOAD_RATE`](crate::Cacheable::LOAD_RATE) as well.
+    ///
+    /// A miss over the limit serves the stale `Cacheable` if cached, e.g. expired or invalidated,
+    /// and fails with `CacheError::RateLimited` otherwise. [`Cache::refresh()`] is not limited.
+    pub fn load_rate(mut self, per_second: f64, burst: usize) -> Self {
+        self.config.load_rate = Some((per_second, burst));
+        self
+    }
+
+    /// Run at most `limit` loads at once across the cache, e.g. to stay within the file descriptor limit
+    /// on a cold start. The other loads wait in line, for at most [`CacheBuilder::load_timeout()`] if set,
+    /// failing with `CacheError::Timeout` then. The background reloads wait as well, and a load
+    /// abandoned by the timeout keeps its place until it returns.
+    pub fn max_loads(mut self, limit: usize) -> Self {
+        self.config.max_loads = Some(limit.max(1));
+        self
+    }
+
+    /// Give up loading a `Cacheable` on a cache miss after `timeout`, failing with `CacheError::Timeout`,
+    /// so that a hung storage does not block the accesses waiting for the same `Cacheable` forever.
+    /// [`Cacheable::load()`](crate::Cacheable::load()) runs on a helper thread then, which is abandoned
+    /// past the deadline and keeps running until the load returns. A timeout counts as a failure for
+    /// [`CacheBuilder::negative_ttl()`] and [`CacheBuilder::circuit_breaker()`].
+    pub fn load_timeout(mut self, timeout: Duration) -> Self {
+        self.config.load_timeout = Some(timeout);
+        self
+    }
+
+    /// Stop loading a `Cacheable` for `cooldown` once [`Cacheable::load()`](crate::Cacheable::load()) failed
+    /// `failures` times in a row, so that a failing storage is not hammered during an outage.
+    /// After the cooldown a single failure opens the circuit again, and a success closes it.
+    ///
+    /// A miss while the circuit is open serves the stale `Cacheable` if cached, e.g. the default one
+    /// used when loading failed, and fails with `CacheError::CircuitOpen` otherwise.
+    pub fn circuit_breaker(mut self, failures: usize, cooldown: Duration) -> Self {
+        self.config.circuit_breaker = Some((failures.max(1), cooldown));
+        self
+    }
+
+    /// Only admit a `Cacheable` into a full `CacheGroup` if it was also missed
+    /// within the last `window` distinct misses, tracked by a small bloom filter.
+    /// Otherwise it is loaded and served without being cached, so that one-shot accesses
+    /// never evict the resident hot `Cacheable`s. A `Cacheable` not admitted and modified
+    /// through `Cache::get_mut()` is written back when the `CacheMut` dropped.
+    pub fn doorkeeper(mut self, window: usize) -> Self {
+        self.config.doorkeeper = Some(window);
+        self
+    }
+
+    /// Let `Cache::get()` return the last committed `Cacheable`, i.e. as loaded or last written back,
+    /// instead of `CacheError::Locked` while it is being written.
+    /// Only `Cacheable`s implementing [`Cacheable::snapshot()`](crate::Cacheable::snapshot()) are captured.
+    pub fn snapshot_reads(mut self, enable: bool) -> Self {
+        self.config.snapshot_reads = enable;
+        self
+    }
+
+    /// Keep the total weight (see [`Cacheable::weight()`](crate::Cacheable::weight())) of the cached `Cacheable`s
+    /// within `max_bytes`, by evicting the least recently used clean ones first, then the dirty ones with write-back.
+    /// `Cacheable`s being used are never evicted, so the budget may be exceeded temporarily.
+    ///
+    /// A `Cacheable` is weighed when loaded and when written back. The check happens on each
+    /// `Cache::get()` and `Cache::get_mut()`, and never fails them: a dirty `Cacheable` failed to write back
+    /// is kept, so the budget may be exceeded until a later check, and the failure is handled as [`CacheBuilder::max_dirty()`] does.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.config.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Limit each namespace (see [`Cache::scope()`]) by `quota`, unless set by [`Cache::set_scope_quota()`].
+    /// A namespace holding as many `CacheLine`s as allowed evicts its own least recently used one on a miss
+    /// instead of the ones of the others, and the cold ones of a namespace over its quota are evicted as
+    /// [`CacheBuilder::max_bytes()`] does, checked on each access of the namespace.
+    pub fn scope_quota(mut self, quota: Quota) -> Self {
+        self.config.scope_quota = Some(quota);
+        self
+    }
+
+    /// Place each `Cacheable` in any `CacheGroup` instead of the one fixed by its type id.
+    /// A `Cacheable` is placed in the group with the most empty `CacheLine`s on its first access,
+    /// and stays there until moved by [`Cache::migrate()`] or [`Cache::rebalance()`].
+    ///
+    /// With only a few distinct `Cacheable`s, this uses the whole capacity `G * L`,
+    /// at the cost of locking every group once on the first access of each `Cacheable`.
+    pub fn fully_associative(mut self, enable: bool) -> Self {
+        self.config.fully_associative = enable;
+        self
+    }
+
+    /// Hand the dirty `Cacheable`s being evicted to a background thread to write back,
+    /// buffering at most `capacity` of them, so that an eviction does not wait for [`Cacheable::store()`](crate::Cacheable::store()).
+    /// Evictions write back synchronously as before while the buffer is full.
+    ///
+    /// A buffered `Cacheable` is written back before loaded again, and by [`Cache::close()`] or when dropped.
+    /// Failures in background are reported to the handler registered by [`Cache::on_store_error()`],
+    /// and the `Cacheable` stays buffered to be written back again.
+    pub fn write_buffer(mut self, capacity: usize) -> Self {
+        self.config.write_buffer = Some(capacity);
+        self
+    }
+
+    /// Write back the buffered `Cacheable`s with a pool of `workers` threads instead of one,
+    /// so that a slow [`Cacheable::store()`](crate::Cacheable::store()) does not hold up the others.
+    /// The same `Cacheable` is never written back by two threads at once.
+    ///
+    /// It enables [`CacheBuilder::write_buffer()`] with a capacity of `workers` if not configured.
+    pub fn store_workers(mut self, workers: usize) -> Self {
+        self.config.store_workers = Some(workers);
+        self
+    }
+
+    /// Split the `CacheLine`s of each `CacheGroup` into a cold tier and a hot tier of at most `lines` `CacheLine`s.
+    /// A `Cacheable` is loaded into the cold tier, and promoted into the hot tier when hit again,
+    /// demoting the least recently used hot one if full. Cold `CacheLine`s are evicted first.
+    ///
+    /// This keeps the frequently used `Cacheable`s from being evicted by a scan over many others.
+    /// [`Cacheable::PRIORITY`](crate::Cacheable::PRIORITY) still takes precedence over the tiers.
+    pub fn hot_tier(mut self, lines: usize) -> Self {
+        self.config.hot_tier = Some(lines);
+        self
+    }
+
+    /// Switch each `CacheGroup` between evicting the least recently used and the least frequently used
+    /// `CacheLine` by its hit ratio, measured every `window` accesses. Each `CacheGroup` starts with LRU,
+    /// tries LFU after the first window, and switches whenever the current policy underperforms for 2 windows
+    /// in a row, i.e. has a lower hit ratio than the other one did in its last window,
+    /// or than itself in its first window since switched to.
+    ///
+    /// This suits workloads alternating between scans, where LRU keeps nothing useful,
+    /// and recency-based access, where LFU keeps stale favorites.
+    /// The current policy of each `CacheGroup` is reported by [`Cache::tuning_report()`](crate::Cache::tuning_report()).
+    pub fn adaptive_policy(mut self, window: usize) -> Self {
+        self.config.adaptive_policy = Some(window.max(1));
+        self
+    }
+
+    /// Report two `Cacheable`s as thrashing if each evicts the other within `window` since evicted by it,
+    /// 1 second by default, see [`Cache::thrashing()`].
+    pub fn thrash_window(mut self, window: Duration) -> Self {
+        self.config.thrash_window = Some(window);
+        self
+    }
+
+    /// Record at most `depth` values of each `Cacheable` before modified through a `CacheMut`,
+    /// to be restored by [`Cache::undo()`] and [`Cache::redo()`]. The history survives evictions.
+    ///
+    /// Only `Cacheable`s implementing [`Cacheable::snapshot()`](crate::Cacheable::snapshot()) are recorded,
+    /// copied once per `CacheMut` when first dereferenced mutably.
+    pub fn history(mut self, depth: usize) -> Self {
+        self.config.history = Some(depth);
+        self
+    }
+
+    /// Evict in background every `interval`, so that each `CacheGroup` keeps an empty `CacheLine`
+    /// for the next miss, and `get` rarely waits for an eviction and its write-back.
+    /// The lower priority, cold and least recently used `CacheLine` not being used is evicted first,
+    /// `get` still evicts by itself if there is no empty `CacheLine`.
+    ///
+    /// This trades a `CacheLine` of each `CacheGroup` for the latency.
+    /// Failed write-backs are reported to the handler registered by [`Cache::on_store_error()`],
+    /// and the sweeper stops once the cache is dropped.
+    pub fn sweep(mut self, interval: Duration) -> Self {
+        self.config.sweep = Some(interval);
+        self
+    }
+
+    /// Report the `CacheRef`s and `CacheMut`s held longer than `threshold` to [`Cache::long_held()`],
+    /// and as warnings with the `log` feature, e.g. a leaked `CacheMut` behind endless `CacheError::Locked`.
+    /// In debug builds, where each was retrieved is captured as well if `RUST_BACKTRACE` is set.
+    ///
+    /// Each retrieval is recorded while enabled, checked in background every `threshold / 2`.
+    pub fn watchdog(mut self, threshold: Duration) -> Self {
+        self.config.watchdog = Some(threshold);
+        self
+    }
+
+    /// Check the memory usage of the cgroup (v2) of the process against its limit every `interval` in background,
+    /// and call [`Cache::on_memory_pressure()`] above 80%, 90% and 95% of the limit with
+    /// [`MemoryPressure::Low`](crate::MemoryPressure::Low), `Moderate` and `Critical` respectively.
+    /// Nothing happens without a limit. The checker stops once the cache is dropped.
+    #[cfg(all(feature = "cgroup", target_os = "linux"))]
+    pub fn cgroup_pressure(mut self, interval: Duration) -> Self {
+        self.config.cgroup_pressure = Some(interval);
+        self
+    }
+
+    /// Load and store the `Cacheable`s with a [`Cacheable::KEY`](crate::Cacheable::KEY) by `backend`,
+    /// (de)serialized by [`Cacheable::decode()`](crate::Cacheable::decode()) and [`Cacheable::encode()`](crate::Cacheable::encode()),
+    /// see [`Backend`]. The other `Cacheable`s still use their own `load` and `store`.
+    pub fn backend(mut self, backend: impl Backend + 'static) -> Self {
+        self.config.backend = Some(SharedBackend(std::sync::Arc::new(backend)));
+        self
+    }
+
+    /// Write back the dirty `Cacheable`s with a [`Cacheable::KEY`](crate::Cacheable::KEY) by one call of `batch`
+    /// when the cache is flushed (e.g. by [`Cache::close()`](crate::Cache::close()) or [`Cache::flush_on_drop()`](crate::Cache::flush_on_drop()))
+    /// and dropped, instead of one `store` each, see [`StoreBatch`].
+    /// The other dirty `Cacheable`s are still written back one by one.
+    pub fn store_batch(mut self, batch: impl StoreBatch + 'static) -> Self {
+        self.config.store_batch = Some(SharedStoreBatch(std::sync::Arc::new(batch)));
+        self
+    }
+
+    /// Let each thread keep copies of the last `capacity` `Cacheable`s read by [`Cache::get_replica()`],
+    /// so that read-heavy threads do not contend on the `CacheGroup`s while the `Cacheable`s are not modified.
+    /// A replica is outdated when its `Cacheable` is loaded or modified, or by [`Cache::bump_generation()`].
+    /// Without it, `Cache::get_replica()` copies the `Cacheable` each time.
+    pub fn read_replicas(mut self, capacity: usize) -> Self {
+        self.config.read_replicas = Some(capacity);
+        self
+    }
+
+    /// Spread the `CacheGroup`s over `nodes` NUMA nodes, and place each `Cacheable` in a group
+    /// of the node of the thread accessing it first instead of `type_id % G`, see [`numa`](crate::numa).
+    /// The placement is kept until moved by [`Cache::migrate()`] or [`Cache::rebalance()`],
+    /// and [`Cacheable::GROUP_HINT`](crate::Cacheable::GROUP_HINT) still takes precedence.
+    ///
+    /// With feature `numa` on Linux, the `CacheLine`s of each group are moved to its node when built,
+    /// as far as they span whole pages, so that large `L` benefits the most.
+    pub fn numa_nodes(mut self, nodes: usize) -> Self {
+        self.config.numa_nodes = Some(nodes.max(1));
+        self
+    }
+
+    /// Report the allocations owned by the cache to `hook`, see [`AllocHook`].
+    pub fn alloc_hook(mut self, hook: impl AllocHook + 'static) -> Self {
+        self.config.alloc_hook = Some(SharedAllocHook(std::sync::Arc::new(hook)));
+        self
+    }
+
+    /// Build the cache.
+    pub fn build(self) -> Cache<G, L> {
+        Cache::with_config(self.config)
+    }
+}
+
+/// The configuration shared by all `CacheGroup`s of a `Cache`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Config {
+    pub(crate) read_only: bool,
+    pub(crate) max_dirty: Option<usize>,
+    pub(crate) ttl: Option<Duration>,
+    pub(crate) ttl_jitter: Option<f64>,
+    pub(crate) stale_while_revalidate: bool,
+    pub(crate) refresh_ahead: Option<f64>,
+    pub(crate) negative_ttl: Option<Duration>,
+    pub(crate) load_rate: Option<(f64, usize)>,
+    pub(crate) circuit_breaker: Option<(usize, Duration)>,
+    pub(crate) load_timeout: Option<Duration>,
+    pub(crate) max_loads: Option<usize>,
+    pub(crate) doorkeeper: Option<usize>,
+    pub(crate) snapshot_reads: bool,
+    pub(crate) max_bytes: Option<usize>,
+    pub(crate) scope_quota: Option<Quota>,
+    pub(crate) fully_associative: bool,
+    pub(crate) write_buffer: Option<usize>,
+    pub(crate) store_workers: Option<usize>,
+    pub(crate) hot_tier: Option<usize>,
+    pub(crate) adaptive_policy: Option<usize>,
+    pub(crate) thrash_window: Option<Duration>,
+    pub(crate) history: Option<usize>,
+    pub(crate) sweep: Option<Duration>,
+    pub(crate) watchdog: Option<Duration>,
+    pub(crate) alloc_hook: Option<SharedAllocHook>,
+    pub(crate) numa_nodes: Option<usize>,
+    pub(crate) read_replicas: Option<usize>,
+    pub(crate) backend: Option<SharedBackend>,
+    pub(crate) store_batch: Option<SharedStoreBatch>,
+    #[cfg(all(feature = "cgroup", target_os = "linux"))]
+    pub(crate) cgroup_pressure: Option<Duration>,
+}
@@ -1,19 +1,35 @@
 //! Cache data structure
 
+use crate::backend::{Backend, SharedBackend};
+use crate::builder::{CacheBuilder, Config};
 use crate::error::CacheResult;
 use crate::CacheError;
 
 #[cfg(loom)]
-use loom::sync::{Arc, Mutex};
+use loom::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+#[cfg(loom)]
+use loom::sync::{Arc, Condvar, Mutex, MutexGuard};
+#[cfg(all(not(loom), feature = "parking_lot"))]
+use parking_lot::{Condvar, Mutex, MutexGuard};
 use std::any::{Any, TypeId};
 #[cfg(not(loom))]
 use std::cell::UnsafeCell;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::marker::PhantomData;
-use std::mem::transmute;
+use std::mem::{transmute, MaybeUninit};
 use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::pin::Pin;
 #[cfg(not(loom))]
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
+#[cfg(all(not(loom), feature = "parking_lot"))]
+use std::sync::Arc;
+#[cfg(all(not(loom), not(feature = "parking_lot")))]
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant, SystemTime};
 
 /// A cache storage structure.
 /// - G: the number of cache groups
@@ -31,19 +47,90 @@ use std::sync::{Arc, Mutex};
 /// [`Cacheable::store()`] will be called when:
 /// 1. The `Cache` is dropped.
 /// 2. The `CacheLine` holding the dirty `Cacheable` is evicted.
+///
+/// Use [`Cache::builder()`] to configure the cache, e.g. make it read only.
+///
+/// Both G and L must be positive, which is checked at compile time:
+/// ```compile_fail
+/// let cache: rom_cache::Cache<0, 4> = rom_cache::Cache::default();
+/// ```
 #[derive(Default, Debug, Clone)]
 pub struct Cache<const G: usize, const L: usize> {
     inner: Arc<CacheInner<G, L>>,
 }
 
 impl<const G: usize, const L: usize> Cache<G, L> {
+    /// Create a [`CacheBuilder`] to configure the cache.
+    pub fn builder() -> CacheBuilder<G, L> {
+        CacheBuilder::new()
+    }
+
+    /// Create a cache loading and storing the `Cacheable`s with a [`Cacheable::KEY`] by `backend`,
+    /// see [`CacheBuilder::backend()`].
+    pub fn with_backend(backend: impl Backend + 'static) -> Self {
+        Self::builder().backend(backend).build()
+    }
+
+    /// Create a read only cache, see [`CacheBuilder::read_only()`].
+    pub fn read_only() -> Self {
+        Self::builder().read_only(true).build()
+    }
+
+    pub(crate) fn with_config(config: Config) -> Self {
+        let (sweep, watchdog) = (config.sweep, config.watchdog);
+        #[cfg(all(feature = "cgroup", target_os = "linux", not(loom)))]
+        let cgroup_pressure = config.cgroup_pressure;
+        let cache = Self {
+            inner: Arc::new(CacheInner::with_config(config)),
+        };
+        #[cfg(not(loom))]
+        {
+            if let Some(interval) = sweep {
+                cache.background(interval, CacheInner::sweep);
+            }
+            if let Some(threshold) = watchdog {
+                cache.background(threshold / 2, |inner| {
+                    inner.ctx.watchdog.check(&inner.ctx.config);
+                });
+            }
+            #[cfg(all(feature = "cgroup", target_os = "linux"))]
+            if let Some(interval) = cgroup_pressure {
+                cache.background(interval, |inner| {
+                    if let Some(level) = crate::pressure::cgroup() {
+                        // reported to the handler of `Cache::on_store_error()` already
+                        inner.relieve(level).ok();
+                    }
+                });
+            }
+        }
+        #[cfg(loom)]
+        let _ = (sweep, watchdog);
+        #[cfg(all(feature = "numa", target_os = "linux", not(loom)))]
+        cache.inner.bind_numa();
+        cache
+    }
+
+    /// Run `f` in a background thread every `interval`, which stops once the cache is dropped.
+    #[cfg(not(loom))]
+    fn background(&self, interval: Duration, f: fn(&CacheInner<G, L>)) {
+        let inner = Arc::downgrade(&self.inner);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let Some(inner) = inner.upgrade() else {
+                break;
+            };
+            f(&inner);
+        });
+    }
+
     /// Retrieve a Cacheable from the cache. Use Default if `Cacheable::load()` failed.
     /// At most (usize::MAX >> 2) CacheRefs for **each** Cacheable type can be retrieved at the same time,
     /// or the counter will overflow and wrap-around, leading to a wrong state.
     /// - If the cache hit and is readable (i.e not being written), return a `CacheRef`. Use Default if `Cacheable::load()` failed.
     /// - CacheError::Busy: cache miss, the CacheLine chosen to evict is being used.
     /// - CacheError::Locked: cache hit, but the CacheLine for T is being written.
-    pub fn get<T: Cacheable + Default>(&self) -> CacheResult<CacheRef<'_, T>> {
+    /// - CacheError::TypeMismatch: the CacheLine found holds another type, whose truncated type id is the same.
+    pub fn get<T: Cacheable + Send + Sync + Default>(&self) -> CacheResult<CacheRef<'_, T>> {
         self.inner.get::<T>()
     }
 
@@ -52,352 +139,5059 @@ impl<const G: usize, const L: usize> Cache<G, L> {
     /// - If the cache hit and is writable (i.e not being read or written), return a `CacheMut`. Use Default if `Cacheable::load()` failed.
     /// - CacheError::Busy: cache miss, the CacheLine chosen to evict is being used.
     /// - CacheError::Locked: cache hit, but the CacheLine for T is being read or written.
-    pub fn get_mut<T: Cacheable + Default>(&self) -> CacheResult<CacheMut<'_, T>> {
+    /// - CacheError::ReadOnly: the cache is read only.
+    /// - CacheError::TypeMismatch: the CacheLine found holds another type, whose truncated type id is the same.
+    pub fn get_mut<T: Cacheable + Send + Sync + Default>(&self) -> CacheResult<CacheMut<'_, T>> {
         self.inner.get_mut::<T>()
     }
-}
 
-#[derive(Debug)]
-struct CacheInner<const G: usize, const L: usize> {
-    groups: [CacheGroup<L>; G],
-}
+    /// Run `f` with T retrieved by [`Cache::get()`], so that the guard cannot escape or outlive `f`.
+    /// The errors are the same as [`Cache::get()`].
+    pub fn with<T: Cacheable + Send + Sync + Default, R>(
+        &self,
+        f: impl FnOnce(&T) -> R,
+    ) -> CacheResult<R> {
+        self.get::<T>().map(|t| f(&t))
+    }
 
-impl<const G: usize, const L: usize> Default for CacheInner<G, L> {
-    fn default() -> Self {
-        debug_assert!(G > 0, "Invalid number of cache groups {}.", G);
-        debug_assert!(L > 0, "Invalid number of cache lines {}.", L);
-        let groups = (0..G).map(|_| CacheGroup::default()).collect::<Vec<_>>();
-        Self {
-            groups: groups.try_into().unwrap(),
+    /// Run `f` with T retrieved by [`Cache::get_mut()`], so that the guard cannot escape or outlive `f`.
+    /// T is marked dirty as if dereferenced mutably. The errors are the same as [`Cache::get_mut()`].
+    pub fn with_mut<T: Cacheable + Send + Sync + Default, R>(
+        &self,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> CacheResult<R> {
+        self.get_mut::<T>().map(|mut t| f(&mut t))
+    }
+
+    /// Retrieve a Cacheable like [`Cache::get()`], returning a guard which keeps the cache alive
+    /// instead of borrowing it, e.g. to be moved into a spawned thread or task.
+    /// The errors are the same as [`Cache::get()`].
+    pub fn get_owned<T: Cacheable + Send + Sync + Default>(
+        &self,
+    ) -> CacheResult<OwnedCacheRef<T, G, L>> {
+        let cache = self.inner.clone();
+        let cache_ref = cache.get::<T>()?;
+        // SAFETY: the `CacheRef` only borrows the `CacheInner` behind the `Arc`, which never moves
+        // and is kept alive by `cache`, dropped after the `CacheRef`
+        let cache_ref = unsafe { transmute::<CacheRef<'_, T>, CacheRef<'static, T>>(cache_ref) };
+        Ok(OwnedCacheRef {
+            cache_ref: MaybeUninit::new(cache_ref),
+            _cache: cache,
+        })
+    }
+
+    /// Retrieve a copy of T shared by the following calls of this thread until T is loaded or modified,
+    /// so that they touch neither the `CacheGroup` nor the `CacheLine`, see [`CacheBuilder::read_replicas()`].
+    ///
+    /// A replica is checked against an epoch of T bumped by each load and modification, so it is never older
+    /// than the last committed T when returned, but it does not expire by itself: an expired T is replaced once reloaded.
+    /// The errors are the same as [`Cache::get()`], on a replica miss.
+    pub fn get_replica<T: Cacheable + Clone + Send + Sync + Default>(
+        &self,
+    ) -> CacheResult<std::sync::Arc<T>> {
+        let ctx = &self.inner.ctx;
+        let type_id = type_id_usize::<T>();
+        if !ctx.deps.is_stale(type_id) {
+            if let Some(value) = ctx.replicas.lookup::<T>() {
+                return Ok(value);
+            }
         }
+        let cache_ref = self.get::<T>()?;
+        // no modification is committed while read
+        let epoch = ctx.replicas.epoch(type_id);
+        let value = std::sync::Arc::new(T::clone(&cache_ref));
+        drop(cache_ref);
+        let capacity = ctx.config.read_replicas.unwrap_or(0);
+        ctx.replicas.keep::<T>(epoch, value.clone(), capacity);
+        Ok(value)
     }
-}
 
-impl<const G: usize, const L: usize> CacheInner<G, L> {
-    fn get<T: Cacheable + Default>(&self) -> CacheResult<CacheRef<'_, T>> {
-        T::retrieve_from(self)
+    /// Whether T is cached and up to date, without loading it or updating the LRU.
+    pub fn contains<T: Cacheable + Send + Sync>(&self) -> CacheResult<bool> {
+        if self.inner.ctx.deps.is_stale(type_id_usize::<T>()) {
+            return Ok(false);
+        }
+        self.inner.group::<T>().contains::<T>(&self.inner.ctx)
     }
 
-    fn get_mut<T: Cacheable + Default>(&self) -> CacheResult<CacheMut<'_, T>> {
-        T::retrieve_mut_from(self)
+    /// Retrieve a Cacheable from the cache like [`Cache::get()`], but `Cacheable::load()` of T runs
+    /// exactly once during the lifetime of the cache, and all callers observe its result (the default one if failed).
+    ///
+    /// Concurrent first accesses share a single load. Once T is initialized, it never expires or is invalidated,
+    /// and an evicted T is written back if dirty and retained in memory, to be put back when accessed again
+    /// instead of loading it. [`Cache::refresh()`] still loads T explicitly.
+    /// - CacheError::Busy: cache miss, the CacheLine chosen to evict is being used.
+    /// - CacheError::Locked: cache hit, but the CacheLine for T is being written.
+    pub fn get_or_init<T: Cacheable + Send + Sync + Default>(
+        &self,
+    ) -> CacheResult<CacheRef<'_, T>> {
+        self.inner.ctx.once.insert(type_id_usize::<T>());
+        self.inner.get::<T>()
     }
-}
 
-#[derive(Debug)]
-struct CacheGroup<const L: usize> {
-    lines: UnsafeCell<[CacheLine; L]>,
-    flags: UnsafeCell<[Flag; L]>,
-    lock: Mutex<()>,
-}
+    /// Retrieve T computed by the future returned by `f`, e.g. fetched over the network, unless cached.
+    ///
+    /// Concurrent misses share a single computation, the other callers wait for it without blocking the thread,
+    /// and compute again if it failed or was cancelled. No async runtime is required.
+    /// The computed T is inserted marked dirty (unless [`Cache::read_only()`]), to be written back when evicted
+    /// like any other modification, and computed again when missed, `Cacheable::load()` is never called.
+    /// - CacheError::Io: `f` failed.
+    /// - CacheError::Busy: cache miss, the CacheLine chosen to evict is being used.
+    /// - CacheError::Locked: cache hit, but the CacheLine for T is being written.
+    pub async fn memo_async<T, F, Fut>(&self, f: F) -> CacheResult<CacheRef<'_, T>>
+    where
+        T: Cacheable + Send + Sync,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = std::io::Result<T>>,
+    {
+        let inner = &*self.inner;
+        let mut f = Some(f);
+        loop {
+            if let Some(cache_ref) = inner.get_cached::<T>()? {
+                return Ok(cache_ref);
+            }
+            let Some(_flight) = inner.ctx.memos.try_begin::<T>()? else {
+                inner.ctx.memos.landed::<T>().await?;
+                continue;
+            };
+            // computed by another caller in between
+            if let Some(cache_ref) = inner.get_cached::<T>()? {
+                return Ok(cache_ref);
+            }
+            let f = f.take().expect("computed twice");
+            let value = f().await?;
+            return inner.insert(value);
+        }
+    }
 
-/// # Safety
-/// All APIs of `CacheGroup` synchronize with the lock.
-unsafe impl<const L: usize> Sync for CacheGroup<L> {}
+    /// Retrieve a mut Cacheable from the cache, or insert the one returned by `f` if missing,
+    /// without calling `Cacheable::load()`. The inserted one is marked dirty.
+    /// - CacheError::Busy: cache miss, the CacheLine chosen to evict is being used.
+    /// - CacheError::Locked: cache hit, but the CacheLine for T is being read or written.
+    /// - CacheError::ReadOnly: the cache is read only.
+    pub fn get_mut_or_insert_with<T: Cacheable + Send + Sync>(
+        &self,
+        f: impl FnOnce() -> T,
+    ) -> CacheResult<CacheMut<'_, T>> {
+        self.inner.get_mut_or_insert_with(f)
+    }
 
-impl<const L: usize> Default for CacheGroup<L> {
-    fn default() -> Self {
-        let lines = (0..L).map(|_| CacheLine::default()).collect::<Vec<_>>();
-        let flags = (0..L).map(|_| Flag::default()).collect::<Vec<_>>();
-        Self {
-            lines: UnsafeCell::new(lines.try_into().unwrap()),
-            flags: UnsafeCell::new(flags.try_into().unwrap()),
-            lock: Mutex::new(()),
+    /// Register a handler called whenever a background [`Cacheable::store()`] fails,
+    /// with the type name of the `Cacheable` and the error, i.e.
+    /// - writing back during eviction, the error is also returned to the caller of `get` or `get_mut`.
+    /// - writing back proactively (see [`CacheBuilder::max_dirty()`]), the error is never returned to the caller.
+    /// - writing back when the cache is dropped.
+    /// - writing back when a [`FlushGuard`] is dropped.
+    ///
+    /// The previous handler is replaced.
+    pub fn on_store_error(&self, handler: impl Fn(&str, &std::io::Error) + Send + Sync + 'static) {
+        self.inner.ctx.on_store_error.set(Box::new(handler));
+    }
+
+    /// Subscribe to the updates of T, i.e. a `CacheMut<T>` dereferenced mutably is dropped,
+    /// or T is loaded into the cache (including [`Cache::refresh()`] and the background reloads).
+    ///
+    /// The `Receiver` holds at most one pending notification, so that a burst of updates
+    /// is coalesced, read T from the cache again to observe the latest one. Drop it to unsubscribe.
+    pub fn watch<T: Cacheable + Send + Sync>(&self) -> Receiver<()> {
+        self.inner.ctx.watchers.subscribe(type_id_usize::<T>())
+    }
+
+    /// Declare that A is derived from B, e.g. an index built from B by `A::load()`.
+    ///
+    /// Whenever B is modified through a `CacheMut`, refreshed or absorbed, A and the `Cacheable`s derived from A
+    /// are invalidated, and reloaded when accessed next, after their dependencies are written back.
+    /// Writing back all dirty `Cacheable`s (e.g. [`Cache::close()`]) writes B before A,
+    /// but evictions are written back in any order.
+    pub fn depends<A: Cacheable + Send + Sync, B: Cacheable + Send + Sync>(&self) {
+        self.inner
+            .ctx
+            .deps
+            .insert(type_id_usize::<A>(), type_id_usize::<B>());
+    }
+
+    /// Restore T to the value before its last modification, see [`CacheBuilder::history()`].
+    /// Return false if there is nothing to undo.
+    /// - CacheError::Locked: T is being read or written.
+    pub fn undo<T: Cacheable + Send + Sync + Default>(&self) -> CacheResult<bool> {
+        Ok(self.get_mut::<T>()?.swap_history(true))
+    }
+
+    /// Reapply the modification of T undone last by [`Cache::undo()`].
+    /// Return false if there is nothing to redo, e.g. T was modified after undone.
+    /// - CacheError::Locked: T is being read or written.
+    pub fn redo<T: Cacheable + Send + Sync + Default>(&self) -> CacheResult<bool> {
+        Ok(self.get_mut::<T>()?.swap_history(false))
+    }
+
+    /// Report the hits, misses, conflicts, occupancy and reuse distance of each `CacheGroup`
+    /// since the cache created, with the recommended `G` and `L`:
+    /// - With conflicts, `L` is large enough for the group with the most distinct `Cacheable`s.
+    /// - Otherwise, `L` is the most occupied `CacheLine`s of a group.
+    ///
+    /// The recommendation only reflects the workload so far, collect it after a representative run.
+    pub fn tuning_report(&self) -> CacheResult<TuningReport> {
+        let mut groups = vec![];
+        for group in self.inner.groups.iter() {
+            groups.push(group.report(&self.inner.ctx)?);
         }
+        let conflicts = groups.iter().any(|g| g.conflicts > 0);
+        let lines = match conflicts {
+            true => groups.iter().map(|g| g.types.len()).max(),
+            false => groups.iter().map(|g| g.occupied).max(),
+        };
+        let collisions = groups
+            .iter()
+            .enumerate()
+            .filter(|(_, g)| g.conflicts > 0 && g.types.len() > L)
+            .map(|(i, g)| (i, g.types.clone()))
+            .collect();
+        Ok(TuningReport {
+            recommended: (G, lines.unwrap_or(L).max(1)),
+            groups,
+            collisions,
+        })
     }
-}
 
-impl<const L: usize> Drop for CacheGroup<L> {
-    fn drop(&mut self) {
-        let lines = unsafe { &mut *self.lines.get() };
-        let flags = unsafe { &*self.flags.get() };
-        for (i, f) in flags.iter().enumerate() {
-            if f.is_dirty() {
-                lines[i].inner.take().unwrap().store().ok();
-            }
+    /// Subscribe to the thrashing, i.e. two `Cacheable`s evicting each other in turn,
+    /// each within [`CacheBuilder::thrash_window()`] (1 second by default) since evicted by the other.
+    /// Consider [`Cache::migrate()`] or [`Cacheable::GROUP_HINT`] to put them apart.
+    ///
+    /// Drop the `Receiver` to unsubscribe, undrained events are buffered without bound.
+    pub fn thrashing(&self) -> Receiver<ThrashEvent> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.inner
+            .ctx
+            .thrash
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .subscribers
+            .push(tx);
+        rx
+    }
+
+    /// Subscribe to the `CacheRef`s and `CacheMut`s held longer than [`CacheBuilder::watchdog()`],
+    /// each reported once while still held. Nothing is reported unless the watchdog is enabled.
+    ///
+    /// Drop the `Receiver` to unsubscribe, undrained events are buffered without bound.
+    pub fn long_held(&self) -> Receiver<LongHeldEvent> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.inner.ctx.watchdog.lock().subscribers.push(tx);
+        rx
+    }
+
+    /// Subscribe to the evictions, including the ones by [`Cache::purge_clean()`], [`Cache::resize_lines()`],
+    /// [`Cache::migrate()`] and [`CacheBuilder::max_bytes()`]. An expired or invalidated `Cacheable` is reported
+    /// when its `CacheLine` is reused. Each subscriber receives every eviction after it subscribed.
+    ///
+    /// Drop the `Receiver` to unsubscribe, undrained events are buffered without bound.
+    pub fn evictions(&self) -> Receiver<EvictionEvent> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.inner
+            .ctx
+            .evictions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(tx);
+        rx
+    }
+
+    /// Load T from the storage again, and replace the cached one in place.
+    /// Nothing happens if T is not cached.
+    /// - CacheError::Io: `Cacheable::load()` failed, the cached T is kept.
+    /// - CacheError::Locked: the CacheLine for T is being read or written.
+    /// - CacheError::Dirty: the cached T is dirty, and the policy is [`RefreshPolicy::Error`].
+    pub fn refresh<T: Cacheable + Send + Sync>(&self, policy: RefreshPolicy<T>) -> CacheResult<()> {
+        self.inner.refresh::<T>(policy)
+    }
+
+    /// Drop every cached `Cacheable` which is clean and not being used, without any IO,
+    /// to free memory cheaply. Return the number of dropped `Cacheable`s.
+    /// `Cacheable`s initialized by [`Cache::get_or_init()`] are kept.
+    pub fn purge_clean(&self) -> CacheResult<usize> {
+        let mut purged = 0;
+        for group in self.inner.groups.iter() {
+            purged += group.purge_clean(&self.inner.ctx)?;
         }
+        Ok(purged)
     }
-}
 
-impl<const L: usize> CacheGroup<L> {
-    /// load Cacheable into CacheLine and update LRU
-    fn load<T: CacheableExt + Default>(&self) -> CacheResult<usize> {
-        let slot = self.slot::<T>();
-        let lines = unsafe { &mut *self.lines.get() };
-        let flags = unsafe { &*self.flags.get() };
-        match slot {
-            Some(CacheSlot::Hit(i)) => {
-                let lru = lines[i].lru;
-                lines
-                    .iter_mut()
-                    .filter(|l| l.lru < lru)
-                    .for_each(|l| l.lru += 1);
-                lines[i].lru = 0;
-                Ok(i)
-            }
-            Some(CacheSlot::Empty(i)) => {
-                lines.iter_mut().for_each(|l| l.lru += 1);
-                lines[i].lru = 0;
-                lines[i].inner = Some(Box::new(T::load_or_default()));
-                lines[i].type_id = T::type_id_usize();
-                Ok(i)
-            }
-            Some(CacheSlot::Evict(i)) => {
-                lines.iter_mut().for_each(|l| l.lru += 1);
-                lines[i].lru = 0;
-                if !flags[i].in_using() {
-                    if flags[i].is_dirty() {
-                        lines[i].inner.take().unwrap().store()?;
-                        flags[i].set_clean();
-                    }
-                    lines[i].inner = Some(Box::new(T::load_or_default()));
-                    lines[i].type_id = T::type_id_usize();
-                    Ok(i)
-                } else {
-                    Err(CacheError::Busy)
-                }
-            }
-            None => unreachable!(),
+    /// Give memory back by dropping cached `Cacheable`s not being used, more of them the more severe `level` is,
+    /// see [`MemoryPressure`]. Return the number of dropped `Cacheable`s, or every failure with the type name
+    /// of the `Cacheable`, which stays cached if failed to write back.
+    /// `Cacheable`s initialized by [`Cache::get_or_init()`] are kept.
+    pub fn on_memory_pressure(
+        &self,
+        level: MemoryPressure,
+    ) -> Result<usize, Vec<(String, std::io::Error)>> {
+        self.inner.relieve(level)
+    }
+
+    /// Write back and drop every cached `Cacheable` not being used for which `f` returns false,
+    /// e.g. the ones of a previous document. Return the number of dropped `Cacheable`s, or every failure
+    /// with the type name of the `Cacheable`, which stays cached if failed to write back.
+    /// `Cacheable`s initialized by [`Cache::get_or_init()`] are kept.
+    pub fn retain(
+        &self,
+        mut f: impl FnMut(&EntryInfo) -> bool,
+    ) -> Result<usize, Vec<(String, std::io::Error)>> {
+        let mut errors = vec![];
+        let dropped = (self.inner.groups.iter().enumerate())
+            .map(|(g, group)| group.retain(g, &self.inner.ctx, &mut f, &mut errors))
+            .sum();
+        match errors.is_empty() {
+            true => Ok(dropped),
+            false => Err(errors),
         }
     }
 
-    fn slot<T: CacheableExt>(&self) -> Option<CacheSlot> {
-        let type_id = T::type_id_usize();
-        let mut slot = None;
-        let lines = unsafe { &*self.lines.get() };
-        for (i, line) in lines.iter().enumerate() {
-            if line.type_id == type_id {
-                return Some(CacheSlot::Hit(i));
-            } else if line.type_id == 0 {
-                return Some(CacheSlot::Empty(i));
-            } else if line.lru == L - 1 {
-                slot = Some(CacheSlot::Evict(i));
-            }
+    /// The total weight of the cached `Cacheable`s, see [`CacheBuilder::max_bytes()`].
+    pub fn bytes(&self) -> usize {
+        self.inner.ctx.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Use only the first `lines` (clamped to `1..=L`) `CacheLine`s of each `CacheGroup`,
+    /// so that the cache can shrink under memory pressure and grow back to `L` afterwards.
+    /// The `Cacheable`s in the `CacheLine`s no longer used are written back if dirty and dropped,
+    /// every failure is returned with the type name of the `Cacheable`.
+    ///
+    /// A `Cacheable` still being used is kept, and reported as `std::io::ErrorKind::WouldBlock`.
+    /// It is still served until evicted by another call to `resize_lines`.
+    pub fn resize_lines(&self, lines: usize) -> Result<(), Vec<(String, std::io::Error)>> {
+        let errors = self.inner.resize_lines(lines);
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
         }
-        slot
     }
 
-    /// Retrieve a Cacheable from the cache.
-    /// At most 63 CacheRefs for each Cacheable type can be retrieved at the same time
-    fn retrieve<T: CacheableExt + Default>(&self) -> CacheResult<CacheRef<'_, T>> {
-        let _lock = self.lock.lock().map_err(|_| CacheError::Poisoned)?;
-        let i = self.load::<T>()?;
-        let lines = unsafe { &*self.lines.get() };
-        let flags = unsafe { &*self.flags.get() };
-        flags[i].read()?;
-        let inner = lines[i].inner.as_deref().unwrap();
-        let flag = &flags[i];
-        Ok(CacheRef {
+    /// Invalidate every `CacheLine` logically, so that the following accesses load from the storage again.
+    /// Dirty `CacheLine`s are written back first, every failure is returned with the type name of the `Cacheable`.
+    ///
+    /// A stale `CacheLine` still being used is reloaded once released,
+    /// accessing it before that returns `CacheError::Busy`.
+    /// A stale `CacheLine` being written is written back when reloaded.
+    pub fn bump_generation(&self) -> Result<(), Vec<(String, std::io::Error)>> {
+        let errors = self.inner.flush_all();
+        self.inner.ctx.generation.fetch_add(1, Ordering::Relaxed);
+        self.inner.ctx.replicas.bump_all();
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+
+    /// Dedicate a `CacheLine` of the `CacheGroup` of T to T permanently, T is loaded into it lazily when accessed.
+    /// Other `Cacheable`s never take it, so T never misses because of them. Reserving twice is a no-op.
+    ///
+    /// The `CacheLine` holding T is taken if T is cached, otherwise an empty or the least recently used one,
+    /// written back if dirty.
+    /// - CacheError::Busy: the `CacheLine` to take is being used.
+    /// - CacheError::Collision: reserving T would leave no `CacheLine` in use of the group to the others.
+    pub fn reserve<T: Cacheable + Send + Sync>(&self) -> CacheResult<()> {
+        self.inner.group::<T>().reserve::<T>(&self.inner.ctx)
+    }
+
+    /// Move T to the `CacheGroup` `group` instead of `type_id % G`, e.g. away from a thrashing group.
+    /// T is written back if dirty and evicted from its current group, and loaded into the new one when accessed.
+    /// - CacheError::Busy: T is being used.
+    ///
+    /// # Panics
+    /// If `group` is out of range.
+    pub fn migrate<T: Cacheable + Send + Sync>(&self, group: usize) -> CacheResult<()> {
+        assert!(group < G, "Invalid cache group {}.", group);
+        self.inner.migrate::<T>(group)
+    }
+
+    /// Move the least recently used `Cacheable`, which is not being used, out of the group
+    /// with the most evictions since last call to the group with the most empty `CacheLine`s (see [`Cache::migrate()`]).
+    /// Return the type name of the moved `Cacheable`, None if no group is thrashing or has room.
+    ///
+    /// Call it periodically, or when evictions hurt.
+    pub fn rebalance(&self) -> CacheResult<Option<&'static str>> {
+        self.inner.rebalance()
+    }
+
+    /// Visit every cached `Cacheable` with its [`EntryInfo`], holding the lock of each `CacheGroup` in turn.
+    /// `CacheLine`s being written are skipped, and so are those invalidated by [`Cache::bump_generation()`].
+    ///
+    /// Do not access the cache in `f`, which may deadlock.
+    pub fn for_each(&self, mut f: impl FnMut(EntryInfo, &dyn Any)) -> CacheResult<()> {
+        for (g, group) in self.inner.groups.iter().enumerate() {
+            group.for_each(g, &self.inner.ctx, &mut f)?;
+        }
+        Ok(())
+    }
+
+    /// Visit every occupied `CacheLine` of the `CacheGroup` `g` with its [`EntryInfo`] and [`LineFlags`],
+    /// holding the lock of the group. Unlike [`Cache::for_each()`], the ones being written or invalidated
+    /// are visited too, without the value for the former.
+    #[cfg(feature = "serde_json")]
+    pub(crate) fn inspect(
+        &self,
+        g: usize,
+        mut f: impl FnMut(EntryInfo, LineFlags, Option<&DynValue>),
+    ) -> CacheResult<()> {
+        self.inner.groups[g].inspect(g, &self.inner.ctx, &mut f)
+    }
+
+    /// Register T as a participating `Cacheable`, so that it is covered by the bulk operations,
+    /// e.g. [`Cache::warm_all()`] and [`Cache::flush_all_registered()`]. Registering twice is a no-op.
+    /// - CacheError::Collision: more registered `Cacheable`s are assigned to the `CacheGroup` of T
+    ///   than it can hold, so that they would keep evicting each other. T is not registered.
+    ///   In fully associative mode (see [`CacheBuilder::fully_associative()`]), more than `G * L` in total.
+    pub fn register<T: Cacheable + Send + Sync + Default>(&self) -> CacheResult<()> {
+        self.inner.register::<T>()
+    }
+
+    /// The type names of the registered `Cacheable`s, in the order of registration.
+    pub fn registered(&self) -> Vec<&'static str> {
+        self.inner
+            .registry
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|r| r.type_name)
+            .collect()
+    }
+
+    /// Load every registered `Cacheable` into the cache eagerly, stopping at the first error.
+    pub fn warm_all(&self) -> CacheResult<()> {
+        let registry = self
+            .inner
+            .registry
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        for registered in registry.iter() {
+            (registered.warm)(&self.inner, registered)?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve a registered (see [`Cache::register()`]) `Cacheable` by its `TypeId`, like [`Cache::get()`],
+    /// for the callers knowing the type only at runtime. Downcast the `&dyn Any` to access it.
+    /// - CacheError::Unregistered: no `Cacheable` of `type_id` is registered.
+    pub fn get_dyn(&self, type_id: TypeId) -> CacheResult<DynCacheRef<'_>> {
+        let registered = self.inner.registered(type_id)?;
+        (registered.get_dyn)(&self.inner, &registered)
+    }
+
+    /// Register the trait object slot K holding a `Box<D>`, e.g. `Box<dyn Asset>`, with the `load` and `store` functions,
+    /// which is retrieved by [`Cache::get_object()`] and accessed through the trait without downcasting.
+    /// K is a marker type naming the slot, so that several slots of the same trait can be cached.
+    /// See [`Cache::register_dyn()`] for the rest.
+    ///
+    /// ```ignore
+    /// struct Background;
+    /// cache.register_object::<Background, dyn Asset>(|| Ok(Box::new(Png::open("bg.png")?)), |asset| asset.save())?;
+    /// cache.get_object::<Background, dyn Asset>()?.draw();
+    /// ```
+    pub fn register_object<K: 'static, D: ?Sized + Send + Sync + 'static>(
+        &self,
+        load: impl Fn() -> std::io::Result<Box<D>> + Send + Sync + 'static,
+        store: impl Fn(&D) -> std::io::Result<()> + Send + Sync + 'static,
+    ) -> CacheResult<()> {
+        self.register_dyn(
+            TypeId::of::<Slot<K, D>>(),
+            std::any::type_name::<K>(),
+            move || Ok(Box::new(Slot::<K, D>(load()?, PhantomData))),
+            move |slot| match slot.downcast_ref::<Slot<K, D>>() {
+                Some(slot) => store(&slot.0),
+                None => Err(std::io::Error::other("not the registered slot")),
+            },
+        )
+    }
+
+    /// Retrieve the trait object in slot K, registered by [`Cache::register_object()`], like [`Cache::get()`].
+    /// - CacheError::Unregistered: slot K of `Box<D>` is not registered.
+    pub fn get_object<K: 'static, D: ?Sized + Send + Sync + 'static>(
+        &self,
+    ) -> CacheResult<ObjectRef<'_, K, D>> {
+        let inner = self.get_dyn(TypeId::of::<Slot<K, D>>())?;
+        slot_of::<K, D>(&*inner)?;
+        Ok(ObjectRef {
             inner,
-            flag,
             _phantom: PhantomData,
         })
     }
 
-    /// Retrieve a mut Cacheable from the cache.
-    fn retrieve_mut<T: CacheableExt + Default>(&self) -> CacheResult<CacheMut<'_, T>> {
-        let _lock = self.lock.lock().map_err(|_| CacheError::Poisoned)?;
-        let i = self.load::<T>()?;
-        let lines = unsafe { &mut *self.lines.get() };
-        let flags = unsafe { &*self.flags.get() };
-        flags[i].write()?;
-        let inner = lines[i].inner.as_deref_mut().unwrap();
-        let flag = &flags[i];
-        Ok(CacheMut {
+    /// Retrieve the trait object in slot K mutably, registered by [`Cache::register_object()`], like [`Cache::get_mut()`].
+    /// - CacheError::Unregistered: slot K of `Box<D>` is not registered.
+    pub fn get_mut_object<K: 'static, D: ?Sized + Send + Sync + 'static>(
+        &self,
+    ) -> CacheResult<ObjectMut<'_, K, D>> {
+        let inner = self.get_mut_dyn(TypeId::of::<Slot<K, D>>())?;
+        slot_of::<K, D>(&*inner)?;
+        Ok(ObjectMut {
             inner,
-            flag,
             _phantom: PhantomData,
         })
     }
-}
 
-#[derive(Debug)]
-enum CacheSlot {
-    Hit(usize),
-    Empty(usize),
-    Evict(usize),
-}
+    /// Retrieve a registered `Cacheable` mutably by its `TypeId`, like [`Cache::get_mut()`].
+    /// Dereferencing it mutably marks the `Cacheable` dirty.
+    /// - CacheError::Unregistered: no `Cacheable` of `type_id` is registered.
+    pub fn get_mut_dyn(&self, type_id: TypeId) -> CacheResult<DynCacheMut<'_>> {
+        let registered = self.inner.registered(type_id)?;
+        (registered.get_mut_dyn)(&self.inner, &registered)
+    }
 
-#[derive(Default)]
-struct CacheLine {
-    lru: usize,
-    type_id: usize,
-    inner: Option<Box<dyn Cacheable>>,
-}
+    /// Register a `Cacheable` at runtime, which has no [`Cacheable`] impl but the `load` and `store` functions,
+    /// e.g. provided by a plugin. It can be retrieved by [`Cache::get_dyn()`] only, and participates in
+    /// the bulk operations as [`Cache::register()`] does. Registering twice is a no-op.
+    ///
+    /// `load` must return a value of `type_id`, which is passed to `store`. A failed `load`
+    /// is returned as `CacheError::Io`, since there is no default value.
+    /// - CacheError::Collision: see [`Cache::register()`].
+    pub fn register_dyn(
+        &self,
+        type_id: TypeId,
+        type_name: &'static str,
+        load: impl Fn() -> std::io::Result<Box<dyn Any + Send + Sync>> + Send + Sync + 'static,
+        store: impl Fn(&dyn Any) -> std::io::Result<()> + Send + Sync + 'static,
+    ) -> CacheResult<()> {
+        let loader = Loader {
+            type_id,
+            load: std::sync::Arc::new(load),
+            store: std::sync::Arc::new(store),
+        };
+        self.inner.register_dyn(type_name, loader)
+    }
 
-impl std::fmt::Debug for CacheLine {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("CacheLine")
-            .field("lru", &self.lru)
-            .field("type_id", &self.type_id)
-            .finish()
+    /// Save the type names of the cached registered `Cacheable`s to `path`, one per line,
+    /// so that the next process can warm its cache up by [`Cache::warm_from()`].
+    /// Only which `Cacheable`s are cached is saved, not their values.
+    ///
+    /// Each line is `type_name\tlru\tdirty`, from the least recently used to the most,
+    /// so that warming up in order restores the recency. The dirty flags are informative only,
+    /// since the dirty `Cacheable`s are written back before the process exits.
+    pub fn save_warm_set(&self, path: impl AsRef<std::path::Path>) -> CacheResult<()> {
+        let registered = self.registered();
+        let mut cached = vec![];
+        self.for_each(|info, _| {
+            if registered.contains(&info.type_name) {
+                cached.push((info.lru, info.type_name, info.dirty));
+            }
+        })?;
+        cached.sort_by_key(|&(lru, ..)| Reverse(lru));
+        let mut content = String::new();
+        for (lru, type_name, dirty) in cached {
+            content.push_str(&format!("{type_name}\t{lru}\t{dirty}\n"));
+        }
+        std::fs::write(path, content)?;
+        Ok(())
     }
-}
 
-#[derive(Debug, Default)]
-struct Flag {
-    // 000...00
-    //        ^ write
-    //  ^^^^^^ read count
-    // ^ dirty
-    inner: AtomicUsize,
-}
+    /// Load the registered `Cacheable`s listed in the file saved by [`Cache::save_warm_set()`] in order,
+    /// before serving the first request. Only registered `Cacheable`s opt in,
+    /// unknown type names are ignored. Stop at the first error.
+    pub fn warm_from(&self, path: impl AsRef<std::path::Path>) -> CacheResult<()> {
+        let content = std::fs::read_to_string(path)?;
+        let registry = self
+            .inner
+            .registry
+            .read()
+            .unwrap_or_else(|e| e.into_inner());
+        for line in content.lines() {
+            let type_name = line.split('\t').next().unwrap_or_default();
+            if let Some(registered) = registry.iter().find(|r| r.type_name == type_name) {
+                (registered.warm)(&self.inner, registered)?;
+            }
+        }
+        Ok(())
+    }
 
-impl Flag {
-    fn write(&self) -> CacheResult<()> {
-        if self.inner.load(Ordering::Relaxed) == 0 {
-            self.inner.store(1, Ordering::Relaxed);
-            Ok(())
-        } else {
-            Err(CacheError::Locked)
+    /// Write back the dirty `CacheLine`s holding registered `Cacheable`s,
+    /// reporting failures as [`Cache::close()`] does.
+    pub fn flush_all_registered(&self) -> Result<(), Vec<(String, std::io::Error)>> {
+        let type_ids = self
+            .inner
+            .registry
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|r| r.type_id)
+            .collect::<HashSet<_>>();
+        let mut errors = vec![];
+        for group in self.inner.groups.iter() {
+            group.flush_where(&self.inner.ctx, &mut errors, |type_id| {
+                type_ids.contains(&type_id)
+            });
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
         }
     }
 
-    fn read(&self) -> CacheResult<()> {
-        if self.inner.load(Ordering::Relaxed) & 1 != 1 {
-            self.inner.fetch_add(2, Ordering::Relaxed);
-            Ok(())
-        } else {
-            Err(CacheError::Locked)
+    /// Fork an independent cache with the same configuration, unlike `Clone` which shares the cache.
+    /// The cached `Cacheable`s are deep-copied by [`Cacheable::snapshot()`], keeping their LRU and dirtiness,
+    /// and those which cannot be copied (or are being written) are left out, to be loaded again when accessed.
+    /// Registered types (see [`Cache::register()`]) and migrated types (see [`Cache::migrate()`]) are kept as well.
+    ///
+    /// Nothing is shared afterwards, but the fork still loads from and writes back to the same storage,
+    /// i.e. its dirty `Cacheable`s are written back when evicted or dropped, like any cache.
+    pub fn fork(&self) -> CacheResult<Self> {
+        let ctx = &self.inner.ctx;
+        let fork = CacheInner::<G, L>::with_config(ctx.config.clone());
+        for (group, forked) in self.inner.groups.iter().zip(fork.groups.iter()) {
+            group.fork_into(ctx, forked, &fork.ctx)?;
         }
+        fork.ctx
+            .active_lines
+            .store(ctx.active_lines.load(Ordering::Relaxed), Ordering::Relaxed);
+        *fork.ctx.remap.write().unwrap_or_else(|e| e.into_inner()) =
+            ctx.remap.read().unwrap_or_else(|e| e.into_inner()).clone();
+        *fork.registry.write().unwrap_or_else(|e| e.into_inner()) = self
+            .inner
+            .registry
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        Ok(Self {
+            inner: Arc::new(fork),
+        })
     }
 
-    fn end_write(&self) {
-        self.inner.fetch_and(usize::MAX - 1, Ordering::Relaxed);
+    /// Move the cached `Cacheable`s of `other` into this cache, resolving the types cached by both with `policy`.
+    /// `other` may be of another shape, e.g. the cache of a [`ScopedCache`](crate::ScopedCache).
+    /// A moved `Cacheable` keeps its dirtiness. The replaced one is written back first if dirty,
+    /// and the discarded one of `other` is dropped without writing back.
+    /// A `Cacheable` of `other` evicts the least recently used one here if its `CacheGroup` is full.
+    ///
+    /// Each `Cacheable` is moved under the locks of both `CacheGroup`s, so that it is never observed missing or duplicated,
+    /// but other threads may observe the absorption half done. Do not absorb two caches into each other concurrently,
+    /// which may deadlock. Every failure is returned with the type name, and the `Cacheable` is left in `other`:
+    /// - `std::io::ErrorKind::WouldBlock`: it, or the one to replace, is being used,
+    ///   or every `CacheLine` it may take is reserved for others.
+    /// - other errors: writing back the evicted or replaced one failed.
+    ///
+    /// What is left in `other` is written back when `other` is dropped, if this handle is the last one.
+    pub fn absorb<const OG: usize, const OL: usize>(
+        &self,
+        other: Cache<OG, OL>,
+        mut policy: ConflictPolicy,
+    ) -> Result<(), Vec<(String, std::io::Error)>> {
+        let mut errors = vec![];
+        if std::ptr::addr_eq(Arc::as_ptr(&self.inner), Arc::as_ptr(&other.inner)) {
+            return Ok(());
+        }
+        for group in other.inner.groups.iter() {
+            group.absorb_into(&other.inner.ctx, &self.inner, &mut policy, &mut errors);
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
     }
 
-    fn end_read(&self) {
-        self.inner.fetch_sub(2, Ordering::Relaxed);
+    /// Write back all dirty `CacheLine`s when the returned guard is dropped, e.g. at the end of a request,
+    /// so that everything modified in the scope is persisted.
+    /// Failures are reported to the handler registered by [`Cache::on_store_error()`],
+    /// including the dirty `CacheLine`s still being written, as `std::io::ErrorKind::WouldBlock`.
+    pub fn flush_on_drop(&self) -> FlushGuard<'_, G, L> {
+        FlushGuard { cache: self }
     }
 
-    fn is_dirty(&self) -> bool {
-        self.inner.load(Ordering::Relaxed) & !(usize::MAX >> 1) != 0
+    /// Write back all dirty `CacheLine`s and close this handle of the cache.
+    /// Every failure is returned with the type name of the `Cacheable`.
+    ///
+    /// Dirty `CacheLine`s still being written through a `CacheMut` (held by a clone of this cache)
+    /// are not written back, and reported as `std::io::ErrorKind::WouldBlock`.
+    /// The cache is still alive if other clones exist, and written back again when finally dropped.
+    pub fn close(self) -> Result<(), Vec<(String, std::io::Error)>> {
+        let errors = self.inner.flush_all();
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
     }
 
-    fn set_dirty(&self) {
-        self.inner.fetch_or(!(usize::MAX >> 1), Ordering::Relaxed);
+    /// The index of the `CacheGroup` T is retrieved from by [`Cache::get()`], placing T first if needed,
+    /// see [`Cache::migrate()`] and [`Cacheable::GROUP_HINT`].
+    pub(crate) fn home_of<T: Cacheable + Send + Sync>(&self) -> usize {
+        self.inner.group::<T>().index
     }
 
-    fn set_clean(&self) {
-        self.inner.fetch_and(usize::MAX >> 1, Ordering::Relaxed);
+    /// The `CacheLine`s held by each namespace, see [`Cache::scope()`].
+    pub(crate) fn scopes(&self) -> &crate::namespace::Usages {
+        &self.inner.ctx.scopes
     }
 
-    fn in_using(&self) -> bool {
-        self.inner.load(Ordering::Relaxed) & (usize::MAX >> 1) != 0
+    /// Report `errors` to the handler registered by [`Cache::on_store_error()`], if any.
+    pub(crate) fn report(&self, errors: Vec<(String, std::io::Error)>) {
+        if let Some(handler) = self.inner.ctx.on_store_error.get().as_deref() {
+            for (type_name, e) in errors {
+                handler(&type_name, &e);
+            }
+        }
+    }
+
+    /// The fault injection layer of this cache, affecting all `load` and `store` calls made by it.
+    #[cfg(feature = "test-util")]
+    pub fn faults(&self) -> &crate::test_util::Faults {
+        &self.inner.ctx.faults
     }
 }
 
-/// An immutable ref wrapper to a cacheable object.
-///
-/// `Cache::get_mut::<T>()` will return `CacheError::Locked` before this ref dropped.
-pub struct CacheRef<'a, T>
-where
-    T: Any,
-{
-    inner: &'a dyn Cacheable,
-    flag: &'a Flag,
-    _phantom: PhantomData<&'a T>,
+#[derive(Debug)]
+struct CacheInner<const G: usize, const L: usize> {
+    groups: [CacheGroup<L>; G],
+    ctx: Context,
+    /// See [`Cache::register()`].
+    registry: std::sync::RwLock<Vec<Registered<G, L>>>,
 }
 
-impl<T: Any> Deref for CacheRef<'_, T> {
-    type Target = T;
+/// A registered `Cacheable`, see [`Cache::register()`].
+#[derive(Debug, Clone)]
+struct Registered<const G: usize, const L: usize> {
+    type_id: usize,
+    type_name: &'static str,
+    /// Load the `Cacheable` into the cache.
+    warm: fn(&CacheInner<G, L>, &Registered<G, L>) -> CacheResult<()>,
+    /// Retrieve the `Cacheable` type-erased, see [`Cache::get_dyn()`].
+    get_dyn: for<'a> fn(&'a CacheInner<G, L>, &Registered<G, L>) -> CacheResult<DynCacheRef<'a>>,
+    /// Retrieve the `Cacheable` mutably type-erased, see [`Cache::get_mut_dyn()`].
+    get_mut_dyn:
+        for<'a> fn(&'a CacheInner<G, L>, &Registered<G, L>) -> CacheResult<DynCacheMut<'a>>,
+    /// Registered at runtime, see [`Cache::register_dyn()`].
+    loader: Option<Loader>,
+}
 
-    fn deref(&self) -> &Self::Target {
-        #[cfg(feature = "nightly")]
-        let dyn_any: &dyn Any = self.inner;
-        #[cfg(not(feature = "nightly"))]
-        let dyn_any = self.inner.as_any();
-        dyn_any.downcast_ref::<T>().expect("downcast failed")
-    }
+/// How a `Cacheable` registered at runtime is loaded and stored, see [`Cache::register_dyn()`].
+#[derive(Clone)]
+struct Loader {
+    type_id: TypeId,
+    load: std::sync::Arc<DynLoadFn>,
+    store: std::sync::Arc<DynStoreFn>,
 }
 
-impl<T: Any> Drop for CacheRef<'_, T> {
-    fn drop(&mut self) {
-        self.flag.end_read();
+impl std::fmt::Debug for Loader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Loader")
+            .field("type_id", &self.type_id)
+            .finish()
     }
 }
 
-/// A mutable ref wrapper to a cacheable object.
-///
-/// `Cache::get::<T>()` and `Cache::get_mut::<T>()`
-/// will return `CacheError::Locked` before this mut ref dropped.
-pub struct CacheMut<'a, T>
-where
-    T: Any,
-{
-    inner: &'a mut dyn Cacheable,
-    flag: &'a Flag,
-    _phantom: PhantomData<&'a T>,
-}
+/// Load a `Cacheable` registered at runtime, see [`Cache::register_dyn()`].
+pub type DynLoadFn = dyn Fn() -> std::io::Result<Box<dyn Any + Send + Sync>> + Send + Sync;
 
-impl<T: Any> Deref for CacheMut<'_, T> {
-    type Target = T;
+/// Store a `Cacheable` registered at runtime, see [`Cache::register_dyn()`].
+pub type DynStoreFn = dyn Fn(&dyn Any) -> std::io::Result<()> + Send + Sync;
 
-    fn deref(&self) -> &Self::Target {
-        #[cfg(feature = "nightly")]
-        let dyn_any: &dyn Any = self.inner;
-        #[cfg(not(feature = "nightly"))]
-        let dyn_any = self.inner.as_any();
-        dyn_any.downcast_ref::<T>().expect("downcast failed")
-    }
+/// The state shared by all `CacheGroup`s of a `Cache`.
+#[derive(Debug, Default)]
+struct Context {
+    config: Config,
+    /// The number of dirty `CacheLine`s.
+    dirty: AtomicUsize,
+    /// The total weight of the cached `Cacheable`s, see [`CacheBuilder::max_bytes()`].
+    bytes: AtomicUsize,
+    /// `CacheLine`s loaded in previous generations are stale.
+    generation: AtomicUsize,
+    flights: Flights,
+    /// The `Cacheable`s being computed by [`Cache::memo_async()`].
+    memos: Flights,
+    /// The number of `CacheGroup`s.
+    groups: usize,
+    /// The number of `CacheLine`s in use of each `CacheGroup`, see [`Cache::resize_lines()`].
+    active_lines: AtomicUsize,
+    /// The `CacheGroup`s of the migrated `Cacheable`s, see [`Cache::migrate()`].
+    remap: std::sync::RwLock<HashMap<usize, usize>>,
+    doorkeeper: Option<std::sync::Mutex<Doorkeeper>>,
+    once: OnceTypes,
+    /// When the loading of each `Cacheable` failed last time, see [`CacheBuilder::negative_ttl()`].
+    failures: std::sync::Mutex<HashMap<usize, Instant>>,
+    limiter: Limiter,
+    breakers: Breakers,
+    /// The loads running at once, see [`CacheBuilder::max_loads()`].
+    loads: std::sync::Arc<Loads>,
+    #[cfg(feature = "test-util")]
+    faults: crate::test_util::Faults,
+    on_store_error: std::sync::Arc<Hook<StoreErrorHandler>>,
+    watchers: Watchers,
+    replicas: Replicas,
+    /// The version of each `Cacheable` read before its last load, see [`Cacheable::version()`].
+    versions: std::sync::Mutex<HashMap<usize, u64>>,
+    /// The `CacheLine`s held by each namespace, see [`CacheBuilder::scope_quota()`].
+    scopes: crate::namespace::Usages,
+    write_buffer: Option<std::sync::Arc<WriteBuffer>>,
+    /// The subscribers of evictions, see [`Cache::evictions()`].
+    evictions: std::sync::Mutex<Vec<Sender<EvictionEvent>>>,
+    thrash: std::sync::Mutex<ThrashDetector>,
+    history: History,
+    watchdog: Watchdog,
+    deps: Deps,
 }
 
-impl<T: Any> DerefMut for CacheMut<'_, T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        self.flag.set_dirty();
-        #[cfg(feature = "nightly")]
-        let dyn_any: &mut dyn Any = self.inner;
-        #[cfg(not(feature = "nightly"))]
-        let dyn_any = self.inner.as_any_mut();
-        dyn_any.downcast_mut::<T>().expect("downcast failed")
+type StoreErrorHandler = dyn Fn(&str, &std::io::Error) + Send + Sync;
+
+impl Context {
+    /// Load T, or use the default value if failed.
+    fn load<T: CacheableExt + Default>(&self) -> T {
+        self.load_or_timeout().unwrap_or_default()
     }
-}
 
-impl<T: Any> Drop for CacheMut<'_, T> {
-    fn drop(&mut self) {
-        self.flag.end_write();
+    /// Load T, or use the default value if failed, unless timed out, see [`CacheBuilder::load_timeout()`].
+    fn load_or_timeout<T: CacheableExt + Default>(&self) -> CacheResult<T> {
+        if let Some(mut value) = self.once.take(T::type_id_usize()) {
+            return downcast_mut::<T>(value.as_mut()).map(std::mem::take);
+        }
+        match self.try_load() {
+            Ok(value) => Ok(value),
+            Err(e) if e.get_ref().is_some_and(|e| e.is::<LoadTimeout>()) => {
+                Err(CacheError::Timeout {
+                    type_name: std::any::type_name::<T>(),
+                })
+            }
+            Err(e) => {
+                warn!(
+                    "failed to load {}, using the default: {e}",
+                    std::any::type_name::<T>()
+                );
+                Ok(T::default())
+            }
+        }
     }
-}
 
-/// A type that can be cached.
-pub trait Cacheable: Any + Send + Sync {
-    /// Load Cacheable from the storage
-    fn load() -> std::io::Result<Self>
-    where
-        Self: Sized;
-    /// Write Cacheable back to storage.
-    fn store(&self) -> std::io::Result<()>;
+    /// Load T on a cache miss, or fail with `CacheError::RateLimited` if loading too often
+    /// (see [`CacheBuilder::load_rate()`]), or `CacheError::CircuitOpen` if loading failed repeatedly
+    /// (see [`CacheBuilder::circuit_breaker()`]). `Cache::get_or_init()` types are not limited.
+    fn load_on_miss<T: CacheableExt + Default>(&self) -> CacheResult<T> {
+        let type_id = T::type_id_usize();
+        if let Some((_, cooldown)) = self.config.circuit_breaker {
+            if self.breakers.is_open(type_id, cooldown) {
+                return Err(CacheError::CircuitOpen {
+                    type_name: std::any::type_name::<T>(),
+                });
+            }
+        }
+        if !self.once.contains(type_id)
+            && !self
+                .limiter
+                .permit(type_id, T::LOAD_RATE, self.config.load_rate)
+        {
+            return Err(CacheError::RateLimited {
+                type_name: std::any::type_name::<T>(),
+            });
+        }
+        self.load_or_timeout()
+    }
 
-    /// As Any. This is needed since `Cacheable` will be used as `&dyn Cacheable`,
-    /// and cannot upcast to `&dyn Any` in stable Rust. Just coding as following is Ok.
-    /// ```ignore
-    /// fn as_any(&self) -> &dyn Any {
-    ///     self
-    /// }
-    /// ```
-    /// Or you can simply enable `nightly` future, this needs nightly Rust.
+    /// Load T.
+    fn try_load<T: CacheableExt>(&self) -> std::io::Result<T> {
+        let Some(negative_ttl) = self.config.negative_ttl else {
+            return self.load_unchecked();
+        };
+        let type_id = T::type_id_usize();
+        let failures = || self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(failed_at) = failures().get(&type_id) {
+            if failed_at.elapsed() < negative_ttl {
+                return Err(std::io::Error::other("loading failed recently"));
+            }
+        }
+        let res = self.load_unchecked();
+        match res {
+            Ok(_) => failures().remove(&type_id),
+            Err(_) => failures().insert(type_id, Instant::now()),
+        };
+        res
+    }
+
+    fn load_unchecked<T: CacheableExt>(&self) -> std::io::Result<T> {
+        self.settle(T::type_id_usize())?;
+        #[cfg(feature = "test-util")]
+        self.faults.before_load(TypeId::of::<T>())?;
+        if let Some(version) = T::version() {
+            let mut versions = self.versions.lock().unwrap_or_else(|e| e.into_inner());
+            versions.insert(T::type_id_usize(), version);
+        }
+        let permit = self
+            .loads
+            .permit(self.config.max_loads, self.config.load_timeout)?;
+        let res = match self.config.load_timeout {
+            Some(timeout) => self.load_within(timeout, permit),
+            None => crate::backend::load(self.config.backend.as_ref()),
+        };
+        if let Some((failures, _)) = self.config.circuit_breaker {
+            self.breakers
+                .record(T::type_id_usize(), res.is_ok(), failures);
+        }
+        res
+    }
+
+    /// Load T on a helper thread holding `permit`, abandoned after `timeout`, see [`CacheBuilder::load_timeout()`].
+    fn load_within<T: CacheableExt>(
+        &self,
+        timeout: Duration,
+        permit: Option<Permit>,
+    ) -> std::io::Result<T> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let backend = self.config.backend.clone();
+        let namespace = crate::namespace::current();
+        std::thread::spawn(move || {
+            crate::namespace::within(namespace, || {
+                tx.send(crate::backend::load::<T>(backend.as_ref())).ok();
+            });
+            drop(permit);
+        });
+        match rx.recv_timeout(timeout) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) => Err(LoadTimeout.into()),
+            Err(RecvTimeoutError::Disconnected) => Err(std::io::Error::other("load panicked")),
+        }
+    }
+
+    /// Write the Cacheable back to storage, only what changed `since` the baseline if any,
+    /// see [`Cacheable::store_delta()`].
+    fn store(&self, value: &dyn DynCacheable, since: Option<&dyn Any>) -> std::io::Result<()> {
+        #[cfg(feature = "test-util")]
+        self.faults.before_store(value.as_any().type_id())?;
+        value.store_since(since, self.config.backend.as_ref())
+    }
+
+    /// Write back the buffered `Cacheable` before loading it again, see [`CacheBuilder::write_buffer()`].
+    /// It stays buffered if failed.
+    fn settle(&self, type_id: usize) -> std::io::Result<()> {
+        let Some(buffer) = self.write_buffer.as_ref() else {
+            return Ok(());
+        };
+        let Some(value) = buffer.take(type_id) else {
+            return Ok(());
+        };
+        self.write_back(&*value, None)
+            .inspect_err(|_| buffer.put_back(type_id, value))
+    }
+
+    /// The `Cacheable` is modified, notify its watchers and invalidate the ones derived from it,
+    /// see [`Cache::watch()`] and [`Cache::depends()`].
+    fn changed(&self, type_id: usize) {
+        self.loaded(type_id);
+        self.deps.invalidate_dependents(type_id);
+    }
+
+    /// The `Cacheable` is loaded or modified, notify its watchers and outdate its replicas,
+    /// see [`Cache::watch()`] and [`Cache::get_replica()`].
+    fn loaded(&self, type_id: usize) {
+        self.watchers.notify(type_id);
+        self.replicas.bump(type_id);
+    }
+
+    /// Write back every buffered `Cacheable`, see [`CacheBuilder::write_buffer()`].
+    fn drain(&self, errors: &mut Vec<(String, std::io::Error)>) {
+        let Some(buffer) = self.write_buffer.as_ref() else {
+            return;
+        };
+        for (type_id, value) in buffer.take_all() {
+            if let Err(e) = self.store(&*value, None) {
+                errors.push((value.type_name().to_string(), e));
+                buffer.put_back(type_id, value);
+            }
+        }
+    }
+
+    /// The `CacheGroup` of the `Cacheable`.
+    fn home(&self, type_id: usize) -> usize {
+        let remap = self.remap.read().unwrap_or_else(|e| e.into_inner());
+        match remap.get(&type_id) {
+            Some(&group) => group,
+            None => type_id % self.groups,
+        }
+    }
+
+    /// Put the `Cacheable` in `group` unless placed or migrated already, see [`Cacheable::GROUP_HINT`].
+    fn hint(&self, type_id: usize, group: usize) {
+        if self
+            .remap
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(&type_id)
+        {
+            return;
+        }
+        if group == type_id % self.groups && !self.config.fully_associative {
+            return;
+        }
+        let mut remap = self.remap.write().unwrap_or_else(|e| e.into_inner());
+        remap.entry(type_id).or_insert(group);
+    }
+
+    fn remap(&self, type_id: usize, group: usize) {
+        // kept even at home, overriding the placement and `Cacheable::GROUP_HINT`
+        let mut remap = self.remap.write().unwrap_or_else(|e| e.into_inner());
+        remap.insert(type_id, group);
+    }
+
+    /// Whether T can be admitted into a full `CacheGroup`, see [`CacheBuilder::doorkeeper()`].
+    fn admit<T: Any>(&self) -> bool {
+        match self.doorkeeper.as_ref() {
+            Some(doorkeeper) => doorkeeper
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .admit(type_id_usize::<T>()),
+            None => true,
+        }
+    }
+
+    /// Write the Cacheable back to storage in background, i.e. during eviction or drop.
+    /// The failure is reported to the handler registered by [`Cache::on_store_error()`].
+    fn write_back(&self, value: &dyn DynCacheable, since: Option<&dyn Any>) -> std::io::Result<()> {
+        self.store(value, since).inspect_err(|e| {
+            warn!("failed to write {} back: {e}", value.type_name());
+            if let Some(handler) = self.on_store_error.get().as_deref() {
+                handler(value.type_name(), e);
+            }
+        })
+    }
+
+    /// Move the value of the dirty `CacheLine` into the write buffer, return false if there is no room.
+    fn buffer(&self, line: &mut CacheLine) -> bool {
+        let Some(buffer) = self.write_buffer.as_ref() else {
+            return false;
+        };
+        let value = line.inner.take().unwrap();
+        match buffer.push(line.type_id, value) {
+            Ok(()) => true,
+            Err(value) => {
+                line.inner = Some(value);
+                false
+            }
+        }
+    }
+
+    /// The `Cacheable` `by` displaced the `victim` in `group`, report if they are thrashing, see [`Cache::thrashing()`].
+    fn displaced(&self, group: usize, by: (usize, &'static str), victim: (usize, &'static str)) {
+        let window = self.config.thrash_window.unwrap_or(Duration::from_secs(1));
+        let mut thrash = self.thrash.lock().unwrap_or_else(|e| e.into_inner());
+        if thrash.subscribers.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        if thrash.last.len() > 1024 {
+            thrash
+                .last
+                .retain(|_, (at, _)| now.duration_since(*at) < window);
+        }
+        // the victim displaced `by` just before
+        let count = match thrash.last.get(&(victim.0, by.0)) {
+            Some((at, count)) if now.duration_since(*at) < window => count + 1,
+            _ => 1,
+        };
+        thrash.last.insert((by.0, victim.0), (now, count));
+        if count < 2 {
+            return;
+        }
+        let event = ThrashEvent {
+            group,
+            types: (by.1, victim.1),
+            count,
+        };
+        thrash
+            .subscribers
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    /// Send the eviction to every subscriber, forgetting the disconnected ones.
+    fn evicted(&self, event: EvictionEvent) {
+        let mut evictions = self.evictions.lock().unwrap_or_else(|e| e.into_inner());
+        evictions.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    fn set_dirty(&self, flag: &Flag) {
+        if flag.set_dirty() {
+            self.dirty.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn set_clean(&self, flag: &Flag) {
+        if flag.set_clean() {
+            self.dirty.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<const G: usize, const L: usize> Default for CacheInner<G, L> {
+    fn default() -> Self {
+        Self::with_config(Config::default())
+    }
+}
+
+impl<const G: usize, const L: usize> Drop for CacheInner<G, L> {
+    fn drop(&mut self) {
+        let mut errors = vec![];
+        let batched = self.flush_batch(&mut errors);
+        for (type_name, e) in errors {
+            warn!("failed to write {type_name} back: {e}");
+            if let Some(handler) = self.ctx.on_store_error.get().as_deref() {
+                handler(&type_name, &e);
+            }
+        }
+        for group in self.groups.iter_mut() {
+            group.flush_on_drop(&self.ctx, &batched);
+        }
+        if let Some(buffer) = self.ctx.write_buffer.as_ref() {
+            buffer.close();
+            for (_, value) in buffer.take_all() {
+                self.ctx.write_back(&*value, None).ok();
+            }
+        }
+        if let Some(hook) = self.ctx.config.alloc_hook.as_ref() {
+            for group in self.groups.iter_mut() {
+                // SAFETY: the cache is being dropped, no `CacheLine` is being accessed
+                let mut lines = unsafe { group.lines() };
+                for line in lines.iter_mut() {
+                    hook.realloc(line.allocated.take(), None);
+                }
+            }
+            hook.realloc(Some(Self::allocated()), None);
+        }
+    }
+}
+
+impl<const G: usize, const L: usize> CacheInner<G, L> {
+    fn with_config(config: Config) -> Self {
+        let () = Shape::<G, L>::VALID;
+        // the thread writing back in background reports to the same handler
+        let workers = config.store_workers.unwrap_or(1);
+        let capacity = config.write_buffer.or(config.store_workers);
+        let (write_buffer, on_store_error) =
+            match capacity.map(|c| WriteBuffer::spawn(c, workers, config.backend.clone())) {
+                Some((buffer, on_store_error)) => (Some(buffer), on_store_error),
+                None => (None, Default::default()),
+            };
+        let groups = (0..G)
+            .map(|index| CacheGroup {
+                index,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        if let Some(hook) = config.alloc_hook.as_ref() {
+            hook.realloc(None, Some(Self::allocated()));
+        }
+        Self {
+            groups: groups.try_into().unwrap(),
+            ctx: Context {
+                groups: G,
+                active_lines: AtomicUsize::new(L),
+                doorkeeper: config
+                    .doorkeeper
+                    .map(|window| std::sync::Mutex::new(Doorkeeper::new(window))),
+                write_buffer,
+                on_store_error,
+                config,
+                ..Default::default()
+            },
+            registry: Default::default(),
+        }
+    }
+
+    /// The `CacheGroup`s and `CacheLine`s reported to the [`AllocHook`](crate::AllocHook),
+    /// see [`CacheBuilder::alloc_hook()`].
+    fn allocated() -> (&'static str, std::alloc::Layout) {
+        (
+            std::any::type_name::<Cache<G, L>>(),
+            std::alloc::Layout::new::<Self>(),
+        )
+    }
+
+    fn get<T: CacheableExt + Default>(&self) -> CacheResult<CacheRef<'_, T>> {
+        self.settle_deps(T::type_id_usize())?;
+        self.write_back();
+        let cache_ref = T::retrieve_from(self)?;
+        self.trim();
+        Ok(cache_ref)
+    }
+
+    fn get_mut<T: CacheableExt + Default>(&self) -> CacheResult<CacheMut<'_, T>> {
+        if self.ctx.config.read_only {
+            return Err(CacheError::ReadOnly);
+        }
+        self.settle_deps(T::type_id_usize())?;
+        self.write_back();
+        let cache_mut = T::retrieve_mut_from(self)?;
+        self.trim();
+        Ok(cache_mut)
+    }
+
+    fn get_mut_or_insert_with<T: CacheableExt>(
+        &self,
+        f: impl FnOnce() -> T,
+    ) -> CacheResult<CacheMut<'_, T>> {
+        if self.ctx.config.read_only {
+            return Err(CacheError::ReadOnly);
+        }
+        self.settle_deps(T::type_id_usize())?;
+        self.write_back();
+        let group = self.group::<T>();
+        let (mut cache_mut, filled) = group.retrieve_mut_with(&self.ctx, || Ok(f()))?;
+        if filled {
+            cache_mut.set_dirty();
+        }
+        self.trim();
+        Ok(cache_mut)
+    }
+
+    fn refresh<T: CacheableExt>(&self, policy: RefreshPolicy<T>) -> CacheResult<()> {
+        self.group::<T>().refresh(&self.ctx, policy)
+    }
+
+    /// Retrieve T if cached, without loading it, see [`Cache::memo_async()`].
+    fn get_cached<T: CacheableExt>(&self) -> CacheResult<Option<CacheRef<'_, T>>> {
+        self.settle_deps(T::type_id_usize())?;
+        self.group::<T>().retrieve_cached(&self.ctx)
+    }
+
+    /// Insert the computed T marked dirty, unless cached in between, see [`Cache::memo_async()`].
+    fn insert<T: CacheableExt>(&self, value: T) -> CacheResult<CacheRef<'_, T>> {
+        self.write_back();
+        let dirty = !self.ctx.config.read_only;
+        let cache_ref = self
+            .group::<T>()
+            .retrieve_with(&self.ctx, || Ok(value), dirty)?;
+        self.trim();
+        Ok(cache_ref)
+    }
+
+    /// If the dependencies of the `Cacheable` changed, write them back and invalidate it,
+    /// so that it is reloaded from them, see [`Cache::depends()`].
+    /// - CacheError::Io: writing back a dependency failed, the `Cacheable` is still invalidated next time.
+    fn settle_deps(&self, type_id: usize) -> CacheResult<()> {
+        let Some(dependencies) = self.ctx.deps.take_stale(type_id) else {
+            return Ok(());
+        };
+        let mut errors = vec![];
+        for dependency in dependencies {
+            self.groups[self.home(dependency)]
+                .flush_where(&self.ctx, &mut errors, |t| t == dependency);
+        }
+        // a dependency being written invalidates the `Cacheable` again when released
+        errors.retain(|(_, e)| e.kind() != std::io::ErrorKind::WouldBlock);
+        if let Some((_, e)) = errors.pop() {
+            self.ctx.deps.invalidate(type_id);
+            return Err(e.into());
+        }
+        self.groups[self.home(type_id)].invalidate(type_id, &self.ctx)
+    }
+
+    /// Move the `CacheLine`s of each `CacheGroup` to its NUMA node, see [`CacheBuilder::numa_nodes()`].
+    #[cfg(all(feature = "numa", target_os = "linux", not(loom)))]
+    fn bind_numa(&self) {
+        let Some(nodes) = self.ctx.config.numa_nodes else {
+            return;
+        };
+        for node in 0..nodes.min(G) {
+            let groups = crate::numa::groups(G, nodes, node);
+            let addr = self.groups[groups.start..groups.end].as_ptr().cast::<u8>();
+            let len = std::mem::size_of::<CacheGroup<L>>() * groups.len();
+            if let Err(e) = crate::numa::bind(addr, len, node) {
+                debug!("failed to move cache groups {groups:?} to NUMA node {node}: {e}");
+            }
+        }
+    }
+
+    /// The `CacheGroup` of T, see [`Cache::migrate()`] and [`Cacheable::GROUP_HINT`].
+    fn group<T: CacheableExt>(&self) -> &CacheGroup<L> {
+        if let Some(hint) = T::GROUP_HINT {
+            self.ctx.hint(T::type_id_usize(), hint % G);
+        }
+        &self.groups[self.home(T::type_id_usize())]
+    }
+
+    /// The `CacheGroup` of the `Cacheable`, placing it first if not yet placed
+    /// in fully associative mode, see [`CacheBuilder::fully_associative()`].
+    fn home(&self, type_id: usize) -> usize {
+        let numa_nodes = self.ctx.config.numa_nodes;
+        if !self.ctx.config.fully_associative && numa_nodes.is_none() {
+            return self.ctx.home(type_id);
+        }
+        if let Some(&group) = self
+            .ctx
+            .remap
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&type_id)
+        {
+            return group;
+        }
+        let candidates = match numa_nodes {
+            Some(nodes) => crate::numa::groups(G, nodes, crate::numa::current_node()),
+            None => 0..G,
+        };
+        let group = if self.ctx.config.fully_associative {
+            // the group with the most empty lines, then with the fewest evictions
+            let natural = type_id % G;
+            candidates
+                .max_by_key(|&g| {
+                    let empty = self.groups[g].empty_lines(&self.ctx).unwrap_or(0);
+                    let evictions = self.groups[g].evictions.load(Ordering::Relaxed);
+                    (empty, Reverse(evictions), g == natural)
+                })
+                .unwrap()
+        } else {
+            candidates.start + type_id % candidates.len()
+        };
+        let mut remap = self.ctx.remap.write().unwrap_or_else(|e| e.into_inner());
+        // placed by another thread meanwhile
+        *remap.entry(type_id).or_insert(group)
+    }
+
+    fn migrate<T: CacheableExt>(&self, to: usize) -> CacheResult<()> {
+        let from = self.home(T::type_id_usize());
+        if from == to {
+            return Ok(());
+        }
+        let group = &self.groups[from];
+        let _lock = group.lock_group()?;
+        if let Some(CacheSlot::Hit(i)) = group.slot::<T>()? {
+            group.evict(i, &self.ctx)?;
+        }
+        // remapped under the lock, so that no one loads it here again
+        self.ctx.remap(T::type_id_usize(), to);
+        Ok(())
+    }
+
+    fn rebalance(&self) -> CacheResult<Option<&'static str>> {
+        let evictions = self
+            .groups
+            .iter()
+            .map(|g| g.evictions.swap(0, Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let Some((hot, _)) = evictions
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n > 0)
+            .max_by_key(|(_, n)| **n)
+        else {
+            return Ok(None);
+        };
+        let mut empty = vec![];
+        for group in self.groups.iter() {
+            empty.push(group.empty_lines(&self.ctx)?);
+        }
+        let Some((cold, _)) = empty
+            .iter()
+            .enumerate()
+            .filter(|(g, n)| *g != hot && **n > 0)
+            .max_by_key(|(g, n)| (**n, Reverse(evictions[*g])))
+        else {
+            return Ok(None);
+        };
+        // the least recently used `Cacheable` not being used
+        self.groups[hot].migrate(&self.ctx, cold, |lines, flags| {
+            (0..L)
+                .filter(|&i| lines[i].type_id != 0 && !flags[i].in_using())
+                .max_by_key(|&i| lines[i].lru)
+        })
+    }
+
+    fn register<T: CacheableExt + Default>(&self) -> CacheResult<()> {
+        if let Some(hint) = T::GROUP_HINT {
+            self.ctx.hint(T::type_id_usize(), hint % G);
+        }
+        self.register_entry(Registered {
+            type_id: T::type_id_usize(),
+            type_name: std::any::type_name::<T>(),
+            warm: |cache, _| cache.get::<T>().map(drop),
+            get_dyn: |cache, _| cache.get::<T>().map(DynCacheRef::from),
+            get_mut_dyn: |cache, _| cache.get_mut::<T>().map(DynCacheMut::from),
+            loader: None,
+        })
+    }
+
+    fn register_dyn(&self, type_name: &'static str, loader: Loader) -> CacheResult<()> {
+        self.register_entry(Registered {
+            type_id: type_id_of(loader.type_id),
+            type_name,
+            warm: |cache, registered| cache.get_loaded(registered).map(drop),
+            get_dyn: |cache, registered| cache.get_loaded(registered),
+            get_mut_dyn: |cache, registered| cache.get_loaded_mut(registered),
+            loader: Some(loader),
+        })
+    }
+
+    /// Find the registered `Cacheable` of `type_id`.
+    fn registered(&self, type_id: TypeId) -> CacheResult<Registered<G, L>> {
+        let type_id = type_id_of(type_id);
+        self.registry
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|r| r.type_id == type_id)
+            .cloned()
+            .ok_or(CacheError::Unregistered)
+    }
+
+    fn register_entry(&self, registered: Registered<G, L>) -> CacheResult<()> {
+        let mut registry = self.registry.write().unwrap_or_else(|e| e.into_inner());
+        let type_id = registered.type_id;
+        if registry.iter().any(|r| r.type_id == type_id) {
+            return Ok(());
+        }
+        // all the groups are shared in fully associative mode
+        let fully_associative = self.ctx.config.fully_associative;
+        let colliding = registry
+            .iter()
+            .filter(|r| fully_associative || self.ctx.home(r.type_id) == self.ctx.home(type_id))
+            .map(|r| r.type_name)
+            .collect::<Vec<_>>();
+        let capacity = match fully_associative {
+            true => G * L,
+            false => L,
+        };
+        if colliding.len() >= capacity {
+            return Err(CacheError::Collision {
+                type_name: registered.type_name,
+                colliding,
+            });
+        }
+        registry.push(registered);
+        Ok(())
+    }
+
+    /// Retrieve a `Cacheable` registered at runtime mutably, see [`Cache::register_dyn()`].
+    fn get_loaded_mut(&self, registered: &Registered<G, L>) -> CacheResult<DynCacheMut<'_>> {
+        if self.ctx.config.read_only {
+            return Err(CacheError::ReadOnly);
+        }
+        let loader = registered
+            .loader
+            .as_ref()
+            .expect("not registered at runtime");
+        self.write_back();
+        let cache_mut = loop {
+            let group = &self.groups[self.home(registered.type_id)];
+            match group.retrieve_loaded_mut(&self.ctx, registered.type_name, loader) {
+                // migrated concurrently, see `Cache::migrate()`
+                Err(CacheError::Missing) => continue,
+                res => break res?,
+            }
+        };
+        self.trim();
+        Ok(cache_mut)
+    }
+
+    /// Retrieve a `Cacheable` registered at runtime, see [`Cache::register_dyn()`].
+    fn get_loaded(&self, registered: &Registered<G, L>) -> CacheResult<DynCacheRef<'_>> {
+        let loader = registered
+            .loader
+            .as_ref()
+            .expect("not registered at runtime");
+        self.write_back();
+        let cache_ref = loop {
+            let group = &self.groups[self.home(registered.type_id)];
+            match group.retrieve_loaded(&self.ctx, registered.type_name, loader) {
+                // migrated concurrently, see `Cache::migrate()`
+                Err(CacheError::Missing) => continue,
+                res => break res?,
+            }
+        };
+        self.trim();
+        Ok(cache_ref)
+    }
+
+    fn resize_lines(&self, lines: usize) -> Vec<(String, std::io::Error)> {
+        let lines = lines.clamp(1, L);
+        self.ctx.active_lines.store(lines, Ordering::Relaxed);
+        let mut errors = vec![];
+        for group in self.groups.iter() {
+            group.shrink(&self.ctx, lines, &mut errors);
+        }
+        errors
+    }
+
+    /// Write back all dirty `CacheLine`s, return the failures with the type names.
+    fn flush_all(&self) -> Vec<(String, std::io::Error)> {
+        let mut errors = vec![];
+        let batched = self.flush_batch(&mut errors);
+        // the dependencies first, see `Cache::depends()`
+        for type_id in self.ctx.deps.order() {
+            self.groups[self.home(type_id)].flush_where(&self.ctx, &mut errors, |t| {
+                t == type_id && !batched.contains(&t)
+            });
+        }
+        for group in self.groups.iter() {
+            group.flush_where(&self.ctx, &mut errors, |t| !batched.contains(&t));
+        }
+        self.ctx.drain(&mut errors);
+        errors
+    }
+
+    /// Write back the dirty `CacheLine`s holding a `Cacheable` with a [`Cacheable::KEY`] by one call of
+    /// the `StoreBatch`, see [`CacheBuilder::store_batch()`].
+    /// Return the type ids offered, stored or not, so that they are not written back one by one again.
+    fn flush_batch(&self, errors: &mut Vec<(String, std::io::Error)>) -> HashSet<usize> {
+        let mut batched = HashSet::new();
+        let Some(batch) = self.ctx.config.store_batch.as_ref() else {
+            return batched;
+        };
+        // in index order, no other path holds more than one group lock
+        let _locks = self
+            .groups
+            .iter()
+            .map(|group| lock_anyway(&group.lock))
+            .collect::<Vec<_>>();
+        let mut entries = vec![];
+        for (g, group) in self.groups.iter().enumerate() {
+            let lines = unsafe { group.lines() };
+            let flags = unsafe { &*group.flags.get() };
+            for (i, flag) in flags.iter().enumerate() {
+                // the ones being written are reported by `CacheGroup::flush_where()`
+                if !flag.is_dirty() || flag.is_writing() {
+                    continue;
+                }
+                let Some((key, bytes)) = lines[i].inner.as_deref().unwrap().encode_keyed() else {
+                    continue;
+                };
+                batched.insert(lines[i].type_id);
+                match bytes {
+                    Ok(bytes) => entries.push((g, i, key, bytes)),
+                    Err(e) => errors.push((lines[i].type_name.to_string(), e)),
+                }
+            }
+        }
+        if entries.is_empty() {
+            return batched;
+        }
+        let pairs = entries
+            .iter()
+            .map(|(_, _, key, bytes)| (key.as_str(), bytes.as_slice()))
+            .collect::<Vec<_>>();
+        let res = batch.0.store_batch(&pairs);
+        for &(g, i, ..) in entries.iter() {
+            let mut lines = unsafe { self.groups[g].lines() };
+            let flags = unsafe { &*self.groups[g].flags.get() };
+            match res.as_ref() {
+                Ok(()) => {
+                    self.ctx.set_clean(&flags[i]);
+                    lines[i].commit(&self.ctx);
+                }
+                Err(e) => errors.push((
+                    lines[i].type_name.to_string(),
+                    std::io::Error::new(e.kind(), e.to_string()),
+                )),
+            }
+        }
+        batched
+    }
+
+    /// Evict the cold clean `CacheLine`s first, then the dirty ones with write-back,
+    /// until the namespace accessed on this thread is within its quota (see [`CacheBuilder::scope_quota()`]),
+    /// and the total weight is no more than `max_bytes`.
+    ///
+    /// It never fails the access checking it, the same as `write_back()`.
+    fn trim(&self) {
+        if let Some(namespace) = crate::namespace::current() {
+            let quota = self.ctx.config.scope_quota;
+            self.evict_cold(Some(&namespace), || {
+                !self.ctx.scopes.over(&namespace, quota)
+            });
+        }
+        let Some(max_bytes) = self.ctx.config.max_bytes else {
+            return;
+        };
+        self.evict_cold(None, || self.ctx.bytes.load(Ordering::Relaxed) <= max_bytes);
+    }
+
+    /// Evict the cold clean `CacheLine`s first, then the dirty ones with write-back, until `within`,
+    /// only the ones of `namespace` if given.
+    /// A dirty `CacheLine` failed to write back is kept, see `trim()`.
+    fn evict_cold(&self, namespace: Option<&str>, within: impl Fn() -> bool) {
+        if within() {
+            return;
+        }
+        let mut candidates = vec![];
+        for (g, group) in self.groups.iter().enumerate() {
+            for (i, lru, dirty) in group.unused_lines(namespace).unwrap_or_default() {
+                candidates.push((dirty, Reverse(lru), g, i));
+            }
+        }
+        candidates.sort_unstable();
+        for (_, _, g, i) in candidates {
+            if within() {
+                break;
+            }
+            self.groups[g].evict_unused(i, &self.ctx).ok();
+        }
+    }
+
+    /// Keep an empty `CacheLine` in each `CacheGroup` for the next miss, see [`CacheBuilder::sweep()`].
+    /// Failed write-backs are reported to the handler registered by [`Cache::on_store_error()`].
+    #[cfg(not(loom))]
+    fn sweep(&self) {
+        for group in self.groups.iter() {
+            group.sweep(&self.ctx).ok();
+        }
+    }
+
+    fn relieve(&self, level: MemoryPressure) -> Result<usize, Vec<(String, std::io::Error)>> {
+        let mut errors = vec![];
+        let dropped = self
+            .groups
+            .iter()
+            .map(|group| group.relieve(&self.ctx, level, &mut errors))
+            .sum();
+        if level == MemoryPressure::Critical {
+            self.ctx.drain(&mut errors);
+        }
+        match errors.is_empty() {
+            true => Ok(dropped),
+            false => Err(errors),
+        }
+    }
+
+    /// Write back the least recently used dirty `CacheLine`s
+    /// until the number of dirty `CacheLine`s is no more than `max_dirty`.
+    ///
+    /// It never fails the access checking it: a failed write-back is logged and reported to the handler
+    /// registered by [`Cache::on_store_error()`] by `Context::write_back()`, and left dirty for the next check,
+    /// and a poisoned `CacheGroup` is logged by `lock_group()` and skipped.
+    fn write_back(&self) {
+        let Some(max_dirty) = self.ctx.config.max_dirty else {
+            return;
+        };
+        let dirty = self.ctx.dirty.load(Ordering::Relaxed);
+        if dirty <= max_dirty {
+            return;
+        }
+        let mut candidates = vec![];
+        for (g, group) in self.groups.iter().enumerate() {
+            for (i, lru) in group.dirty_lines().unwrap_or_default() {
+                candidates.push((lru, g, i));
+            }
+        }
+        candidates.sort_unstable_by_key(|c| std::cmp::Reverse(c.0));
+        for (_, g, i) in candidates.into_iter().take(dirty - max_dirty) {
+            self.groups[g].write_back(i, &self.ctx).ok();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CacheGroup<const L: usize> {
+    index: usize,
+    lines: UnsafeCell<[CacheLine; L]>,
+    flags: UnsafeCell<[Flag; L]>,
+    lock: Mutex<()>,
+    /// The number of evictions since last [`Cache::rebalance()`].
+    evictions: AtomicUsize,
+    /// Evict the least frequently used `CacheLine` instead of the least recently used one,
+    /// see [`CacheBuilder::adaptive_policy()`].
+    lfu: AtomicBool,
+    stats: std::sync::Mutex<GroupStats>,
+}
+
+/// # Safety
+/// All APIs of `CacheGroup` synchronize with the lock.
+unsafe impl<const L: usize> Sync for CacheGroup<L> {}
+
+impl<const L: usize> Default for CacheGroup<L> {
+    fn default() -> Self {
+        let lines = (0..L).map(|_| CacheLine::default()).collect::<Vec<_>>();
+        let flags = (0..L).map(|_| Flag::default()).collect::<Vec<_>>();
+        Self {
+            lines: UnsafeCell::new(lines.try_into().unwrap()),
+            flags: UnsafeCell::new(flags.try_into().unwrap()),
+            lock: Mutex::new(()),
+            index: 0,
+            evictions: AtomicUsize::new(0),
+            lfu: AtomicBool::new(false),
+            stats: Default::default(),
+        }
+    }
+}
+
+impl<const L: usize> CacheGroup<L> {
+    fn lock_group(&self) -> CacheResult<MutexGuard<'_, ()>> {
+        lock(&self.lock, "cache group")
+    }
+
+    /// Access the `CacheLine`s, see [`Lines`].
+    ///
+    /// # Safety
+    /// The caller must hold the lock of the group, or have exclusive access to it, e.g. a new or dropped one.
+    unsafe fn lines(&self) -> Lines<'_, L> {
+        Lines {
+            ptr: self.lines.get(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Write back all dirty `CacheLine`s but the `batched` ones, called when the `Cache` is dropped.
+    fn flush_on_drop(&mut self, ctx: &Context, batched: &HashSet<usize>) {
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        for (i, f) in flags.iter().enumerate() {
+            if f.is_dirty() && !batched.contains(&lines[i].type_id) {
+                ctx.write_back(lines[i].inner.as_deref().unwrap(), lines[i].baseline())
+                    .ok();
+            }
+        }
+    }
+
+    /// Lock the group and find the `CacheLine` for T.
+    /// Loading T happens outside the lock, and concurrent loadings of T are deduplicated.
+    /// `init` is called at most once to produce T instead of loading it.
+    fn acquire<'a, T: CacheableExt>(
+        &'a self,
+        ctx: &'a Context,
+        init: impl FnOnce() -> CacheResult<T>,
+    ) -> CacheResult<Acquired<'a, T>> {
+        let mut init = Some(init);
+        let mut init = || (init.take().expect("init called twice"))();
+        let mut flight = None;
+        let mut loaded = None;
+        let mut admitted = None;
+        let mut refused = None;
+        loop {
+            let lock = self.lock_group()?;
+            if ctx.home(T::type_id_usize()) != self.index {
+                return Err(CacheError::Missing);
+            }
+            let had_loaded = loaded.is_some();
+            if let Some(i) = self.load::<T>(ctx, &mut loaded, &mut admitted, &mut refused)? {
+                return Ok(Acquired::Line {
+                    _lock: lock,
+                    _flight: flight,
+                    i,
+                    filled: had_loaded && loaded.is_none(),
+                });
+            }
+            drop(lock);
+            if admitted == Some(false) {
+                let value = match (loaded.take(), refused.take()) {
+                    (Some(value), _) => value,
+                    (None, Some(e)) => return Err(e),
+                    (None, None) => init()?,
+                };
+                return Ok(Acquired::Bypass(value));
+            }
+            if flight.is_none() {
+                flight = ctx.flights.begin::<T>()?;
+                if flight.is_none() {
+                    // T was being loaded by another thread, try again
+                    continue;
+                }
+            }
+            match init() {
+                Ok(value) => loaded = Some(value),
+                // serve the stale T if cached, see `CacheBuilder::load_rate()` and `CacheBuilder::circuit_breaker()`
+                Err(e @ (CacheError::RateLimited { .. } | CacheError::CircuitOpen { .. })) => {
+                    refused = Some(e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Put the `loaded` T into CacheLine and update LRU.
+    /// Return None if T needs loading but nothing `loaded`, or T is not `admitted` by the doorkeeper.
+    /// If loading was `refused`, the stale T is served, or the error is returned if none.
+    fn load<T: CacheableExt>(
+        &self,
+        ctx: &Context,
+        loaded: &mut Option<T>,
+        admitted: &mut Option<bool>,
+        refused: &mut Option<CacheError>,
+    ) -> CacheResult<Option<usize>> {
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let slot = match self.active_slot::<T>(ctx.active_lines.load(Ordering::Relaxed))? {
+            Some(CacheSlot::Hit(i)) => Some(CacheSlot::Hit(i)),
+            slot => self.scope_victim(ctx).or(slot),
+        };
+        if matches!(slot, Some(CacheSlot::Evict(_)))
+            && !*admitted
+                .get_or_insert_with(|| ctx.once.contains(T::type_id_usize()) || ctx.admit::<T>())
+        {
+            return Ok(None);
+        }
+        let (i, lru) = match slot {
+            Some(CacheSlot::Hit(i)) => (i, lines[i].lru),
+            Some(CacheSlot::Empty(i)) => (i, usize::MAX),
+            Some(CacheSlot::Evict(i)) => (i, lines[i].lru),
+            None => unreachable!(),
+        };
+        // Evicting, or hit but loaded in previous generations
+        let hit = lines[i].type_id == T::type_id_usize();
+        let mut stale = !hit
+            || lines[i].generation != ctx.generation.load(Ordering::Relaxed)
+            || lines[i].is_outdated();
+        // `Cache::get_or_init()` types are never loaded again
+        let once = || {
+            let mut lines = unsafe { self.lines() };
+            if !lines[i].once {
+                lines[i].once = ctx.once.contains(lines[i].type_id);
+            }
+            lines[i].once
+        };
+        if hit && stale && once() {
+            stale = false;
+        }
+        if !stale {
+            self.swap_revalidated(i, ctx);
+            let lines = unsafe { self.lines() };
+            let expired = lines[i].is_expired(ctx);
+            if (expired || lines[i].is_expiring(ctx)) && !once() {
+                match (expired, ctx.config.stale_while_revalidate) {
+                    (true, false) => stale = true,
+                    _ => self.revalidate::<T>(i, ctx),
+                }
+            }
+        }
+        if stale && loaded.is_none() {
+            if let Some(e) = refused.take() {
+                match hit {
+                    true => stale = false,
+                    false => return Err(e),
+                }
+            }
+        }
+        if !stale {
+            self.hit(i, ctx);
+        }
+        if stale {
+            if flags[i].in_using() {
+                return Err(CacheError::Busy);
+            }
+            let Some(value) = loaded.take() else {
+                return Ok(None);
+            };
+            self.replace(i, ctx, value)?;
+            self.stats()
+                .miss(T::type_id_usize(), std::any::type_name::<T>());
+            self.adapt(ctx, T::type_id_usize(), false);
+        }
+        let mut lines = unsafe { self.lines() };
+        age(lines.iter_mut().map(|l| &mut l.lru), lru);
+        lines[i].lru = 0;
+        lines[i].accessed_at = Some(SystemTime::now());
+        Ok(Some(i))
+    }
+
+    fn stats(&self) -> std::sync::MutexGuard<'_, GroupStats> {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Report the statistics and the occupied `CacheLine`s, see [`Cache::tuning_report()`].
+    fn report(&self, ctx: &Context) -> CacheResult<GroupReport> {
+        let occupied = ctx.active_lines.load(Ordering::Relaxed) - self.empty_lines(ctx)?;
+        let stats = self.stats();
+        let mut types = stats.seen.values().copied().collect::<Vec<_>>();
+        types.sort_unstable();
+        Ok(GroupReport {
+            hits: stats.hits,
+            misses: stats.misses,
+            conflicts: stats.conflicts,
+            occupied,
+            mean_reuse_distance: match stats.hits {
+                0 => 0.0,
+                hits => stats.reuse as f64 / hits as f64,
+            },
+            types,
+            lfu: self.lfu.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Count a hit of the i-th `CacheLine`.
+    fn hit(&self, i: usize, ctx: &Context) {
+        let lines = unsafe { self.lines() };
+        self.promote(i, ctx);
+        self.stats().hit(lines[i].lru);
+        self.adapt(ctx, lines[i].type_id, true);
+    }
+
+    /// The access frequencies to evict by, if evicting the least frequently used `CacheLine`.
+    fn frequency(&self) -> Option<std::sync::MutexGuard<'_, GroupStats>> {
+        self.lfu.load(Ordering::Relaxed).then(|| self.stats())
+    }
+
+    /// Switch between evicting the least recently used and the least frequently used `CacheLine`s
+    /// if the current policy underperforms, see [`CacheBuilder::adaptive_policy()`].
+    fn adapt(&self, ctx: &Context, type_id: usize, hit: bool) {
+        let Some(window) = ctx.config.adaptive_policy else {
+            return;
+        };
+        let lfu = self.lfu.load(Ordering::Relaxed);
+        if self.stats().tick(type_id, hit, window, lfu) {
+            self.lfu.store(!lfu, Ordering::Relaxed);
+        }
+    }
+
+    /// Promote the i-th `CacheLine` into the hot tier on a hit,
+    /// demoting the least recently used hot one if full, see [`CacheBuilder::hot_tier()`].
+    fn promote(&self, i: usize, ctx: &Context) {
+        let Some(hot_lines) = ctx.config.hot_tier else {
+            return;
+        };
+        let mut lines = unsafe { self.lines() };
+        if lines[i].hot {
+            return;
+        }
+        if lines.iter().filter(|l| l.hot).count() >= hot_lines {
+            let coldest = (0..L)
+                .filter(|&j| lines[j].hot)
+                .max_by_key(|&j| lines[j].lru);
+            match coldest {
+                Some(j) => lines[j].hot = false,
+                None => return,
+            }
+        }
+        lines[i].hot = true;
+    }
+
+    /// Load T in background for the i-th `CacheLine`, which is still served before swapped.
+    fn revalidate<T: CacheableExt>(&self, i: usize, ctx: &Context) {
+        let mut lines = unsafe { self.lines() };
+        if lines[i].revalidating.is_some() {
+            return;
+        }
+        let (tx, rx) = std::sync::mpsc::channel();
+        let backend = ctx.config.backend.clone();
+        let namespace = crate::namespace::current();
+        let (loads, max_loads) = (ctx.loads.clone(), ctx.config.max_loads);
+        std::thread::spawn(move || {
+            let _permit = loads.permit(max_loads, None);
+            crate::namespace::within(namespace, || {
+                let loaded = crate::backend::load::<T>(backend.as_ref());
+                tx.send(loaded.map(|t| crate::namespace::tag(Box::new(t))))
+            })
+        });
+        lines[i].revalidating = Some(rx);
+    }
+
+    /// Swap in the value loaded by `revalidate` if ready and the i-th `CacheLine` is not being used.
+    /// The value is discarded if the `CacheLine` is dirty, or the loading failed.
+    fn swap_revalidated(&self, i: usize, ctx: &Context) {
+        let mut lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        if flags[i].in_using() {
+            return;
+        }
+        let Some(rx) = lines[i].revalidating.as_ref() else {
+            return;
+        };
+        match rx.try_recv() {
+            Err(std::sync::mpsc::TryRecvError::Empty) => return,
+            Ok(Ok(value)) if !flags[i].is_dirty() => {
+                lines[i].inner = Some(value);
+                lines[i].loaded_at = Some(Instant::now());
+                lines[i].commit(ctx);
+                ctx.changed(lines[i].type_id);
+            }
+            Ok(Ok(_)) => lines[i].loaded_at = Some(Instant::now()),
+            _ => {}
+        }
+        lines[i].revalidating = None;
+    }
+
+    /// Write back the i-th `CacheLine` if dirty, then put T into it.
+    /// - CacheError::Busy: the `CacheLine` is being used.
+    fn replace<T: CacheableExt>(&self, i: usize, ctx: &Context, value: T) -> CacheResult<()> {
+        self.evict_for(i, ctx, T::type_id_usize(), std::any::type_name::<T>())?;
+        let mut lines = unsafe { self.lines() };
+        lines[i].fill(ctx, value);
+        Ok(())
+    }
+
+    /// Empty the i-th `CacheLine` to put the `Cacheable` with `type_id` into it, like `evict`.
+    /// Displacing another `Cacheable` counts for [`Cache::rebalance()`] and [`Cache::thrashing()`].
+    fn evict_for(
+        &self,
+        i: usize,
+        ctx: &Context,
+        type_id: usize,
+        type_name: &'static str,
+    ) -> CacheResult<()> {
+        let lines = unsafe { self.lines() };
+        let displaced = lines[i].type_id != 0 && lines[i].type_id != type_id;
+        let (victim, victim_name) = (lines[i].type_id, lines[i].type_name);
+        if displaced {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+            self.stats().conflicts += 1;
+        }
+        self.evict(i, ctx)?;
+        if displaced {
+            ctx.displaced(self.index, (type_id, type_name), (victim, victim_name));
+        }
+        Ok(())
+    }
+
+    /// Write back the i-th `CacheLine` if dirty, then empty it.
+    /// - CacheError::Busy: the `CacheLine` is being used.
+    fn evict(&self, i: usize, ctx: &Context) -> CacheResult<()> {
+        let mut lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        if flags[i].in_using() {
+            return Err(CacheError::Busy);
+        }
+        if lines[i].type_id == 0 {
+            return Ok(());
+        }
+        let dirty = flags[i].is_dirty();
+        if dirty && !lines[i].once && ctx.buffer(&mut lines[i]) {
+            ctx.evicted(EvictionEvent {
+                type_name: lines[i].type_name,
+                dirty,
+                stored: false,
+                buffered: true,
+            });
+            ctx.set_clean(&flags[i]);
+        } else if dirty {
+            let stored = ctx.write_back(lines[i].inner.as_deref().unwrap(), lines[i].baseline());
+            ctx.evicted(EvictionEvent {
+                type_name: lines[i].type_name,
+                dirty,
+                stored: stored.is_ok(),
+                buffered: false,
+            });
+            stored?;
+            ctx.set_clean(&flags[i]);
+        } else {
+            ctx.evicted(EvictionEvent {
+                type_name: lines[i].type_name,
+                dirty,
+                stored: false,
+                buffered: false,
+            });
+        }
+        if lines[i].once {
+            ctx.once
+                .retain(lines[i].type_id, lines[i].inner.take().unwrap());
+        }
+        lines[i].clear(ctx);
+        Ok(())
+    }
+
+    /// Evict the `CacheLine` chosen by `pick` and move its `Cacheable` to group `to`,
+    /// return the type name of the moved `Cacheable`.
+    fn migrate(
+        &self,
+        ctx: &Context,
+        to: usize,
+        pick: impl FnOnce(Lines<'_, L>, &[Flag]) -> Option<usize>,
+    ) -> CacheResult<Option<&'static str>> {
+        let _lock = self.lock_group()?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let Some(i) = pick(lines, flags) else {
+            return Ok(None);
+        };
+        let (type_id, type_name) = (lines[i].type_id, lines[i].type_name);
+        self.evict(i, ctx)?;
+        // remapped under the lock, so that no one loads it here again
+        ctx.remap(type_id, to);
+        Ok(Some(type_name))
+    }
+
+    /// Copy the `Cacheable`s which can be copied by [`Cacheable::snapshot()`] into the same `CacheLine`s of `fork`,
+    /// with their LRU and dirtiness, see [`Cache::fork()`].
+    fn fork_into(
+        &self,
+        ctx: &Context,
+        fork: &CacheGroup<L>,
+        fork_ctx: &Context,
+    ) -> CacheResult<()> {
+        let _lock = self.lock_group()?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let mut fork_lines = unsafe { fork.lines() };
+        let fork_flags = unsafe { &*fork.flags.get() };
+        let generation = ctx.generation.load(Ordering::Relaxed);
+        for (i, line) in lines.iter().enumerate() {
+            fork_lines[i].lru = line.lru;
+            if flags[i].is_writing() || (line.generation != generation && !line.once) {
+                continue;
+            }
+            let copy = match (line.snapshot_of, line.inner.as_deref()) {
+                (Some(snapshot_of), Some(inner)) => {
+                    crate::namespace::within(inner.namespace().cloned(), || {
+                        snapshot_of(inner).map(crate::namespace::tag)
+                    })
+                }
+                _ => None,
+            };
+            let Some(copy) = copy else {
+                continue;
+            };
+            let forked = &mut fork_lines[i];
+            forked.inner = Some(copy);
+            forked.snapshot_of = line.snapshot_of;
+            forked.type_id = line.type_id;
+            forked.type_name = line.type_name;
+            forked.exact = line.exact;
+            forked.priority = line.priority;
+            forked.expire_after = line.expire_after;
+            forked.loaded_at = line.loaded_at;
+            forked.accessed_at = line.accessed_at;
+            forked.commit(fork_ctx);
+            if flags[i].is_dirty() {
+                fork_ctx.set_dirty(&fork_flags[i]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the `Cacheable` in `from` (with `from_flag`, of another cache with `from_ctx`) into this `CacheGroup`,
+    /// unless the cached one of the same type is kept by `policy`, in which case `from` is discarded.
+    /// Either way, `from` is emptied. See [`Cache::absorb()`].
+    /// - CacheError::Busy: the `CacheLine` to replace is being used, or no `CacheLine` can be taken,
+    ///   `from` is left untouched.
+    /// - CacheError::Io: writing back the `CacheLine` to replace failed, `from` is left untouched.
+    fn absorb(
+        &self,
+        ctx: &Context,
+        from: &mut CacheLine,
+        from_flag: &Flag,
+        from_ctx: &Context,
+        policy: &mut ConflictPolicy,
+    ) -> CacheResult<()> {
+        let _lock = self.lock_group()?;
+        let mut lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let active = ctx.active_lines.load(Ordering::Relaxed);
+        let slot = match lines.iter().position(|l| l.type_id == from.type_id) {
+            Some(i) => CacheSlot::Hit(i),
+            None => self
+                .free_slot(from.type_id, active)
+                .ok_or(CacheError::Busy)?,
+        };
+        let from_dirty = from_flag.is_dirty();
+        let i = match slot {
+            CacheSlot::Hit(i) if lines[i].exact != from.exact => {
+                return Err(CacheError::TypeMismatch {
+                    expected: from.type_name,
+                    found: lines[i].type_name,
+                });
+            }
+            CacheSlot::Hit(i) => {
+                if flags[i].in_using() {
+                    return Err(CacheError::Busy);
+                }
+                let current =
+                    lines[i].once || lines[i].generation == ctx.generation.load(Ordering::Relaxed);
+                let incoming = from.inner.as_deref().unwrap().as_any();
+                let take = !current
+                    || match policy {
+                        ConflictPolicy::KeepNewest => from.accessed_at >= lines[i].accessed_at,
+                        ConflictPolicy::PreferDirty => from_dirty || !flags[i].is_dirty(),
+                        ConflictPolicy::Callback(f) => f(
+                            from.type_name,
+                            lines[i].inner.as_deref().unwrap().as_any(),
+                            incoming,
+                        ),
+                    };
+                if !current {
+                    self.evict(i, ctx)?;
+                } else if take {
+                    if flags[i].is_dirty() {
+                        ctx.store(lines[i].inner.as_deref().unwrap(), lines[i].baseline())?;
+                    }
+                    ctx.set_clean(&flags[i]);
+                    lines[i].clear(ctx);
+                }
+                if !take {
+                    from_ctx.set_clean(from_flag);
+                    from.clear(from_ctx);
+                    return Ok(());
+                }
+                i
+            }
+            CacheSlot::Empty(i) => i,
+            CacheSlot::Evict(i) => {
+                self.evict(i, ctx)?;
+                i
+            }
+        };
+        let lru = match lines[i].type_id {
+            0 => usize::MAX,
+            _ => lines[i].lru,
+        };
+        from_ctx.set_clean(from_flag);
+        from_ctx.bytes.fetch_sub(from.weight, Ordering::Relaxed);
+        if let Some(namespace) = from.namespace.take() {
+            from_ctx.scopes.remove(&namespace, from.weight);
+        }
+        let from_lru = from.lru;
+        let mut line = std::mem::replace(
+            from,
+            CacheLine {
+                lru: from_lru,
+                ..Default::default()
+            },
+        );
+        line.weight = 0;
+        if let Some(hook) = from_ctx.config.alloc_hook.as_ref() {
+            hook.realloc(line.allocated.take(), None);
+        }
+        line.generation = ctx.generation.load(Ordering::Relaxed);
+        line.once = ctx.once.contains(line.type_id);
+        line.lru = lru;
+        line.commit(ctx);
+        lines[i] = line;
+        if from_dirty {
+            ctx.set_dirty(&flags[i]);
+        }
+        age(lines.iter_mut().map(|l| &mut l.lru), lru);
+        lines[i].lru = 0;
+        ctx.changed(lines[i].type_id);
+        Ok(())
+    }
+
+    /// Move every `Cacheable` not being used into `into`, see [`Cache::absorb()`].
+    fn absorb_into<const G: usize, const IL: usize>(
+        &self,
+        ctx: &Context,
+        into: &CacheInner<G, IL>,
+        policy: &mut ConflictPolicy,
+        errors: &mut Vec<(String, std::io::Error)>,
+    ) {
+        let _lock = lock_anyway(&self.lock);
+        let mut lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let generation = ctx.generation.load(Ordering::Relaxed);
+        for (line, flag) in lines.iter_mut().zip(flags.iter()) {
+            if line.inner.is_none() || (line.generation != generation && !line.once) {
+                continue;
+            }
+            let type_name = line.type_name;
+            let group = &into.groups[into.home(line.type_id)];
+            let res = match flag.in_using() {
+                true => Err(CacheError::Busy),
+                false => group.absorb(&into.ctx, line, flag, ctx, policy),
+            };
+            match res {
+                Ok(()) => {}
+                Err(CacheError::Busy) => errors.push((
+                    type_name.to_string(),
+                    std::io::Error::new(std::io::ErrorKind::WouldBlock, CacheError::Busy),
+                )),
+                Err(CacheError::Io(e)) => errors.push((type_name.to_string(), e)),
+                Err(e) => errors.push((type_name.to_string(), std::io::Error::other(e))),
+            }
+        }
+    }
+
+    /// The number of empty `CacheLine`s.
+    fn empty_lines(&self, ctx: &Context) -> CacheResult<usize> {
+        let _lock = self.lock_group()?;
+        let lines = unsafe { self.lines() };
+        let active = lines.iter().take(ctx.active_lines.load(Ordering::Relaxed));
+        Ok(active.filter(|l| l.type_id == 0).count())
+    }
+
+    /// Replace the cached T with the loaded one according to the policy.
+    fn refresh<T: CacheableExt>(&self, ctx: &Context, policy: RefreshPolicy<T>) -> CacheResult<()> {
+        let _lock = self.lock_group()?;
+        let Some(CacheSlot::Hit(i)) = self.slot::<T>()? else {
+            return Ok(());
+        };
+        let mut lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        if flags[i].in_using() {
+            return Err(CacheError::Locked);
+        }
+        if flags[i].is_dirty() && matches!(policy, RefreshPolicy::Error) {
+            return Err(CacheError::Dirty);
+        }
+        let loaded = ctx.try_load::<T>()?;
+        let cached = downcast_mut::<T>(lines[i].inner.as_deref_mut().unwrap())?;
+        match policy {
+            RefreshPolicy::Merge(merge) if flags[i].is_dirty() => merge(cached, loaded),
+            _ => {
+                *cached = loaded;
+                ctx.set_clean(&flags[i]);
+                lines[i].commit(ctx);
+            }
+        }
+        lines[i].generation = ctx.generation.load(Ordering::Relaxed);
+        ctx.changed(lines[i].type_id);
+        Ok(())
+    }
+
+    /// Make the `CacheLine` holding the `Cacheable` stale, to be reloaded when accessed next.
+    fn invalidate(&self, type_id: usize, ctx: &Context) -> CacheResult<()> {
+        let _lock = self.lock_group()?;
+        let mut lines = unsafe { self.lines() };
+        if let Some(line) = lines.iter_mut().find(|l| l.type_id == type_id) {
+            line.generation = ctx.generation.load(Ordering::Relaxed).wrapping_sub(1);
+        }
+        Ok(())
+    }
+
+    /// Whether T is cached and up to date, see [`Cache::contains()`].
+    fn contains<T: CacheableExt>(&self, ctx: &Context) -> CacheResult<bool> {
+        let _lock = self.lock_group()?;
+        let Some(CacheSlot::Hit(i)) = self.slot::<T>()? else {
+            return Ok(false);
+        };
+        let line = unsafe { self.lines() }.get(i);
+        let current = line.generation == ctx.generation.load(Ordering::Relaxed) || line.once;
+        let current = current && (line.once || !line.is_outdated());
+        Ok(line.exact == Some(TypeId::of::<T>()) && current && !line.is_expired(ctx))
+    }
+
+    /// Find the `CacheLine` for T.
+    /// - CacheError::TypeMismatch: the `CacheLine` found holds another type, whose truncated type id is the same.
+    fn slot<T: CacheableExt>(&self) -> CacheResult<Option<CacheSlot>> {
+        let lines = unsafe { self.lines() };
+        let slot = find_slot::<T, _>(lines.iter().map(|l| l.slot_key(T::type_id_usize(), None)));
+        if let Some(CacheSlot::Hit(i)) = slot {
+            lines[i].holds::<T>()?;
+        }
+        Ok(slot)
+    }
+
+    /// Like `slot`, but only the first `active` `CacheLine`s can be filled, see [`Cache::resize_lines()`].
+    fn active_slot<T: CacheableExt>(&self, active: usize) -> CacheResult<Option<CacheSlot>> {
+        Ok(match self.slot::<T>()? {
+            Some(CacheSlot::Hit(i)) => Some(CacheSlot::Hit(i)),
+            _ => self.free_slot(T::type_id_usize(), active),
+        })
+    }
+
+    /// The coldest `CacheLine` of the namespace accessed on this thread not being used, if the namespace
+    /// holds as many as its quota allows, so that it evicts its own ones instead of the others', see [`CacheBuilder::scope_quota()`].
+    fn scope_victim(&self, ctx: &Context) -> Option<CacheSlot> {
+        let namespace = crate::namespace::current()?;
+        if !ctx.scopes.full(&namespace, ctx.config.scope_quota) {
+            return None;
+        }
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        (0..ctx.active_lines.load(Ordering::Relaxed))
+            .filter(|&i| lines[i].namespace.as_deref() == Some(&*namespace))
+            .filter(|&i| !lines[i].once && !flags[i].in_using())
+            .min_by_key(|&i| (lines[i].priority, Reverse(lines[i].lru)))
+            .map(CacheSlot::Evict)
+    }
+
+    /// Find the `CacheLine` to put the `Cacheable` with `type_id` into among the first `active` ones,
+    /// which is the reserved one if any, see [`Cache::reserve()`].
+    fn free_slot(&self, type_id: usize, active: usize) -> Option<CacheSlot> {
+        let lines = unsafe { self.lines() };
+        let reserved = lines
+            .iter()
+            .position(|l| l.reserved.is_some_and(|(t, _)| t == type_id));
+        if let Some(i) = reserved {
+            return Some(match lines[i].type_id {
+                t if t == type_id => CacheSlot::Hit(i),
+                0 => CacheSlot::Empty(i),
+                _ => CacheSlot::Evict(i),
+            });
+        }
+        let frequency = self.frequency();
+        find_slot_of(
+            type_id,
+            lines
+                .iter()
+                .take(active)
+                .map(|l| l.slot_key(type_id, frequency.as_deref())),
+        )
+    }
+
+    /// Dedicate a `CacheLine` to T, see [`Cache::reserve()`].
+    fn reserve<T: CacheableExt>(&self, ctx: &Context) -> CacheResult<()> {
+        let _lock = self.lock_group()?;
+        let lines = unsafe { self.lines() };
+        let type_id = T::type_id_usize();
+        if lines
+            .iter()
+            .any(|l| l.reserved.is_some_and(|(t, _)| t == type_id))
+        {
+            return Ok(());
+        }
+        let active = ctx.active_lines.load(Ordering::Relaxed);
+        let colliding = lines
+            .iter()
+            .take(active)
+            .filter_map(|l| l.reserved.map(|(_, type_name)| type_name))
+            .collect::<Vec<_>>();
+        // at least one `CacheLine` is left for the others
+        if colliding.len() + 1 >= active {
+            return Err(CacheError::Collision {
+                type_name: std::any::type_name::<T>(),
+                colliding,
+            });
+        }
+        let i = match self.active_slot::<T>(active)? {
+            Some(CacheSlot::Hit(i)) | Some(CacheSlot::Empty(i)) => i,
+            Some(CacheSlot::Evict(i)) => {
+                self.evict(i, ctx)?;
+                i
+            }
+            None => unreachable!(),
+        };
+        let mut lines = unsafe { self.lines() };
+        lines[i].reserved = Some((type_id, std::any::type_name::<T>()));
+        Ok(())
+    }
+
+    /// Empty the clean `CacheLine`s not being used, return the number of them.
+    fn purge_clean(&self, ctx: &Context) -> CacheResult<usize> {
+        let _lock = self.lock_group()?;
+        let mut lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let mut purged = 0;
+        for (line, flag) in lines.iter_mut().zip(flags.iter()) {
+            if line.type_id == 0 || line.once || flag.is_dirty() || flag.in_using() {
+                continue;
+            }
+            ctx.evicted(EvictionEvent {
+                type_name: line.type_name,
+                dirty: false,
+                stored: false,
+                buffered: false,
+            });
+            line.clear(ctx);
+            purged += 1;
+        }
+        Ok(purged)
+    }
+
+    /// Evict the `CacheLine`s not being used as `level` requires, see [`Cache::on_memory_pressure()`].
+    /// Return the number of evicted ones, push the failures with the type names into `errors`.
+    fn relieve(
+        &self,
+        ctx: &Context,
+        level: MemoryPressure,
+        errors: &mut Vec<(String, std::io::Error)>,
+    ) -> usize {
+        let _lock = lock_anyway(&self.lock);
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let cold = ctx.active_lines.load(Ordering::Relaxed) / 2;
+        let victims = (0..L)
+            .filter(|&i| lines[i].type_id != 0 && !lines[i].once && !flags[i].in_using())
+            .filter(|&i| match level {
+                MemoryPressure::Low => !flags[i].is_dirty() && lines[i].lru >= cold,
+                MemoryPressure::Moderate => !flags[i].is_dirty(),
+                MemoryPressure::Critical => true,
+            })
+            .map(|i| (i, lines[i].type_name))
+            .collect::<Vec<_>>();
+        self.evict_all(ctx, victims, errors)
+    }
+
+    /// Evict the `CacheLine`s failing `f` and not being used, see [`Cache::retain()`].
+    /// Return the number of evicted ones, push the failures with the type names into `errors`.
+    fn retain(
+        &self,
+        g: usize,
+        ctx: &Context,
+        f: &mut impl FnMut(&EntryInfo) -> bool,
+        errors: &mut Vec<(String, std::io::Error)>,
+    ) -> usize {
+        let _lock = lock_anyway(&self.lock);
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let victims = (0..L)
+            .filter(|&i| lines[i].type_id != 0 && !lines[i].once && !flags[i].in_using())
+            .filter(|&i| !f(&lines[i].info(g, i, &flags[i], ctx)))
+            .map(|i| (i, lines[i].type_name))
+            .collect::<Vec<_>>();
+        self.evict_all(ctx, victims, errors)
+    }
+
+    /// Evict the `CacheLine`s with the group lock held, return the number of evicted ones,
+    /// push the failures with the type names into `errors`.
+    fn evict_all(
+        &self,
+        ctx: &Context,
+        victims: Vec<(usize, &'static str)>,
+        errors: &mut Vec<(String, std::io::Error)>,
+    ) -> usize {
+        let mut evicted = 0;
+        for (i, type_name) in victims {
+            match self.evict(i, ctx) {
+                Ok(()) => evicted += 1,
+                Err(CacheError::Io(e)) => errors.push((type_name.to_string(), e)),
+                Err(e) => errors.push((type_name.to_string(), std::io::Error::other(e))),
+            }
+        }
+        evicted
+    }
+
+    /// Evict the `CacheLine`s beyond the first `active` ones, see [`Cache::resize_lines()`].
+    fn shrink(&self, ctx: &Context, active: usize, errors: &mut Vec<(String, std::io::Error)>) {
+        let _lock = lock_anyway(&self.lock);
+        let lines = unsafe { self.lines() };
+        let occupied = lines
+            .iter()
+            .enumerate()
+            .skip(active)
+            .filter(|(_, l)| l.type_id != 0)
+            .map(|(i, l)| (i, l.type_name))
+            .collect::<Vec<_>>();
+        for (i, type_name) in occupied {
+            match self.evict(i, ctx) {
+                Ok(()) => {}
+                Err(CacheError::Busy) => errors.push((
+                    type_name.to_string(),
+                    std::io::Error::new(std::io::ErrorKind::WouldBlock, CacheError::Busy),
+                )),
+                Err(CacheError::Io(e)) => errors.push((type_name.to_string(), e)),
+                Err(e) => errors.push((type_name.to_string(), std::io::Error::other(e))),
+            }
+        }
+    }
+
+    /// Retrieve a Cacheable from the cache.
+    /// At most 63 CacheRefs for each Cacheable type can be retrieved at the same time
+    fn retrieve<'a, T: CacheableExt + Default>(
+        &'a self,
+        ctx: &'a Context,
+    ) -> CacheResult<CacheRef<'a, T>> {
+        self.retrieve_with(ctx, || ctx.load_on_miss::<T>(), false)
+    }
+
+    /// Retrieve a Cacheable from the cache, using `init` instead of loading it if missing,
+    /// which is marked dirty if `dirty`.
+    fn retrieve_with<'a, T: CacheableExt>(
+        &'a self,
+        ctx: &'a Context,
+        init: impl FnOnce() -> CacheResult<T>,
+        dirty: bool,
+    ) -> CacheResult<CacheRef<'a, T>> {
+        let acquired = self.acquire::<T>(ctx, init)?;
+        let i = match acquired {
+            Acquired::Line { i, filled, .. } => {
+                if filled && dirty {
+                    let flags = unsafe { &*self.flags.get() };
+                    ctx.set_dirty(&flags[i]);
+                }
+                i
+            }
+            Acquired::Bypass(value) => {
+                if dirty {
+                    ctx.write_back(&value, None)?;
+                }
+                let inner = Held::Bypass(crate::namespace::tag(Box::new(value)));
+                return CacheRef::new(inner, None);
+            }
+        };
+        self.read_line(ctx, i)
+    }
+
+    /// Retrieve T if cached, without loading it.
+    fn retrieve_cached<'a, T: CacheableExt>(
+        &'a self,
+        ctx: &'a Context,
+    ) -> CacheResult<Option<CacheRef<'a, T>>> {
+        let _lock = self.lock_group()?;
+        if ctx.home(T::type_id_usize()) != self.index {
+            return Err(CacheError::Missing);
+        }
+        // admitted, so that the doorkeeper does not count this as a miss
+        match self.load::<T>(ctx, &mut None, &mut Some(true), &mut None)? {
+            Some(i) => self.read_line(ctx, i).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the i-th `CacheLine` holding T, with the group locked.
+    fn read_line<'a, T: CacheableExt>(
+        &'a self,
+        ctx: &'a Context,
+        i: usize,
+    ) -> CacheResult<CacheRef<'a, T>> {
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        if let Err(e) = flags[i].read() {
+            return match lines[i].snapshot.clone() {
+                Some(snapshot) => CacheRef::new(Held::Snapshot(snapshot), None),
+                None => Err(e),
+            };
+        }
+        let inner = lines.get(i).inner.as_deref().unwrap();
+        let flag = &flags[i];
+        let watched = ctx
+            .watchdog
+            .watch(std::any::type_name::<T>(), &ctx.config, false);
+        CacheRef::new(Held::Line(inner, flag), watched)
+    }
+
+    /// Retrieve a `Cacheable` registered at runtime, see [`Cache::register_dyn()`].
+    fn retrieve_loaded<'a>(
+        &'a self,
+        ctx: &'a Context,
+        type_name: &'static str,
+        loader: &Loader,
+    ) -> CacheResult<DynCacheRef<'a>> {
+        let (_lock, i) = self.acquire_loaded(ctx, type_name, loader)?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        flags[i].read()?;
+        Ok(DynCacheRef {
+            inner: Held::Line(lines.get(i).inner.as_deref().unwrap(), &flags[i]),
+            _watched: ctx.watchdog.watch(type_name, &ctx.config, false),
+        })
+    }
+
+    /// Retrieve a `Cacheable` registered at runtime mutably, see [`Cache::register_dyn()`].
+    fn retrieve_loaded_mut<'a>(
+        &'a self,
+        ctx: &'a Context,
+        type_name: &'static str,
+        loader: &Loader,
+    ) -> CacheResult<DynCacheMut<'a>> {
+        let (_lock, i) = self.acquire_loaded(ctx, type_name, loader)?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        flags[i].write()?;
+        let snapshot_of = lines[i].snapshot_of;
+        Ok(DynCacheMut {
+            inner: HeldMut::Line(
+                lines.get_mut(i).inner.as_deref_mut().unwrap(),
+                &flags[i],
+                false,
+                snapshot_of,
+                (self, i),
+            ),
+            ctx,
+            _watched: ctx.watchdog.watch(type_name, &ctx.config, true),
+        })
+    }
+
+    /// Like `acquire`, for a `Cacheable` registered at runtime, returning the locked `CacheGroup`
+    /// and the index of the `CacheLine` holding it.
+    /// Unlike `acquire`, the doorkeeper, negative caching, background reloads and snapshot reads do not apply.
+    fn acquire_loaded<'a>(
+        &'a self,
+        ctx: &'a Context,
+        type_name: &'static str,
+        loader: &Loader,
+    ) -> CacheResult<(MutexGuard<'a, ()>, usize)> {
+        let type_id = type_id_of(loader.type_id);
+        let mut flight = None;
+        let mut loaded = None;
+        loop {
+            let lock = self.lock_group()?;
+            if ctx.home(type_id) != self.index {
+                return Err(CacheError::Missing);
+            }
+            let lines = unsafe { self.lines() };
+            let active = ctx.active_lines.load(Ordering::Relaxed);
+            let slot = match lines.iter().position(|l| l.type_id == type_id) {
+                // filled by another `Cacheable`, whose truncated type id is the same
+                Some(i) if !lines[i].registered_by(loader) => {
+                    return Err(CacheError::TypeMismatch {
+                        expected: type_name,
+                        found: lines[i].type_name,
+                    });
+                }
+                Some(i) => Some(CacheSlot::Hit(i)),
+                None => self.free_slot(type_id, active),
+            };
+            let (i, lru) = match slot {
+                Some(CacheSlot::Hit(i)) | Some(CacheSlot::Evict(i)) => (i, lines[i].lru),
+                Some(CacheSlot::Empty(i)) => (i, usize::MAX),
+                None => unreachable!(),
+            };
+            let hit = lines[i].type_id == type_id
+                && lines[i].generation == ctx.generation.load(Ordering::Relaxed)
+                && !lines[i].is_expired(ctx);
+            if !hit {
+                let Some(value) = loaded.take() else {
+                    drop(lock);
+                    if flight.is_none() {
+                        flight = ctx.flights.begin_of(loader.type_id)?;
+                        if flight.is_none() {
+                            // loaded by another thread, try again
+                            continue;
+                        }
+                    }
+                    ctx.settle(type_id)?;
+                    let permit = ctx
+                        .loads
+                        .permit(ctx.config.max_loads, ctx.config.load_timeout)
+                        .map_err(|_| CacheError::Timeout { type_name })?;
+                    let value = (loader.load)()
+                        .inspect_err(|e| debug!("failed to load {type_name}: {e}"))?;
+                    drop(permit);
+                    loaded = Some(DynEntry {
+                        value,
+                        type_name,
+                        store: loader.store.clone(),
+                    });
+                    continue;
+                };
+                self.evict_for(i, ctx, type_id, type_name)?;
+                let mut lines = unsafe { self.lines() };
+                lines[i].fill_loaded(ctx, type_id, value);
+                self.stats().miss(type_id, type_name);
+                self.adapt(ctx, type_id, false);
+            } else {
+                self.hit(i, ctx);
+            }
+            let mut lines = unsafe { self.lines() };
+            age(lines.iter_mut().map(|l| &mut l.lru), lru);
+            lines[i].lru = 0;
+            lines[i].accessed_at = Some(SystemTime::now());
+            return Ok((lock, i));
+        }
+    }
+
+    /// Retrieve a mut Cacheable from the cache.
+    fn retrieve_mut<'a, T: CacheableExt + Default>(
+        &'a self,
+        ctx: &'a Context,
+    ) -> CacheResult<CacheMut<'a, T>> {
+        self.retrieve_mut_with(ctx, || ctx.load_on_miss::<T>())
+            .map(|(cache_mut, _)| cache_mut)
+    }
+
+    /// Retrieve a mut Cacheable from the cache, using `init` instead of loading it if missing.
+    /// Return whether `init` was used as well.
+    fn retrieve_mut_with<'a, T: CacheableExt>(
+        &'a self,
+        ctx: &'a Context,
+        init: impl FnOnce() -> CacheResult<T>,
+    ) -> CacheResult<(CacheMut<'a, T>, bool)> {
+        let acquired = self.acquire::<T>(ctx, init)?;
+        let (i, filled) = match acquired {
+            Acquired::Line { i, filled, .. } => (i, filled),
+            Acquired::Bypass(value) => {
+                let inner = HeldMut::Bypass(crate::namespace::tag(Box::new(value)), false);
+                return Ok((CacheMut::new(inner, ctx, None)?, true));
+            }
+        };
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        flags[i].write()?;
+        let snapshot_of = lines[i].snapshot_of;
+        let inner = lines.get_mut(i).inner.as_deref_mut().unwrap();
+        let flag = &flags[i];
+        let watched = ctx
+            .watchdog
+            .watch(std::any::type_name::<T>(), &ctx.config, true);
+        let cache_mut = CacheMut::new(
+            HeldMut::Line(inner, flag, false, snapshot_of, (self, i)),
+            ctx,
+            watched,
+        )?;
+        Ok((cache_mut, filled))
+    }
+
+    /// Visit the cached `Cacheable`s which are not being written, see [`Cache::for_each()`].
+    fn for_each(
+        &self,
+        g: usize,
+        ctx: &Context,
+        f: &mut impl FnMut(EntryInfo, &dyn Any),
+    ) -> CacheResult<()> {
+        let _lock = self.lock_group()?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let generation = ctx.generation.load(Ordering::Relaxed);
+        for (i, line) in lines.iter().enumerate() {
+            let Some(inner) = line.inner.as_deref() else {
+                continue;
+            };
+            if flags[i].is_writing() || line.generation != generation {
+                continue;
+            }
+            f(line.info(g, i, &flags[i], ctx), inner.as_any());
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn inspect(
+        &self,
+        g: usize,
+        ctx: &Context,
+        f: &mut impl FnMut(EntryInfo, LineFlags, Option<&DynValue>),
+    ) -> CacheResult<()> {
+        let _lock = self.lock_group()?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let generation = ctx.generation.load(Ordering::Relaxed);
+        for (i, line) in lines.iter().enumerate() {
+            if line.type_id == 0 {
+                continue;
+            }
+            let writing = flags[i].is_writing();
+            let line_flags = LineFlags {
+                writing,
+                in_use: flags[i].in_using(),
+                stale: line.generation != generation,
+                once: line.once,
+                hot: line.hot,
+            };
+            // the value being written is not read
+            let value = line.inner.as_deref().filter(|_| !writing);
+            f(line.info(g, i, &flags[i], ctx), line_flags, value);
+        }
+        Ok(())
+    }
+
+    /// The index, LRU and whether dirty of the `CacheLine`s which can be evicted to free memory.
+    /// Only the ones of `namespace` if given.
+    fn unused_lines(&self, namespace: Option<&str>) -> CacheResult<Vec<(usize, usize, bool)>> {
+        let _lock = self.lock_group()?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        Ok((0..L)
+            .filter(|&i| lines[i].type_id != 0 && !lines[i].once && !flags[i].in_using())
+            .filter(|&i| namespace.is_none() || lines[i].namespace.as_deref() == namespace)
+            .map(|i| (i, lines[i].lru, flags[i].is_dirty()))
+            .collect())
+    }
+
+    /// Evict the i-th `CacheLine` to free memory, if it is still occupied and not being used.
+    fn evict_unused(&self, i: usize, ctx: &Context) -> CacheResult<()> {
+        let _lock = self.lock_group()?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        if lines[i].type_id == 0 || lines[i].once || flags[i].in_using() {
+            return Ok(());
+        }
+        self.evict(i, ctx)
+    }
+
+    /// Evict a `CacheLine` not being used if no active one is empty,
+    /// in the same order as [`find_slot`] with the `CacheLine`s described by `slot_key`.
+    /// The ones reserved by [`Cache::reserve()`] are kept.
+    #[cfg(not(loom))]
+    fn sweep(&self, ctx: &Context) -> CacheResult<()> {
+        let _lock = self.lock_group()?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        let active = 0..ctx.active_lines.load(Ordering::Relaxed);
+        if active.clone().any(|i| lines[i].type_id == 0) {
+            return Ok(());
+        }
+        let frequency = self.frequency();
+        let victim = active
+            .filter(|&i| lines[i].reserved.is_none() && !lines[i].once && !flags[i].in_using())
+            .min_by_key(|&i| {
+                let (_, key, lru) = lines[i].slot_key(0, frequency.as_deref());
+                (key, Reverse(lru))
+            });
+        drop(frequency);
+        match victim {
+            Some(i) => self.evict(i, ctx),
+            None => Ok(()),
+        }
+    }
+
+    /// The index and LRU of the dirty `CacheLine`s which are not being written.
+    fn dirty_lines(&self) -> CacheResult<Vec<(usize, usize)>> {
+        let _lock = self.lock_group()?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        Ok(flags
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.is_dirty() && !f.is_writing())
+            .map(|(i, _)| (i, lines[i].lru))
+            .collect())
+    }
+
+    /// Write back the dirty `CacheLine`s holding the `Cacheable`s whose type id satisfies `f`.
+    fn flush_where(
+        &self,
+        ctx: &Context,
+        errors: &mut Vec<(String, std::io::Error)>,
+        f: impl Fn(usize) -> bool,
+    ) {
+        let _lock = lock_anyway(&self.lock);
+        let mut lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        for (line, flag) in lines.iter_mut().zip(flags.iter()) {
+            if !flag.is_dirty() || !f(line.type_id) {
+                continue;
+            }
+            if flag.is_writing() {
+                errors.push((
+                    line.type_name.to_string(),
+                    std::io::Error::new(std::io::ErrorKind::WouldBlock, CacheError::Locked),
+                ));
+                continue;
+            }
+            match ctx.store(line.inner.as_deref().unwrap(), line.baseline()) {
+                Ok(()) => {
+                    ctx.set_clean(flag);
+                    line.commit(ctx);
+                }
+                Err(e) => errors.push((line.type_name.to_string(), e)),
+            }
+        }
+    }
+
+    /// Write back the `CacheLine` if it is dirty and not being written.
+    fn write_back(&self, i: usize, ctx: &Context) -> CacheResult<()> {
+        let _lock = self.lock_group()?;
+        let mut lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        if flags[i].is_dirty() && !flags[i].is_writing() {
+            ctx.write_back(lines[i].inner.as_deref().unwrap(), lines[i].baseline())?;
+            ctx.set_clean(&flags[i]);
+            lines[i].commit(ctx);
+        }
+        Ok(())
+    }
+}
+
+/// The `CacheGroup` of the `CacheLine` held by a [`CacheMut`], see [`CacheMut::commit()`] and [`CacheMut::rollback()`].
+trait CommitLine: Sync {
+    /// Write back the i-th `CacheLine` holding `value` being written if it is dirty, as `write_back` does.
+    fn commit(&self, i: usize, ctx: &Context, value: &DynValue) -> std::io::Result<()>;
+
+    /// The copy of the value last loaded or written back of the i-th `CacheLine`, if kept.
+    fn committed(&self, i: usize) -> Option<std::sync::Arc<DynValue>>;
+
+    /// Mark the i-th `CacheLine` clean, whose `value` being written is restored, and commit it.
+    fn reset(&self, i: usize, ctx: &Context, value: &DynValue);
+}
+
+impl<const L: usize> CommitLine for CacheGroup<L> {
+    fn commit(&self, i: usize, ctx: &Context, value: &DynValue) -> std::io::Result<()> {
+        let _lock = self.lock_group().map_err(std::io::Error::other)?;
+        let lines = unsafe { self.lines() };
+        let flags = unsafe { &*self.flags.get() };
+        if flags[i].is_dirty() {
+            ctx.write_back(value, lines[i].baseline())?;
+            ctx.set_clean(&flags[i]);
+            lines.get_mut(i).commit_held(ctx, Some(value));
+        }
+        Ok(())
+    }
+
+    fn committed(&self, i: usize) -> Option<std::sync::Arc<DynValue>> {
+        let _lock = lock_anyway(&self.lock);
+        let line = unsafe { self.lines() }.get(i);
+        line.snapshot.clone().or_else(|| line.baseline.clone())
+    }
+
+    fn reset(&self, i: usize, ctx: &Context, value: &DynValue) {
+        let _lock = lock_anyway(&self.lock);
+        let flags = unsafe { &*self.flags.get() };
+        ctx.set_clean(&flags[i]);
+        unsafe { self.lines() }
+            .get_mut(i)
+            .commit_held(ctx, Some(value));
+    }
+}
+
+/// The information of a cached `Cacheable`, see [`Cache::for_each()`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct EntryInfo {
+    /// The type name of the `Cacheable`.
+    pub type_name: &'static str,
+    /// The index of the `CacheGroup`.
+    pub group: usize,
+    /// The index of the `CacheLine` in the `CacheGroup`.
+    pub line: usize,
+    /// The LRU counter, 0 for the most recently used in the `CacheGroup`.
+    pub lru: usize,
+    /// The eviction priority, see [`Cacheable::PRIORITY`].
+    pub priority: u8,
+    /// Whether modified and not written back yet.
+    pub dirty: bool,
+    /// When last accessed by `get` or `get_mut`.
+    pub accessed_at: Option<SystemTime>,
+    /// When became dirty, None if clean.
+    pub dirtied_at: Option<SystemTime>,
+    /// The time to live since loaded, with [`CacheBuilder::ttl_jitter()`] applied. None if never expires.
+    pub ttl: Option<Duration>,
+    /// The time since loaded.
+    pub age: Option<Duration>,
+    /// The weight when loaded or last written back, see [`Cacheable::weight()`].
+    pub weight: usize,
+    /// The namespace, None if not accessed through [`Cache::scope()`].
+    pub namespace: Option<std::sync::Arc<str>>,
+}
+
+/// The state of a `CacheLine` besides its [`EntryInfo`], see `Cache::inspect()`.
+#[cfg(feature = "serde_json")]
+pub(crate) struct LineFlags {
+    /// Held by a `CacheMut`.
+    pub(crate) writing: bool,
+    /// Held by a `CacheRef` or a `CacheMut`.
+    pub(crate) in_use: bool,
+    /// Invalidated by [`Cache::bump_generation()`].
+    pub(crate) stale: bool,
+    /// Loaded at most once, see [`Cache::get_or_init()`].
+    pub(crate) once: bool,
+    /// Hit since loaded, see [`CacheBuilder::hot_tier()`].
+    pub(crate) hot: bool,
+}
+
+/// An eviction from the cache, see [`Cache::evictions()`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct EvictionEvent {
+    /// The type name of the evicted `Cacheable`.
+    pub type_name: &'static str,
+    /// Whether it was dirty, and had to be written back.
+    pub dirty: bool,
+    /// Whether written back successfully, false if clean.
+    /// A dirty `Cacheable` failed to be written back is kept in the cache, and evicted again later.
+    pub stored: bool,
+    /// Whether handed to the write buffer to be written back in background, see [`CacheBuilder::write_buffer()`].
+    pub buffered: bool,
+}
+
+/// A `CacheRef` or `CacheMut` held longer than [`CacheBuilder::watchdog()`], see [`Cache::long_held()`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct LongHeldEvent {
+    /// The type name of the `Cacheable` held.
+    pub type_name: &'static str,
+    /// Whether held by a `CacheMut`.
+    pub mutable: bool,
+    /// How long it has been held so far.
+    pub held: Duration,
+    /// Where it was retrieved, captured in debug builds if enabled by `RUST_BACKTRACE`.
+    pub backtrace: Option<String>,
+}
+
+/// The `CacheRef`s and `CacheMut`s holding `CacheLine`s, see [`CacheBuilder::watchdog()`].
+#[derive(Default)]
+struct Watchdog(std::sync::Mutex<Guards>);
+
+#[derive(Default)]
+struct Guards {
+    next: u64,
+    held: HashMap<u64, Guard>,
+    subscribers: Vec<Sender<LongHeldEvent>>,
+}
+
+struct Guard {
+    type_name: &'static str,
+    mutable: bool,
+    since: Instant,
+    backtrace: Option<std::backtrace::Backtrace>,
+    reported: bool,
+}
+
+impl std::fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Watchdog")
+            .field(&self.lock().held.len())
+            .finish()
+    }
+}
+
+impl Watchdog {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Guards> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record the guard of `type_name` just retrieved if the watchdog is enabled, until the returned token dropped.
+    fn watch(
+        &self,
+        type_name: &'static str,
+        config: &Config,
+        mutable: bool,
+    ) -> Option<Watched<'_>> {
+        config.watchdog?;
+        let backtrace = cfg!(debug_assertions)
+            .then(std::backtrace::Backtrace::capture)
+            .filter(|b| b.status() == std::backtrace::BacktraceStatus::Captured);
+        let mut guards = self.lock();
+        let id = guards.next;
+        guards.next += 1;
+        guards.held.insert(
+            id,
+            Guard {
+                type_name,
+                mutable,
+                since: Instant::now(),
+                backtrace,
+                reported: false,
+            },
+        );
+        Some(Watched { watchdog: self, id })
+    }
+
+    /// Report the guards held longer than the threshold, each once.
+    #[cfg_attr(loom, allow(dead_code))]
+    fn check(&self, config: &Config) {
+        let Some(threshold) = config.watchdog else {
+            return;
+        };
+        let mut guards = self.lock();
+        let Guards {
+            held, subscribers, ..
+        } = &mut *guards;
+        for guard in held.values_mut() {
+            let elapsed = guard.since.elapsed();
+            if guard.reported || elapsed < threshold {
+                continue;
+            }
+            guard.reported = true;
+            warn!(
+                "{} has been held by a {} for {elapsed:?}",
+                guard.type_name,
+                if guard.mutable {
+                    "CacheMut"
+                } else {
+                    "CacheRef"
+                }
+            );
+            let event = LongHeldEvent {
+                type_name: guard.type_name,
+                mutable: guard.mutable,
+                held: elapsed,
+                backtrace: guard.backtrace.as_ref().map(|b| b.to_string()),
+            };
+            subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        }
+    }
+}
+
+/// Forget the guard when dropped, see [`Watchdog::watch()`].
+struct Watched<'a> {
+    watchdog: &'a Watchdog,
+    id: u64,
+}
+
+impl Drop for Watched<'_> {
+    fn drop(&mut self) {
+        self.watchdog.lock().held.remove(&self.id);
+    }
+}
+
+/// The undo and redo stacks of each `Cacheable`, see [`CacheBuilder::history()`].
+#[derive(Default)]
+struct History(std::sync::Mutex<HashMap<usize, UndoRedo>>);
+
+/// The values to undo, the oldest first, and the ones to redo, the latest last.
+type UndoRedo = (VecDeque<Box<DynValue>>, Vec<Box<DynValue>>);
+
+impl std::fmt::Debug for History {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("History").field(&self.lock().len()).finish()
+    }
+}
+
+impl History {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<usize, UndoRedo>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record the value before modified, forgetting the redo stack and the oldest value beyond the depth.
+    fn record(&self, config: &Config, value: &DynValue, snapshot_of: SnapshotFn) {
+        let Some(depth) = config.history.filter(|&depth| depth > 0) else {
+            return;
+        };
+        let Some(copy) = snapshot_of(value) else {
+            return;
+        };
+        let mut history = self.lock();
+        let (undo, redo) = history
+            .entry(crate::namespace::id_of(
+                value,
+                type_id_of(value.as_any().type_id()),
+            ))
+            .or_default();
+        redo.clear();
+        undo.push_back(copy);
+        if undo.len() > depth {
+            undo.pop_front();
+        }
+    }
+
+    /// Swap the current T with the last one to undo (or redo), pushing it to redo (or undo).
+    /// Return false if nothing to undo (or redo).
+    fn swap<T: Any>(&self, current: &mut T, undo: bool) -> bool {
+        let mut history = self.lock();
+        let Some((undos, redos)) = history.get_mut(&type_id_usize::<T>()) else {
+            return false;
+        };
+        let last = match undo {
+            true => undos.back_mut(),
+            false => redos.last_mut(),
+        };
+        // recorded for another `Cacheable`, whose truncated type id is the same
+        let Some(recorded) = last.and_then(|last| last.as_any_mut().downcast_mut::<T>()) else {
+            return false;
+        };
+        std::mem::swap(current, recorded);
+        match undo {
+            true => redos.extend(undos.pop_back()),
+            false => undos.extend(redos.pop()),
+        }
+        true
+    }
+}
+
+/// The statistics of a `CacheGroup` since created, see [`Cache::tuning_report()`].
+#[derive(Debug, Default)]
+struct GroupStats {
+    hits: usize,
+    misses: usize,
+    conflicts: usize,
+    /// The sum of the LRU positions of the hits, i.e. the reuse distances.
+    reuse: usize,
+    /// The type names of the `Cacheable`s ever loaded into the group.
+    seen: HashMap<usize, &'static str>,
+    /// The accesses of each `Cacheable`, halved every window, see [`CacheBuilder::adaptive_policy()`].
+    accesses: HashMap<usize, usize>,
+    /// The hits and accesses in the current window.
+    window: (usize, usize),
+    /// The hit ratio of the last window under LRU and LFU.
+    ratios: [Option<f64>; 2],
+    /// The hit ratio of the first window since switched to the current policy.
+    baseline: Option<f64>,
+    /// The number of consecutive windows the current policy underperformed.
+    behind: usize,
+}
+
+impl GroupStats {
+    fn hit(&mut self, lru: usize) {
+        self.hits += 1;
+        self.reuse += lru;
+    }
+
+    fn miss(&mut self, type_id: usize, type_name: &'static str) {
+        self.misses += 1;
+        self.seen.insert(type_id, type_name);
+    }
+
+    /// The recent accesses of the `Cacheable` with `type_id`, which age out over windows.
+    fn frequency(&self, type_id: usize) -> usize {
+        self.accesses.get(&type_id).copied().unwrap_or_default()
+    }
+
+    /// Count an access under the current policy (LFU if `lfu`) and return whether to switch,
+    /// i.e. the other policy is not tried yet, or the current one underperformed for 2 windows in a row:
+    /// worse than the other one in its last window, or than itself in its first window since switched,
+    /// which means the workload changed.
+    fn tick(&mut self, type_id: usize, hit: bool, window: usize, lfu: bool) -> bool {
+        *self.accesses.entry(type_id).or_default() += 1;
+        self.window.0 += hit as usize;
+        self.window.1 += 1;
+        if self.window.1 < window {
+            return false;
+        }
+        self.accesses.retain(|_, n| {
+            *n /= 2;
+            *n > 0
+        });
+        let ratio = self.window.0 as f64 / self.window.1 as f64;
+        self.window = (0, 0);
+        self.ratios[lfu as usize] = Some(ratio);
+        let baseline = *self.baseline.get_or_insert(ratio);
+        match self.ratios[!lfu as usize] {
+            Some(other) if ratio < other || ratio < baseline => self.behind += 1,
+            Some(_) => self.behind = 0,
+            None => self.behind = 2,
+        }
+        if self.behind < 2 {
+            return false;
+        }
+        self.behind = 0;
+        self.baseline = None;
+        true
+    }
+}
+
+/// Statistics of the cache with the recommended `G` and `L`, see [`Cache::tuning_report()`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct TuningReport {
+    /// The report of each `CacheGroup`.
+    pub groups: Vec<GroupReport>,
+    /// The recommended `(G, L)`.
+    pub recommended: (usize, usize),
+    /// The `CacheGroup`s with more `Cacheable`s competing than `L`, with their type names.
+    /// Consider [`Cache::migrate()`] or [`Cacheable::GROUP_HINT`] to put them apart.
+    pub collisions: Vec<(usize, Vec<&'static str>)>,
+}
+
+/// Statistics of a `CacheGroup` since the cache created, see [`TuningReport`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct GroupReport {
+    /// The number of cache hits.
+    pub hits: usize,
+    /// The number of cache misses, i.e. loads into the group.
+    pub misses: usize,
+    /// The number of evictions of a `Cacheable` to put another one in, i.e. conflict misses.
+    pub conflicts: usize,
+    /// The number of occupied `CacheLine`s now.
+    pub occupied: usize,
+    /// The mean LRU position of the hits, 0 for the most recently used.
+    pub mean_reuse_distance: f64,
+    /// The type names of the `Cacheable`s ever loaded into the group.
+    pub types: Vec<&'static str>,
+    /// Whether the group evicts the least frequently used `CacheLine` now,
+    /// see [`CacheBuilder::adaptive_policy()`].
+    pub lfu: bool,
+}
+
+/// Two `Cacheable`s evicting each other in turn, see [`Cache::thrashing()`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ThrashEvent {
+    /// The `CacheGroup` they are thrashing in.
+    pub group: usize,
+    /// The type names of the evicting `Cacheable` and the evicted one.
+    pub types: (&'static str, &'static str),
+    /// The number of evictions in turn so far, at least 2.
+    pub count: usize,
+}
+
+/// The recent evictions between `Cacheable`s, see [`Cache::thrashing()`].
+#[derive(Debug, Default)]
+struct ThrashDetector {
+    /// When the first type id displaced the second one last time, and the number of evictions in turn then.
+    last: HashMap<(usize, usize), (Instant, usize)>,
+    subscribers: Vec<Sender<ThrashEvent>>,
+}
+
+/// The severity of a memory pressure, see [`Cache::on_memory_pressure()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemoryPressure {
+    /// Drop the clean `Cacheable`s in the less recently used half of each `CacheGroup`, without any IO.
+    Low,
+    /// Drop every clean `Cacheable`, without any IO, see [`Cache::purge_clean()`].
+    Moderate,
+    /// Also write back and drop every dirty `Cacheable`, including the ones in the write buffer,
+    /// see [`CacheBuilder::write_buffer()`].
+    Critical,
+}
+
+/// What [`Cache::refresh()`] does if the cached `Cacheable` is dirty.
+pub enum RefreshPolicy<T> {
+    /// Discard the dirty one.
+    Overwrite,
+    /// Return `CacheError::Dirty`.
+    Error,
+    /// Merge the loaded one (the second argument) into the dirty one, which stays dirty.
+    Merge(MergeFn<T>),
+}
+
+/// Merge the second argument into the first one, see [`RefreshPolicy::Merge`].
+pub type MergeFn<T> = Box<dyn FnOnce(&mut T, T)>;
+
+impl<T> std::fmt::Debug for RefreshPolicy<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Overwrite => write!(f, "Overwrite"),
+            Self::Error => write!(f, "Error"),
+            Self::Merge(_) => write!(f, "Merge"),
+        }
+    }
+}
+
+/// Which one to keep when both caches hold the same type, see [`Cache::absorb()`].
+pub enum ConflictPolicy {
+    /// Keep the more recently accessed one, the incoming one if tied.
+    KeepNewest,
+    /// Keep the dirty one, the incoming one if both or neither are dirty.
+    PreferDirty,
+    /// Return true to keep the incoming one (the third argument) instead of the cached one (the second argument),
+    /// with the type name as the first argument.
+    Callback(ConflictFn),
+}
+
+/// Resolve a conflict, see [`ConflictPolicy::Callback`].
+pub type ConflictFn = Box<dyn FnMut(&str, &dyn Any, &dyn Any) -> bool>;
+
+impl std::fmt::Debug for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KeepNewest => write!(f, "KeepNewest"),
+            Self::PreferDirty => write!(f, "PreferDirty"),
+            Self::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
+/// The result of `CacheGroup::acquire()`.
+enum Acquired<'a, T> {
+    /// A locked `CacheGroup` with the `CacheLine` found.
+    Line {
+        _lock: MutexGuard<'a, ()>,
+        /// Dropped after the lock, so that the waiting threads find the loaded T.
+        _flight: Option<Flight<'a>>,
+        i: usize,
+        /// Whether T was just put into the `CacheLine`.
+        filled: bool,
+    },
+    /// T is not admitted by the doorkeeper, see [`CacheBuilder::doorkeeper()`].
+    Bypass(T),
+}
+
+/// A bloom filter over the recently missed `Cacheable`s, see [`CacheBuilder::doorkeeper()`].
+#[derive(Debug)]
+struct Doorkeeper {
+    bits: Vec<u64>,
+    /// The number of `Cacheable`s recorded since last cleared.
+    recorded: usize,
+    window: usize,
+}
+
+impl Doorkeeper {
+    /// The number of hash functions.
+    const K: usize = 3;
+
+    fn new(window: usize) -> Self {
+        Self {
+            bits: vec![0; (window.max(1) * 8).div_ceil(64)],
+            recorded: 0,
+            window: window.max(1),
+        }
+    }
+
+    /// Record the `Cacheable`, return whether it was already recorded.
+    /// The filter is cleared every `window` records.
+    fn admit(&mut self, type_id: usize) -> bool {
+        if self.recorded >= self.window {
+            self.bits.fill(0);
+            self.recorded = 0;
+        }
+        let m = self.bits.len() * 64;
+        let h2 = type_id.rotate_left(usize::BITS / 2) | 1;
+        let mut seen = true;
+        for k in 0..Self::K {
+            let bit = type_id.wrapping_add(k.wrapping_mul(h2)) % m;
+            let mask = 1 << (bit % 64);
+            if self.bits[bit / 64] & mask == 0 {
+                self.bits[bit / 64] |= mask;
+                seen = false;
+            }
+        }
+        if !seen {
+            self.recorded += 1;
+        }
+        seen
+    }
+}
+
+/// The `Cacheable`s being loaded, to deduplicate concurrent loadings.
+#[derive(Debug)]
+struct Flights {
+    loading: Mutex<HashSet<TypeId>>,
+    done: Condvar,
+    /// The tasks waiting asynchronously, see `Flights::landed()`.
+    wakers: std::sync::Mutex<Vec<Waker>>,
+}
+
+impl Default for Flights {
+    fn default() -> Self {
+        Self {
+            loading: Mutex::new(HashSet::new()),
+            done: Condvar::new(),
+            wakers: std::sync::Mutex::new(vec![]),
+        }
+    }
+}
+
+impl Flights {
+    /// Return a `Flight` if T is not being loaded,
+    /// otherwise wait until the loading finished and return None.
+    fn begin<T: Any>(&self) -> CacheResult<Option<Flight<'_>>> {
+        self.begin_of(TypeId::of::<T>())
+    }
+
+    /// Like `begin`, with the type erased.
+    fn begin_of(&self, type_id: TypeId) -> CacheResult<Option<Flight<'_>>> {
+        let mut loading = lock(&self.loading, "flights")?;
+        if loading.insert(type_id) {
+            return Ok(Some(Flight {
+                flights: self,
+                type_id,
+            }));
+        }
+        while loading.contains(&type_id) {
+            loading = wait(&self.done, loading, "flights")?;
+        }
+        Ok(None)
+    }
+
+    /// Return a `Flight` if T is not being loaded, without waiting.
+    fn try_begin<T: Any>(&self) -> CacheResult<Option<Flight<'_>>> {
+        let type_id = TypeId::of::<T>();
+        let mut loading = lock(&self.loading, "flights")?;
+        if !loading.insert(type_id) {
+            return Ok(None);
+        }
+        Ok(Some(Flight {
+            flights: self,
+            type_id,
+        }))
+    }
+
+    /// A future ready once T is no longer being loaded.
+    fn landed<T: Any>(&self) -> Landed<'_> {
+        Landed {
+            flights: self,
+            type_id: TypeId::of::<T>(),
+        }
+    }
+}
+
+/// See `Flights::landed()`.
+struct Landed<'a> {
+    flights: &'a Flights,
+    type_id: TypeId,
+}
+
+impl Future for Landed<'_> {
+    type Output = CacheResult<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        // registered under the lock, so that the `Flight` dropped next wakes it
+        let loading = lock(&self.flights.loading, "flights")?;
+        if !loading.contains(&self.type_id) {
+            return Poll::Ready(Ok(()));
+        }
+        let mut wakers = self
+            .flights
+            .wakers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// T is being loaded until this dropped.
+struct Flight<'a> {
+    flights: &'a Flights,
+    type_id: TypeId,
+}
+
+impl Drop for Flight<'_> {
+    fn drop(&mut self) {
+        let mut loading = lock_anyway(&self.flights.loading);
+        loading.remove(&self.type_id);
+        self.flights.done.notify_all();
+        drop(loading);
+        let wakers = std::mem::take(
+            &mut *self
+                .flights
+                .wakers
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()),
+        );
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum CacheSlot {
+    Hit(usize),
+    Empty(usize),
+    Evict(usize),
+}
+
+/// The shape of a cache, with G `CacheGroup`s of L `CacheLine`s.
+pub(crate) struct Shape<const G: usize, const L: usize>;
+
+impl<const G: usize, const L: usize> Shape<G, L> {
+    /// Fail to compile if G or L is 0, when evaluated.
+    pub(crate) const VALID: () = {
+        assert!(G > 0, "Invalid number of cache groups.");
+        assert!(L > 0, "Invalid number of cache lines.");
+    };
+}
+
+/// A lock is poisoned by a panic while holding it.
+#[cfg(any(loom, not(feature = "parking_lot")))]
+fn poisoned(lock: &str) -> CacheError {
+    warn!("the lock of {lock} is poisoned");
+    CacheError::Poisoned
+}
+
+/// Lock the `Mutex` of `name`, failing with `CacheError::Poisoned` if poisoned.
+#[cfg(any(loom, not(feature = "parking_lot")))]
+fn lock<'a, T>(mutex: &'a Mutex<T>, name: &str) -> CacheResult<MutexGuard<'a, T>> {
+    mutex.lock().map_err(|_| poisoned(name))
+}
+
+/// Lock the `Mutex`, even if poisoned.
+#[cfg(any(loom, not(feature = "parking_lot")))]
+fn lock_anyway<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Wait on the `Condvar` with the `Mutex` of `name` locked, failing with `CacheError::Poisoned` if poisoned.
+#[cfg(any(loom, not(feature = "parking_lot")))]
+fn wait<'a, T>(
+    condvar: &Condvar,
+    guard: MutexGuard<'a, T>,
+    name: &str,
+) -> CacheResult<MutexGuard<'a, T>> {
+    condvar.wait(guard).map_err(|_| poisoned(name))
+}
+
+/// `parking_lot` locks are never poisoned.
+#[cfg(all(not(loom), feature = "parking_lot"))]
+fn lock<'a, T>(mutex: &'a Mutex<T>, _name: &str) -> CacheResult<MutexGuard<'a, T>> {
+    Ok(mutex.lock())
+}
+
+#[cfg(all(not(loom), feature = "parking_lot"))]
+fn lock_anyway<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock()
+}
+
+#[cfg(all(not(loom), feature = "parking_lot"))]
+fn wait<'a, T>(
+    condvar: &Condvar,
+    mut guard: MutexGuard<'a, T>,
+    _name: &str,
+) -> CacheResult<MutexGuard<'a, T>> {
+    condvar.wait(&mut guard);
+    Ok(guard)
+}
+
+/// Find the `CacheLine` for T among lines described as `(type_id, priority, lru)`.
+/// The victim to evict is the one with the lowest priority, e.g. [`Cacheable::PRIORITY`],
+/// and the least recently used among them.
+pub(crate) fn find_slot<T: Any, P: Ord>(
+    lines: impl Iterator<Item = (usize, P, usize)> + Clone,
+) -> Option<CacheSlot> {
+    find_slot_of(type_id_usize::<T>(), lines)
+}
+
+/// Like [`find_slot`], with the type id erased.
+pub(crate) fn find_slot_of<P: Ord>(
+    type_id: usize,
+    lines: impl Iterator<Item = (usize, P, usize)> + Clone,
+) -> Option<CacheSlot> {
+    if let Some(i) = lines.clone().position(|(t, _, _)| t == type_id) {
+        return Some(CacheSlot::Hit(i));
+    }
+    if let Some(i) = lines.clone().position(|(t, _, _)| t == 0) {
+        return Some(CacheSlot::Empty(i));
+    }
+    lines
+        .enumerate()
+        .min_by(|(_, (_, p1, lru1)), (_, (_, p2, lru2))| {
+            (p1, Reverse(lru1)).cmp(&(p2, Reverse(lru2)))
+        })
+        .map(|(i, _)| CacheSlot::Evict(i))
+}
+
+/// Age the lines more recently used than `lru`, before a line with `lru` becomes the most recently used.
+/// `usize::MAX` for an empty line.
+pub(crate) fn age<'a>(lrus: impl Iterator<Item = &'a mut usize>, lru: usize) {
+    lrus.filter(|l| **l < lru).for_each(|l| *l += 1);
+}
+
+#[derive(Default)]
+struct CacheLine {
+    lru: usize,
+    type_id: usize,
+    type_name: &'static str,
+    /// The untruncated type id of the value, checked without touching the value being written by others.
+    exact: Option<TypeId>,
+    priority: u8,
+    /// The generation of the cache when loaded, see [`Cache::bump_generation()`].
+    generation: usize,
+    loaded_at: Option<Instant>,
+    /// See [`Cacheable::EXPIRE_AFTER`].
+    expire_after: Option<Duration>,
+    /// The version when loaded and how to read the current one, see [`Cacheable::version()`].
+    version: Option<(u64, VersionFn)>,
+    accessed_at: Option<SystemTime>,
+    /// The value being loaded in background, see [`CacheBuilder::stale_while_revalidate()`].
+    revalidating: Option<Receiver<std::io::Result<Box<DynValue>>>>,
+    /// Loaded at most once, see [`Cache::get_or_init()`].
+    once: bool,
+    /// Hit since loaded, see [`CacheBuilder::hot_tier()`].
+    hot: bool,
+    /// The type id and name of the `Cacheable` this `CacheLine` is dedicated to, see [`Cache::reserve()`].
+    reserved: Option<(usize, &'static str)>,
+    /// The committed value, see [`CacheBuilder::snapshot_reads()`].
+    snapshot: Option<std::sync::Arc<DynValue>>,
+    snapshot_of: Option<SnapshotFn>,
+    /// The value last loaded or written back, kept if [`Cacheable::STORE_DELTA`].
+    baseline: Option<std::sync::Arc<DynValue>>,
+    delta: bool,
+    /// The weight when loaded or last written back, see [`CacheBuilder::max_bytes()`].
+    weight: usize,
+    /// The boxed value reported to the [`AllocHook`](crate::AllocHook), see [`CacheBuilder::alloc_hook()`].
+    allocated: Option<(&'static str, std::alloc::Layout)>,
+    /// The namespace the committed value is accounted to, see [`CacheBuilder::scope_quota()`].
+    namespace: Option<std::sync::Arc<str>>,
+    inner: Option<Box<DynValue>>,
+}
+
+/// The `CacheLine`s of a locked `CacheGroup`, see [`CacheGroup::lines()`].
+///
+/// Every access borrows a single `CacheLine` from the raw pointer, only for the expression using it,
+/// so that no borrow outlives a nested call borrowing the same `CacheLine`s mutably,
+/// and no borrow of the whole array aliases a `CacheLine` borrowed elsewhere.
+/// The values in `CacheLine`s are boxed, so `CacheRef`s and `CacheMut`s never alias the `CacheLine`s themselves.
+#[derive(Clone, Copy)]
+struct Lines<'a, const L: usize> {
+    ptr: *mut [CacheLine; L],
+    _phantom: PhantomData<&'a CacheGroup<L>>,
+}
+
+impl<'a, const L: usize> Lines<'a, L> {
+    /// Borrow the i-th `CacheLine` for `'a`, e.g. to hand out its value,
+    /// which must not be borrowed mutably in the meantime.
+    fn get(self, i: usize) -> &'a CacheLine {
+        // SAFETY: the group is locked, see `CacheGroup::lines()`
+        unsafe { &(*self.ptr)[i] }
+    }
+
+    /// Borrow the i-th `CacheLine` mutably for `'a`.
+    #[allow(clippy::mut_from_ref)]
+    fn get_mut(self, i: usize) -> &'a mut CacheLine {
+        // SAFETY: the group is locked, see `CacheGroup::lines()`
+        unsafe { &mut (*self.ptr)[i] }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &CacheLine> + Clone + '_ {
+        (0..L).map(|i| self.get(i))
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut CacheLine> + '_ {
+        let this = *self;
+        (0..L).map(move |i| this.get_mut(i))
+    }
+}
+
+impl<const L: usize> std::ops::Index<usize> for Lines<'_, L> {
+    type Output = CacheLine;
+
+    fn index(&self, i: usize) -> &CacheLine {
+        self.get(i)
+    }
+}
+
+impl<const L: usize> std::ops::IndexMut<usize> for Lines<'_, L> {
+    fn index_mut(&mut self, i: usize) -> &mut CacheLine {
+        self.get_mut(i)
+    }
+}
+
+/// Copy the `Cacheable` by [`Cacheable::snapshot()`].
+type SnapshotFn = fn(&DynValue) -> Option<Box<DynValue>>;
+type VersionFn = fn() -> Option<u64>;
+
+impl CacheLine {
+    /// Describe this `CacheLine` for [`find_slot`] of the `Cacheable` with `type_id`.
+    /// Cold ones are evicted before hot ones of the same priority, and the ones reserved
+    /// for other `Cacheable`s (see [`Cache::reserve()`]) are never empty and evicted last.
+    /// Then the less frequently used ones are evicted first if `frequency` is given (see [`GroupStats::frequency`]),
+    /// and the least recently used ones at last.
+    fn slot_key(
+        &self,
+        type_id: usize,
+        frequency: Option<&GroupStats>,
+    ) -> (usize, (bool, u8, bool, usize), usize) {
+        let elsewhere = self.reserved.is_some_and(|(t, _)| t != type_id);
+        let t = match (elsewhere, self.type_id) {
+            (true, 0) => usize::MAX,
+            (_, t) => t,
+        };
+        let frequency = frequency.map_or(0, |stats| stats.frequency(self.type_id));
+        (t, (elsewhere, self.priority, self.hot, frequency), self.lru)
+    }
+
+    /// Fail with `CacheError::TypeMismatch` if this `CacheLine` holds another `Cacheable` than T,
+    /// or T of another namespace than the one accessed on this thread, whose truncated type id is the same.
+    fn holds<T: Any>(&self) -> CacheResult<()> {
+        let exact = self.exact.is_none_or(|exact| exact == TypeId::of::<T>());
+        match exact && crate::namespace::is_current(self.namespace.as_deref()) {
+            true => Ok(()),
+            false => Err(CacheError::TypeMismatch {
+                expected: std::any::type_name::<T>(),
+                found: self.type_name,
+            }),
+        }
+    }
+
+    /// Put T into this `CacheLine`.
+    fn fill<T: CacheableExt>(&mut self, ctx: &Context, value: T) {
+        self.inner = Some(crate::namespace::tag(Box::new(value)));
+        self.exact = Some(TypeId::of::<T>());
+        self.snapshot_of = Some(|value| {
+            let value = value.as_any().downcast_ref::<T>()?;
+            value
+                .snapshot()
+                .map(|snapshot| Box::new(snapshot) as Box<DynValue>)
+        });
+        self.delta = T::STORE_DELTA;
+        self.commit(ctx);
+        self.generation = ctx.generation.load(Ordering::Relaxed);
+        self.loaded_at = Some(Instant::now());
+        self.revalidating = None;
+        self.type_id = T::type_id_usize();
+        self.type_name = std::any::type_name::<T>();
+        self.priority = T::PRIORITY;
+        self.expire_after = T::EXPIRE_AFTER;
+        self.version = T::version().map(|current| {
+            let mut versions = ctx.versions.lock().unwrap_or_else(|e| e.into_inner());
+            // the current one if not loaded, e.g. inserted
+            let loaded = versions.remove(&self.type_id).unwrap_or(current);
+            (loaded, T::version as VersionFn)
+        });
+        self.once = ctx.once.contains(self.type_id);
+        ctx.loaded(self.type_id);
+    }
+}
+
+impl CacheLine {
+    /// Put a `Cacheable` registered at runtime into this `CacheLine`, see [`Cache::register_dyn()`].
+    fn fill_loaded(&mut self, ctx: &Context, type_id: usize, value: DynEntry) {
+        self.type_name = value.type_name;
+        self.exact = Some((*value.value).type_id());
+        self.inner = Some(Box::new(value));
+        self.snapshot_of = None;
+        self.delta = false;
+        self.commit(ctx);
+        self.generation = ctx.generation.load(Ordering::Relaxed);
+        self.loaded_at = Some(Instant::now());
+        self.revalidating = None;
+        self.type_id = type_id;
+        self.priority = 0;
+        self.expire_after = None;
+        self.version = None;
+        self.once = false;
+        ctx.loaded(self.type_id);
+    }
+
+    /// Whether this `CacheLine` is filled by `loader`, see [`Cache::register_dyn()`].
+    fn registered_by(&self, loader: &Loader) -> bool {
+        let store = self
+            .inner
+            .as_deref()
+            .and_then(DynCacheable::registered_store);
+        store.is_some_and(|store| std::sync::Arc::ptr_eq(store, &loader.store))
+    }
+
+    /// Empty this `CacheLine`, keeping the LRU.
+    fn clear(&mut self, ctx: &Context) {
+        ctx.bytes.fetch_sub(self.weight, Ordering::Relaxed);
+        if let Some(namespace) = self.namespace.as_deref() {
+            ctx.scopes.remove(namespace, self.weight);
+        }
+        if let Some(hook) = ctx.config.alloc_hook.as_ref() {
+            hook.realloc(self.allocated, None);
+        }
+        *self = CacheLine {
+            lru: self.lru,
+            reserved: self.reserved,
+            ..Default::default()
+        };
+    }
+
+    /// Weigh the committed value, and capture the snapshot of it
+    /// (see [`CacheBuilder::snapshot_reads()`]) and the baseline (see [`Cacheable::STORE_DELTA`]).
+    fn commit(&mut self, ctx: &Context) {
+        let inner = self.inner.take();
+        self.commit_held(ctx, inner.as_deref());
+        self.inner = inner;
+    }
+
+    /// Like `commit`, of the `value` held by a [`CacheMut`], so that it is not accessed through `inner` meanwhile.
+    fn commit_held(&mut self, ctx: &Context, value: Option<&DynValue>) {
+        let weight = value.map_or(0, |inner| inner.weight());
+        ctx.bytes.fetch_add(weight, Ordering::Relaxed);
+        ctx.bytes.fetch_sub(self.weight, Ordering::Relaxed);
+        if let Some(namespace) = self.namespace.take() {
+            ctx.scopes.remove(&namespace, self.weight);
+        }
+        self.namespace = value.and_then(|inner| inner.namespace().cloned());
+        if let Some(namespace) = self.namespace.as_ref() {
+            ctx.scopes.add(namespace, weight);
+        }
+        self.weight = weight;
+        if let Some(hook) = ctx.config.alloc_hook.as_ref() {
+            let allocated = value
+                .map(|inner| (inner.type_name(), std::alloc::Layout::for_value(inner)))
+                .filter(|(_, layout)| layout.size() > 0);
+            hook.realloc(self.allocated, allocated);
+            self.allocated = allocated;
+        }
+        if !ctx.config.snapshot_reads && !self.delta {
+            return;
+        }
+        let snapshot = match (self.snapshot_of, value) {
+            (Some(snapshot_of), Some(inner)) => snapshot_of(inner).map(std::sync::Arc::from),
+            _ => None,
+        };
+        if self.delta {
+            self.baseline.clone_from(&snapshot);
+        }
+        if ctx.config.snapshot_reads {
+            self.snapshot = snapshot;
+        }
+    }
+
+    /// The baseline to write back the changes since, see [`Cacheable::store_delta()`].
+    fn baseline(&self) -> Option<&dyn Any> {
+        self.baseline.as_deref().map(|baseline| baseline.as_any())
+    }
+
+    /// The information of the i-th `CacheLine` of the g-th `CacheGroup`, see [`Cache::for_each()`].
+    fn info(&self, g: usize, i: usize, flag: &Flag, ctx: &Context) -> EntryInfo {
+        EntryInfo {
+            type_name: self.type_name,
+            group: g,
+            line: i,
+            lru: self.lru,
+            priority: self.priority,
+            dirty: flag.is_dirty(),
+            accessed_at: self.accessed_at,
+            dirtied_at: flag.dirtied_at(),
+            ttl: self.ttl(ctx),
+            age: self.loaded_at.map(|loaded_at| loaded_at.elapsed()),
+            weight: self.weight,
+            namespace: self
+                .inner
+                .as_deref()
+                .and_then(|inner| inner.namespace().cloned()),
+        }
+    }
+
+    /// Whether the stored `Cacheable` changed since loaded, see [`Cacheable::version()`].
+    fn is_outdated(&self) -> bool {
+        matches!(self.version, Some((loaded, current)) if current() != Some(loaded))
+    }
+
+    fn is_expired(&self, ctx: &Context) -> bool {
+        match (self.ttl(ctx), self.loaded_at) {
+            (Some(ttl), Some(loaded_at)) => loaded_at.elapsed() >= ttl,
+            _ => false,
+        }
+    }
+
+    /// The TTL of the `Cacheable`, preferring [`Cacheable::EXPIRE_AFTER`] to [`CacheBuilder::ttl()`],
+    /// shortened by [`CacheBuilder::ttl_jitter()`].
+    fn ttl(&self, ctx: &Context) -> Option<Duration> {
+        let ttl = self.expire_after.or(ctx.config.ttl)?;
+        let (Some(jitter), Some(loaded_at)) = (ctx.config.ttl_jitter, self.loaded_at) else {
+            return Some(ttl);
+        };
+        // the same for each load, different among the `Cacheable`s loaded together
+        let mut hasher = std::hash::DefaultHasher::new();
+        std::hash::Hash::hash(&(self.type_id, loaded_at), &mut hasher);
+        let random = std::hash::Hasher::finish(&hasher) as f64 / u64::MAX as f64;
+        Some(ttl.mul_f64(1.0 - jitter * random))
+    }
+
+    /// Whether it is in the last part of its TTL, see [`CacheBuilder::refresh_ahead()`].
+    fn is_expiring(&self, ctx: &Context) -> bool {
+        match (self.ttl(ctx), ctx.config.refresh_ahead, self.loaded_at) {
+            (Some(ttl), Some(ahead), Some(loaded_at)) => {
+                loaded_at.elapsed() >= ttl.mul_f64(1.0 - ahead)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Debug for CacheLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheLine")
+            .field("lru", &self.lru)
+            .field("type_id", &self.type_id)
+            .field("type_name", &self.type_name)
+            .field("priority", &self.priority)
+            .field("generation", &self.generation)
+            .field("loaded_at", &self.loaded_at)
+            .field("expire_after", &self.expire_after)
+            .field("revalidating", &self.revalidating.is_some())
+            .field("once", &self.once)
+            .field("snapshot", &self.snapshot.is_some())
+            .field("baseline", &self.baseline.is_some())
+            .finish()
+    }
+}
+
+/// The read/write/dirty state of a `CacheLine`.
+///
+/// Guards are dropped without the group lock, so the flag itself orders the accesses to the value:
+/// - `read()` and `write()` acquire: they only succeed by an Acquire RMW, which sees every write to
+///   the value made before the last `end_write()`.
+/// - `end_read()` and `end_write()` release: no access to the value may happen after them.
+/// - The dirty bit is only set while writing, and only cleared after the value is stored, both
+///   with AcqRel, so a write-back always stores what the last writer left.
+/// - `is_*()` and `in_using()` are Acquire loads, a line seen unused may be accessed under the
+///   group lock without racing a dropped guard.
+///
+/// `dirtied_at` is informative only and stays Relaxed.
+#[derive(Debug, Default)]
+struct Flag {
+    // 000...00
+    //        ^ write
+    //  ^^^^^^ read count
+    // ^ dirty
+    inner: AtomicUsize,
+    /// When became dirty, in milliseconds since `UNIX_EPOCH`, 0 if clean.
+    dirtied_at: AtomicU64,
+}
+
+impl Flag {
+    const WRITE: usize = 1;
+    const READ: usize = 2;
+    const DIRTY: usize = !(usize::MAX >> 1);
+    const USING: usize = usize::MAX >> 1;
+
+    fn write(&self) -> CacheResult<()> {
+        self.inner
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |flag| {
+                (flag & Self::USING == 0).then_some(flag | Self::WRITE)
+            })
+            .map(|_| ())
+            .map_err(|_| CacheError::Locked)
+    }
+
+    fn read(&self) -> CacheResult<()> {
+        self.inner
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |flag| {
+                (flag & Self::WRITE == 0).then_some(flag + Self::READ)
+            })
+            .map(|_| ())
+            .map_err(|_| CacheError::Locked)
+    }
+
+    fn end_write(&self) {
+        self.inner.fetch_and(!Self::WRITE, Ordering::Release);
+    }
+
+    fn end_read(&self) {
+        self.inner.fetch_sub(Self::READ, Ordering::Release);
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.inner.load(Ordering::Acquire) & Self::DIRTY != 0
+    }
+
+    fn is_writing(&self) -> bool {
+        self.inner.load(Ordering::Acquire) & Self::WRITE != 0
+    }
+
+    /// Set dirty, return true if it was clean.
+    fn set_dirty(&self) -> bool {
+        let was_clean = self.inner.fetch_or(Self::DIRTY, Ordering::AcqRel) & Self::DIRTY == 0;
+        if was_clean {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            // 0 is reserved for clean
+            let millis = (now.as_millis() as u64).max(1);
+            self.dirtied_at.store(millis, Ordering::Relaxed);
+        }
+        was_clean
+    }
+
+    /// Set clean, return true if it was dirty.
+    fn set_clean(&self) -> bool {
+        self.dirtied_at.store(0, Ordering::Relaxed);
+        self.inner.fetch_and(!Self::DIRTY, Ordering::AcqRel) & Self::DIRTY != 0
+    }
+
+    /// When became dirty, None if clean.
+    fn dirtied_at(&self) -> Option<SystemTime> {
+        match self.dirtied_at.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(SystemTime::UNIX_EPOCH + Duration::from_millis(millis)),
+        }
+    }
+
+    fn in_using(&self) -> bool {
+        self.inner.load(Ordering::Acquire) & Self::USING != 0
+    }
+}
+
+/// An immutable ref wrapper to a cacheable object.
+///
+/// `Cache::get_mut::<T>()` will return `CacheError::Locked` before this ref dropped.
+pub struct CacheRef<'a, T>
+where
+    T: Any,
+{
+    inner: Held<'a>,
+    _watched: Option<Watched<'a>>,
+    _phantom: PhantomData<&'a T>,
+}
+
+/// The value held by a [`CacheRef`].
+enum Held<'a> {
+    Line(&'a DynValue, &'a Flag),
+    /// Not admitted into the cache, see [`CacheBuilder::doorkeeper()`].
+    Bypass(Box<DynValue>),
+    /// The committed value while being written, see [`CacheBuilder::snapshot_reads()`].
+    Snapshot(std::sync::Arc<DynValue>),
+}
+
+impl<'a, T: Any> CacheRef<'a, T> {
+    /// Hold the value, which is released if not a T.
+    /// - CacheError::TypeMismatch: the value is another `Cacheable`, whose truncated type id is the same.
+    fn new(inner: Held<'a>, watched: Option<Watched<'a>>) -> CacheResult<Self> {
+        let cache_ref = Self {
+            inner,
+            _watched: watched,
+            _phantom: PhantomData,
+        };
+        downcast_ref::<T>(cache_ref.inner.get())?;
+        Ok(cache_ref)
+    }
+}
+
+impl<T: Any> Deref for CacheRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: checked to be a T when created
+        unsafe { cast_ref(self.inner.get().as_any()) }
+    }
+}
+
+impl Held<'_> {
+    fn get(&self) -> &DynValue {
+        match self {
+            Held::Line(inner, _) => *inner,
+            Held::Bypass(inner) => inner.as_ref(),
+            Held::Snapshot(inner) => inner.as_ref(),
+        }
+    }
+}
+
+impl Drop for Held<'_> {
+    fn drop(&mut self) {
+        if let Held::Line(_, flag) = self {
+            flag.end_read();
+        }
+    }
+}
+
+/// A [`CacheRef`] keeping its cache alive, see [`Cache::get_owned()`].
+pub struct OwnedCacheRef<T: Any, const G: usize, const L: usize> {
+    // Always initialized, dropped before `_cache`. `MaybeUninit` hides the references into `_cache`,
+    // which must not be asserted alive while the last `Arc` is dropped.
+    cache_ref: MaybeUninit<CacheRef<'static, T>>,
+    _cache: Arc<CacheInner<G, L>>,
+}
+
+impl<T: Any, const G: usize, const L: usize> Deref for OwnedCacheRef<T, G, L> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: initialized until dropped
+        unsafe { self.cache_ref.assume_init_ref() }
+    }
+}
+
+impl<T: Any, const G: usize, const L: usize> Drop for OwnedCacheRef<T, G, L> {
+    fn drop(&mut self) {
+        // SAFETY: initialized, and never used again; `_cache` is dropped afterwards
+        unsafe { self.cache_ref.assume_init_drop() };
+    }
+}
+
+/// Write back all dirty `CacheLine`s when dropped, see [`Cache::flush_on_drop()`].
+#[must_use = "the cache is flushed when the guard is dropped"]
+pub struct FlushGuard<'a, const G: usize, const L: usize> {
+    cache: &'a Cache<G, L>,
+}
+
+impl<const G: usize, const L: usize> Drop for FlushGuard<'_, G, L> {
+    fn drop(&mut self) {
+        self.cache.report(self.cache.inner.flush_all());
+    }
+}
+
+/// A type-erased [`CacheRef`], see [`Cache::get_dyn()`].
+pub struct DynCacheRef<'a> {
+    inner: Held<'a>,
+    _watched: Option<Watched<'a>>,
+}
+
+impl<'a, T: Any> From<CacheRef<'a, T>> for DynCacheRef<'a> {
+    fn from(cache_ref: CacheRef<'a, T>) -> Self {
+        Self {
+            inner: cache_ref.inner,
+            _watched: cache_ref._watched,
+        }
+    }
+}
+
+impl Deref for DynCacheRef<'_> {
+    type Target = dyn Any;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.get().as_any()
+    }
+}
+
+impl<'a, T: Any> CacheRef<'a, T> {
+    /// Project this ref onto a part of T, which is still locked for reading until the projected ref dropped.
+    /// See [`project!`](crate::project!) for the shorthand of fields.
+    pub fn map<U: ?Sized>(self, project: fn(&T) -> &U) -> MappedRef<'a, T, U> {
+        MappedRef {
+            guard: self,
+            project,
+        }
+    }
+}
+
+/// A [`CacheRef`] projected onto a part of T, see [`CacheRef::map()`].
+pub struct MappedRef<'a, T: Any, U: ?Sized> {
+    guard: CacheRef<'a, T>,
+    project: fn(&T) -> &U,
+}
+
+impl<T: Any, U: ?Sized> Deref for MappedRef<'_, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        (self.project)(&self.guard)
+    }
+}
+
+/// A mutable ref wrapper to a cacheable object.
+///
+/// `Cache::get::<T>()` and `Cache::get_mut::<T>()`
+/// will return `CacheError::Locked` before this mut ref dropped.
+///
+/// If T is not admitted into the cache (see [`CacheBuilder::doorkeeper()`]),
+/// it is written back when this mut ref dropped if dereferenced mutably,
+/// and the error can only be observed by [`Cache::on_store_error()`].
+pub struct CacheMut<'a, T>
+where
+    T: Any,
+{
+    inner: HeldMut<'a>,
+    ctx: &'a Context,
+    _watched: Option<Watched<'a>>,
+    _phantom: PhantomData<&'a T>,
+}
+
+/// The value held by a [`CacheMut`].
+enum HeldMut<'a> {
+    /// With whether dereferenced mutably, how to copy it for [`CacheBuilder::history()`],
+    /// and the `CacheGroup` and index of the `CacheLine` to commit it, see [`CacheMut::commit()`].
+    Line(
+        &'a mut DynValue,
+        &'a Flag,
+        bool,
+        Option<SnapshotFn>,
+        (&'a dyn CommitLine, usize),
+    ),
+    /// Not admitted into the cache with whether dirty, see [`CacheBuilder::doorkeeper()`].
+    Bypass(Box<DynValue>, bool),
+}
+
+impl HeldMut<'_> {
+    fn get(&self) -> &DynValue {
+        match self {
+            HeldMut::Line(inner, ..) => &**inner,
+            HeldMut::Bypass(inner, _) => inner.as_ref(),
+        }
+    }
+
+    /// Get the value mutably, marking it dirty.
+    /// The value before the first modification is recorded, see [`CacheBuilder::history()`].
+    fn get_mut(&mut self, ctx: &Context) -> &mut DynValue {
+        match self {
+            HeldMut::Line(inner, flag, modified, snapshot_of, _) => {
+                if let (false, Some(snapshot_of)) = (*modified, snapshot_of) {
+                    ctx.history.record(&ctx.config, &**inner, *snapshot_of);
+                }
+                ctx.set_dirty(flag);
+                *modified = true;
+                &mut **inner
+            }
+            HeldMut::Bypass(inner, dirty) => {
+                *dirty = true;
+                inner.as_mut()
+            }
+        }
+    }
+
+    /// Release the write lock, or write back the modified bypass value.
+    /// Notify the watchers if modified, see [`Cache::watch()`].
+    fn release(&self, ctx: &Context) {
+        // read before released, after which the value may be written by others
+        let value = self.get();
+        let type_id = crate::namespace::id_of(value, type_id_of(value.as_any().type_id()));
+        let modified = match self {
+            HeldMut::Line(_, flag, modified, ..) => {
+                flag.end_write();
+                *modified
+            }
+            HeldMut::Bypass(inner, true) => {
+                ctx.write_back(inner.as_ref(), None).ok();
+                true
+            }
+            HeldMut::Bypass(_, false) => false,
+        };
+        if modified {
+            ctx.changed(type_id);
+        }
+    }
+}
+
+impl<T: Any> Deref for CacheMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: checked to be a T when created
+        unsafe { cast_ref(self.inner.get().as_any()) }
+    }
+}
+
+impl<T: Any> DerefMut for CacheMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: checked to be a T when created
+        unsafe { cast_mut(self.inner.get_mut(self.ctx).as_any_mut()) }
+    }
+}
+
+impl<'a, T: Any> CacheMut<'a, T> {
+    /// Hold the value mutably, which is released if not a T.
+    /// - CacheError::TypeMismatch: the value is another `Cacheable`, whose truncated type id is the same.
+    fn new(
+        inner: HeldMut<'a>,
+        ctx: &'a Context,
+        watched: Option<Watched<'a>>,
+    ) -> CacheResult<Self> {
+        let cache_mut = Self {
+            inner,
+            ctx,
+            _watched: watched,
+            _phantom: PhantomData,
+        };
+        downcast_ref::<T>(cache_mut.inner.get())?;
+        Ok(cache_mut)
+    }
+}
+
+impl<T: Any> CacheMut<'_, T> {
+    /// Undo or redo T, marking it dirty, see [`Cache::undo()`].
+    fn swap_history(&mut self, undo: bool) -> bool {
+        // modified without being recorded
+        let current = match &mut self.inner {
+            HeldMut::Line(inner, _, modified, ..) => {
+                *modified = true;
+                &mut **inner
+            }
+            HeldMut::Bypass(inner, _) => inner.as_mut(),
+        };
+        // SAFETY: checked to be a T when created
+        let current = unsafe { cast_mut::<T>(current.as_any_mut()) };
+        let swapped = self.ctx.history.swap(current, undo);
+        if swapped {
+            self.set_dirty();
+        }
+        swapped
+    }
+
+    /// Mark the `Cacheable` dirty without dereferencing mutably.
+    fn set_dirty(&mut self) {
+        match &mut self.inner {
+            HeldMut::Line(_, flag, ..) => self.ctx.set_dirty(flag),
+            HeldMut::Bypass(_, dirty) => *dirty = true,
+        }
+    }
+}
+
+impl<T: Cacheable + Send + Sync + Default> CacheMut<'_, T> {
+    /// Discard the changes: restore T last loaded or written back in place and mark it clean,
+    /// so that the modified T is never written back, nor reported to [`Cache::watch()`].
+    ///
+    /// T is copied from the snapshot if kept, see [`CacheBuilder::snapshot_reads()`] and [`Cacheable::STORE_DELTA`],
+    /// or loaded again otherwise, in which case the default T is restored if loading failed.
+    pub fn rollback(mut self) {
+        let committed = match &self.inner {
+            HeldMut::Line(.., (group, i)) => group.committed(*i),
+            HeldMut::Bypass(..) => None,
+        };
+        let restored = committed
+            .as_deref()
+            .and_then(|committed| committed.as_any().downcast_ref::<T>())
+            .and_then(T::snapshot)
+            .unwrap_or_else(|| {
+                let namespace = self.inner.get().namespace().cloned();
+                crate::namespace::within(namespace, || self.ctx.load::<T>())
+            });
+        match &mut self.inner {
+            HeldMut::Line(inner, _, modified, _, (group, i)) => {
+                // SAFETY: checked to be a T when created
+                *unsafe { cast_mut::<T>(inner.as_any_mut()) } = restored;
+                *modified = false;
+                group.reset(*i, self.ctx, &**inner);
+            }
+            HeldMut::Bypass(inner, dirty) => {
+                // SAFETY: checked to be a T when created
+                *unsafe { cast_mut::<T>(inner.as_any_mut()) } = restored;
+                *dirty = false;
+            }
+        }
+    }
+}
+
+impl<T: Any> Drop for CacheMut<'_, T> {
+    fn drop(&mut self) {
+        self.inner.release(self.ctx);
+    }
+}
+
+/// The trait object cached in slot K, see [`Cache::register_object()`].
+struct Slot<K, D: ?Sized>(Box<D>, PhantomData<fn() -> K>);
+
+/// Fail with `CacheError::TypeMismatch` if `value` is not the trait object in slot K.
+fn slot_of<K: 'static, D: ?Sized + 'static>(value: &dyn Any) -> CacheResult<()> {
+    match value.is::<Slot<K, D>>() {
+        true => Ok(()),
+        false => Err(CacheError::TypeMismatch {
+            expected: std::any::type_name::<K>(),
+            found: "another slot",
+        }),
+    }
+}
+
+/// A ref to the trait object in slot K, see [`Cache::get_object()`].
+pub struct ObjectRef<'a, K, D: ?Sized> {
+    inner: DynCacheRef<'a>,
+    _phantom: PhantomData<fn() -> Slot<K, D>>,
+}
+
+impl<K: 'static, D: ?Sized + 'static> Deref for ObjectRef<'_, K, D> {
+    type Target = D;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: checked to be the slot when created
+        &unsafe { cast_ref::<Slot<K, D>>(&*self.inner) }.0
+    }
+}
+
+/// A mut ref to the trait object in slot K, see [`Cache::get_mut_object()`].
+/// Dereferencing it mutably marks the slot dirty.
+pub struct ObjectMut<'a, K, D: ?Sized> {
+    inner: DynCacheMut<'a>,
+    _phantom: PhantomData<fn() -> Slot<K, D>>,
+}
+
+impl<K: 'static, D: ?Sized + 'static> Deref for ObjectMut<'_, K, D> {
+    type Target = D;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: checked to be the slot when created
+        &unsafe { cast_ref::<Slot<K, D>>(&*self.inner) }.0
+    }
+}
+
+impl<K: 'static, D: ?Sized + 'static> DerefMut for ObjectMut<'_, K, D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: checked to be the slot when created
+        &mut unsafe { cast_mut::<Slot<K, D>>(&mut *self.inner) }.0
+    }
+}
+
+/// A type-erased [`CacheMut`], see [`Cache::get_mut_dyn()`].
+pub struct DynCacheMut<'a> {
+    inner: HeldMut<'a>,
+    ctx: &'a Context,
+    _watched: Option<Watched<'a>>,
+}
+
+impl<'a, T: Any> From<CacheMut<'a, T>> for DynCacheMut<'a> {
+    fn from(cache_mut: CacheMut<'a, T>) -> Self {
+        let mut cache_mut = std::mem::ManuallyDrop::new(cache_mut);
+        Self {
+            // Safety: moved out of `cache_mut`, which is never dropped
+            inner: unsafe { std::ptr::read(&cache_mut.inner) },
+            ctx: cache_mut.ctx,
+            _watched: cache_mut._watched.take(),
+        }
+    }
+}
+
+impl Deref for DynCacheMut<'_> {
+    type Target = dyn Any;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.get().as_any()
+    }
+}
+
+impl DerefMut for DynCacheMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inner.get_mut(self.ctx).as_any_mut()
+    }
+}
+
+impl Drop for DynCacheMut<'_> {
+    fn drop(&mut self) {
+        self.inner.release(self.ctx);
+    }
+}
+
+impl<'a, T: Any> CacheMut<'a, T> {
+    /// Project this mut ref onto a part of T, which is still locked for writing until the projected ref dropped.
+    /// Dereferencing the projected ref mutably marks T dirty.
+    /// See [`project!`](crate::project!) for the shorthand of fields.
+    pub fn map<U: ?Sized>(
+        self,
+        project: fn(&T) -> &U,
+        project_mut: fn(&mut T) -> &mut U,
+    ) -> MappedMut<'a, T, U> {
+        MappedMut {
+            guard: self,
+            project,
+            project_mut,
+        }
+    }
+
+    /// Write T back now and mark it clean, while still holding it, e.g. at a checkpoint of a long edit,
+    /// as it is written back when evicted. The snapshot read by others meanwhile
+    /// (see [`CacheBuilder::snapshot_reads()`]) is the committed T afterwards.
+    /// Dereferencing mutably afterwards marks it dirty again. T stays dirty if failed, and a clean T is not written.
+    pub fn commit(&mut self) -> std::io::Result<()> {
+        match &mut self.inner {
+            HeldMut::Line(inner, .., (group, i)) => group.commit(*i, self.ctx, &**inner),
+            HeldMut::Bypass(inner, dirty) => {
+                if *dirty {
+                    self.ctx.write_back(inner.as_ref(), None)?;
+                    *dirty = false;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A [`CacheMut`] projected onto a part of T, see [`CacheMut::map()`].
+pub struct MappedMut<'a, T: Any, U: ?Sized> {
+    guard: CacheMut<'a, T>,
+    project: fn(&T) -> &U,
+    project_mut: fn(&mut T) -> &mut U,
+}
+
+impl<T: Any, U: ?Sized> Deref for MappedMut<'_, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        (self.project)(&self.guard)
+    }
+}
+
+impl<T: Any, U: ?Sized> DerefMut for MappedMut<'_, T, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        (self.project_mut)(&mut self.guard)
+    }
+}
+
+/// Project a [`CacheRef`] or (with `mut`) a [`CacheMut`] onto a (nested) field,
+/// by [`CacheRef::map()`] or [`CacheMut::map()`].
+///
+/// ```ignore
+/// let pos = project!(world.player.position);
+/// let mut hp = project!(mut world_mut.player.hp);
+/// *hp -= 1;
+/// ```
+#[macro_export]
+macro_rules! project {
+    (mut $guard:ident $(. $field:tt)+) => {
+        $guard.map(|v| &v $(. $field)+, |v| &mut v $(. $field)+)
+    };
+    ($guard:ident $(. $field:tt)+) => {
+        $guard.map(|v| &v $(. $field)+)
+    };
+}
+
+/// Load every listed `Cacheable` into a [`Cache`] eagerly, each in its own thread,
+/// return the type names with the results of `Cache::get()` in the order listed.
+///
+/// ```ignore
+/// for (type_name, res) in warm_up!(cache, Config, Assets, World) {
+///     res.map_err(|e| format!("failed to warm {type_name} up: {e}"))?;
+/// }
+/// ```
+#[macro_export]
+macro_rules! warm_up {
+    ($cache:expr, $($T:ty),+ $(,)?) => {{
+        let cache = &$cache;
+        ::std::thread::scope(|s| {
+            [$((
+                ::std::any::type_name::<$T>(),
+                s.spawn(move || cache.get::<$T>().map(drop)),
+            )),+]
+            .map(|(type_name, handle)| {
+                let res = handle
+                    .join()
+                    .unwrap_or_else(|e| ::std::panic::resume_unwind(e));
+                (type_name, res)
+            })
+        })
+    }};
+}
+
+/// A type that can be cached.
+///
+/// [`Cache`] needs `Send + Sync` Cacheables, while [`LocalCache`](crate::LocalCache) does not.
+///
+/// Implement either [`Cacheable::load()`] and [`Cacheable::store()`] doing the IO,
+/// or [`Cacheable::KEY`] with [`Cacheable::encode()`] and [`Cacheable::decode()`] for a cache with a [`Backend`].
+pub trait Cacheable: Any {
+    /// The eviction priority, 0 by default.
+    /// When a `CacheGroup` is full, `CacheLine`s holding lower priority `Cacheable`s
+    /// are evicted first, even if they are more recently used.
+    const PRIORITY: u8 = 0;
+
+    /// Expire the cached Cacheable after this duration since loaded, None by default.
+    /// It overrides [`CacheBuilder::ttl()`] for this type, the rest of the TTL settings still apply.
+    const EXPIRE_AFTER: Option<Duration> = None;
+
+    /// The `CacheGroup` (modulo `G`) to put this type in instead of `type_id % G`, None by default.
+    /// Use it to keep the hot types apart from each other. [`Cache::migrate()`] still takes precedence.
+    const GROUP_HINT: Option<usize> = None;
+
+    /// Write back by [`Cacheable::store_delta()`] instead of [`Cacheable::store()`], false by default.
+    /// If true, [`Cache`] keeps the value last loaded or written back as the baseline,
+    /// copied by [`Cacheable::snapshot()`], so it costs as much memory again.
+    const STORE_DELTA: bool = false;
+
+    /// The key of the Cacheable in the [`Backend`] of the cache, None by default.
+    /// With a key and a backend, the Cacheable is loaded by [`Cacheable::decode()`] and written back by
+    /// [`Cacheable::encode()`] instead of [`Cacheable::load()`] and [`Cacheable::store()`].
+    /// [`Cacheable::store_delta()`] is not used then.
+    const KEY: Option<&'static str> = None;
+
+    /// Load at most `per_second` of this Cacheable on cache misses, in bursts of at most `burst`,
+    /// on top of [`CacheBuilder::load_rate()`]. Unlimited by default.
+    const LOAD_RATE: Option<(f64, usize)> = None;
+
+    /// Load Cacheable from the storage. Fails with `ErrorKind::Unsupported` by default,
+    /// for the Cacheables only stored in a [`Backend`].
+    fn load() -> std::io::Result<Self>
+    where
+        Self: Sized,
+    {
+        Err(unsupported("Cacheable::load()"))
+    }
+
+    /// Write Cacheable back to storage. Fails with `ErrorKind::Unsupported` by default,
+    /// for the Cacheables only stored in a [`Backend`].
+    fn store(&self) -> std::io::Result<()> {
+        Err(unsupported("Cacheable::store()"))
+    }
+
+    /// Serialize Cacheable to be stored in a [`Backend`], see [`Cacheable::KEY`].
+    /// Fails with `ErrorKind::Unsupported` by default.
+    fn encode(&self) -> std::io::Result<Vec<u8>> {
+        Err(unsupported("Cacheable::encode()"))
+    }
+
+    /// Deserialize Cacheable loaded from a [`Backend`], see [`Cacheable::KEY`].
+    /// Fails with `ErrorKind::Unsupported` by default.
+    fn decode(bytes: &[u8]) -> std::io::Result<Self>
+    where
+        Self: Sized,
+    {
+        let _ = bytes;
+        Err(unsupported("Cacheable::decode()"))
+    }
+
+    /// The version of the stored Cacheable, e.g. the modification time of a file,
+    /// or a counter bumped by external writers. None by default, i.e. unversioned.
+    ///
+    /// The version is read before each load, and a cached Cacheable whose version changed since
+    /// is loaded again when accessed next, like an expired one (written back first if dirty).
+    /// It is read on each hit with the lock of the `CacheGroup` held, so it must be cheap.
+    fn version() -> Option<u64>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Write back only what changed since the baseline, see [`Cacheable::STORE_DELTA`].
+    /// [`Cacheable::store()`] by default. It is still used without a baseline,
+    /// e.g. if [`Cacheable::snapshot()`] returns None, or for a value in the write buffer.
+    fn store_delta(&self, since: &Self) -> std::io::Result<()>
+    where
+        Self: Sized,
+    {
+        let _ = since;
+        self.store()
+    }
+
+    /// The approximate number of bytes held, see [`CacheBuilder::max_bytes()`].
+    /// `size_of_val(self)` by default, override it to count the heap allocations.
+    fn weight(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+
+    /// The value shown by `Cache::debug_json()`, None by default, i.e. hidden.
+    /// Enabled by feature `serde_json`.
+    #[cfg(feature = "serde_json")]
+    fn debug_json(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Copy the committed Cacheable for snapshot reads, see [`CacheBuilder::snapshot_reads()`].
+    /// None by default, i.e. no snapshot. Usually implemented as `Some(self.clone())`.
+    fn snapshot(&self) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// As Any. This is no longer needed since the cache upcasts internally,
+    /// it is kept for compatibility and provided by default.
+    #[cfg(not(feature = "nightly"))]
+    fn as_any(&self) -> &dyn Any
+    where
+        Self: Sized,
+    {
+        self
+    }
+    /// As Any mut. This is no longer needed, see [`Cacheable::as_any()`].
     #[cfg(not(feature = "nightly"))]
+    fn as_any_mut(&mut self) -> &mut dyn Any
+    where
+        Self: Sized,
+    {
+        self
+    }
+}
+
+/// Forward to T, sharing the loaded T.
+impl<T: Cacheable> Cacheable for std::sync::Arc<T> {
+    const PRIORITY: u8 = T::PRIORITY;
+    const EXPIRE_AFTER: Option<Duration> = T::EXPIRE_AFTER;
+    const GROUP_HINT: Option<usize> = T::GROUP_HINT;
+    const STORE_DELTA: bool = T::STORE_DELTA;
+    const KEY: Option<&'static str> = T::KEY;
+    const LOAD_RATE: Option<(f64, usize)> = T::LOAD_RATE;
+
+    fn load() -> std::io::Result<Self> {
+        T::load().map(std::sync::Arc::new)
+    }
+
+    fn decode(bytes: &[u8]) -> std::io::Result<Self> {
+        T::decode(bytes).map(std::sync::Arc::new)
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        T::store(self)
+    }
+
+    fn version() -> Option<u64> {
+        T::version()
+    }
+
+    fn encode(&self) -> std::io::Result<Vec<u8>> {
+        T::encode(self)
+    }
+
+    fn store_delta(&self, since: &Self) -> std::io::Result<()> {
+        T::store_delta(self, since)
+    }
+
+    fn weight(&self) -> usize {
+        std::mem::size_of::<Self>() + T::weight(self)
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn debug_json(&self) -> Option<serde_json::Value> {
+        T::debug_json(self)
+    }
+
+    fn snapshot(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
+
+/// Forward to T.
+impl<T: Cacheable> Cacheable for Box<T> {
+    const PRIORITY: u8 = T::PRIORITY;
+    const EXPIRE_AFTER: Option<Duration> = T::EXPIRE_AFTER;
+    const GROUP_HINT: Option<usize> = T::GROUP_HINT;
+    const STORE_DELTA: bool = T::STORE_DELTA;
+    const KEY: Option<&'static str> = T::KEY;
+    const LOAD_RATE: Option<(f64, usize)> = T::LOAD_RATE;
+
+    fn load() -> std::io::Result<Self> {
+        T::load().map(Box::new)
+    }
+
+    fn decode(bytes: &[u8]) -> std::io::Result<Self> {
+        T::decode(bytes).map(Box::new)
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        T::store(self)
+    }
+
+    fn version() -> Option<u64> {
+        T::version()
+    }
+
+    fn encode(&self) -> std::io::Result<Vec<u8>> {
+        T::encode(self)
+    }
+
+    fn store_delta(&self, since: &Self) -> std::io::Result<()> {
+        T::store_delta(self, since)
+    }
+
+    fn weight(&self) -> usize {
+        std::mem::size_of::<Self>() + T::weight(self)
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn debug_json(&self) -> Option<serde_json::Value> {
+        T::debug_json(self)
+    }
+
+    fn snapshot(&self) -> Option<Self> {
+        T::snapshot(self).map(Box::new)
+    }
+}
+
+/// Forward to T under the read lock, failing with `std::io::Error` if poisoned.
+impl<T: Cacheable> Cacheable for std::sync::RwLock<T> {
+    const PRIORITY: u8 = T::PRIORITY;
+    const EXPIRE_AFTER: Option<Duration> = T::EXPIRE_AFTER;
+    const GROUP_HINT: Option<usize> = T::GROUP_HINT;
+    const STORE_DELTA: bool = T::STORE_DELTA;
+    const KEY: Option<&'static str> = T::KEY;
+    const LOAD_RATE: Option<(f64, usize)> = T::LOAD_RATE;
+
+    fn load() -> std::io::Result<Self> {
+        T::load().map(std::sync::RwLock::new)
+    }
+
+    fn decode(bytes: &[u8]) -> std::io::Result<Self> {
+        T::decode(bytes).map(std::sync::RwLock::new)
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        let inner = self
+            .read()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        T::store(&inner)
+    }
+
+    fn version() -> Option<u64> {
+        T::version()
+    }
+
+    fn encode(&self) -> std::io::Result<Vec<u8>> {
+        let inner = self
+            .read()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        T::encode(&inner)
+    }
+
+    fn store_delta(&self, since: &Self) -> std::io::Result<()> {
+        let inner = self
+            .read()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        let since = since.read().unwrap_or_else(|e| e.into_inner());
+        T::store_delta(&inner, &since)
+    }
+
+    fn weight(&self) -> usize {
+        let inner = self.read().unwrap_or_else(|e| e.into_inner());
+        std::mem::size_of::<Self>() - std::mem::size_of::<T>() + T::weight(&inner)
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn debug_json(&self) -> Option<serde_json::Value> {
+        let inner = self.read().unwrap_or_else(|e| e.into_inner());
+        T::debug_json(&inner)
+    }
+
+    fn snapshot(&self) -> Option<Self> {
+        let inner = self.read().unwrap_or_else(|e| e.into_inner());
+        T::snapshot(&inner).map(std::sync::RwLock::new)
+    }
+}
+
+/// The error of a `Cacheable` method not implemented.
+fn unsupported(method: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{method} is not implemented"),
+    )
+}
+
+/// The value stored in `CacheLine`s of [`Cache`].
+pub(crate) type DynValue = dyn DynCacheable + Send + Sync;
+
+/// Downcast the `Cacheable` to T.
+/// - CacheError::TypeMismatch: `value` is another `Cacheable`, whose truncated type id is the same.
+fn downcast_ref<T: Any>(value: &DynValue) -> CacheResult<&T> {
+    let found = value.type_name();
+    value
+        .as_any()
+        .downcast_ref()
+        .ok_or(CacheError::TypeMismatch {
+            expected: std::any::type_name::<T>(),
+            found,
+        })
+}
+
+/// Like [`downcast_ref`], mutably.
+fn downcast_mut<T: Any>(value: &mut DynValue) -> CacheResult<&mut T> {
+    let found = value.type_name();
+    value
+        .as_any_mut()
+        .downcast_mut()
+        .ok_or(CacheError::TypeMismatch {
+            expected: std::any::type_name::<T>(),
+            found,
+        })
+}
+
+/// Cast the value held by a guard, checked to be a T when the guard was created.
+///
+/// # Safety
+/// `value` must be a T.
+unsafe fn cast_ref<T: Any>(value: &dyn Any) -> &T {
+    debug_assert!(value.is::<T>());
+    // SAFETY: a T, as the caller guarantees
+    unsafe { &*(value as *const dyn Any as *const T) }
+}
+
+/// Like [`cast_ref`], mutably.
+///
+/// # Safety
+/// `value` must be a T.
+unsafe fn cast_mut<T: Any>(value: &mut dyn Any) -> &mut T {
+    debug_assert!(value.is::<T>());
+    // SAFETY: a T, as the caller guarantees
+    unsafe { &mut *(value as *mut dyn Any as *mut T) }
+}
+
+/// The object safe part of [`Cacheable`], which is stored in `CacheLine`s.
+pub(crate) trait DynCacheable: Any {
+    fn store(&self) -> std::io::Result<()>;
+    /// Store to `backend` if T has a [`Cacheable::KEY`],
+    /// otherwise [`Cacheable::store_delta()`] if `since` is a T, [`Cacheable::store()`] otherwise.
+    fn store_since(
+        &self,
+        since: Option<&dyn Any>,
+        backend: Option<&SharedBackend>,
+    ) -> std::io::Result<()>;
+    fn type_name(&self) -> &'static str;
+    fn weight(&self) -> usize;
     fn as_any(&self) -> &dyn Any;
-    /// As Any mut.
-    /// ```ignore
-    /// fn as_any_mut(&mut self) -> &mut dyn Any {
-    ///     self
-    /// }
-    /// ```
-    #[cfg(not(feature = "nightly"))]
     fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// The namespace of the value, see [`Cache::scope()`].
+    fn namespace(&self) -> Option<&std::sync::Arc<str>> {
+        None
+    }
+    /// The key in the `Backend` and the bytes if T has a [`Cacheable::KEY`], see [`CacheBuilder::store_batch()`].
+    fn encode_keyed(&self) -> Option<(String, std::io::Result<Vec<u8>>)> {
+        None
+    }
+    /// How the value is written back if registered at runtime, see [`Cache::register_dyn()`].
+    fn registered_store(&self) -> Option<&std::sync::Arc<DynStoreFn>> {
+        None
+    }
+    #[cfg(feature = "serde_json")]
+    fn debug_json(&self) -> Option<serde_json::Value> {
+        None
+    }
 }
 
-trait CacheableExt: Cacheable + Sized {
-    /// Load Cacheable from the storage, or return default value.
-    fn load_or_default() -> Self
-    where
-        Self: Default,
-    {
-        Self::load().unwrap_or_default()
+impl<T: Cacheable> DynCacheable for T {
+    fn store(&self) -> std::io::Result<()> {
+        Cacheable::store(self)
+    }
+
+    fn store_since(
+        &self,
+        since: Option<&dyn Any>,
+        backend: Option<&SharedBackend>,
+    ) -> std::io::Result<()> {
+        if let Some(res) = crate::backend::store(self, backend) {
+            return res;
+        }
+        match since.and_then(|since| since.downcast_ref::<T>()) {
+            Some(since) => Cacheable::store_delta(self, since),
+            None => Cacheable::store(self),
+        }
+    }
+
+    fn encode_keyed(&self) -> Option<(String, std::io::Result<Vec<u8>>)> {
+        let key = T::KEY?;
+        Some((crate::namespace::key(key).into_owned(), self.encode()))
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn debug_json(&self) -> Option<serde_json::Value> {
+        Cacheable::debug_json(self)
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+
+    fn weight(&self) -> usize {
+        Cacheable::weight(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
+}
+
+/// A `Cacheable` registered at runtime, see [`Cache::register_dyn()`].
+struct DynEntry {
+    value: Box<dyn Any + Send + Sync>,
+    type_name: &'static str,
+    store: std::sync::Arc<DynStoreFn>,
+}
+
+impl DynCacheable for DynEntry {
+    fn store(&self) -> std::io::Result<()> {
+        (self.store)(self.value.as_ref())
+    }
+
+    fn store_since(
+        &self,
+        _since: Option<&dyn Any>,
+        _backend: Option<&SharedBackend>,
+    ) -> std::io::Result<()> {
+        self.store()
+    }
+
+    fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    fn weight(&self) -> usize {
+        std::mem::size_of_val(self.value.as_ref())
+    }
+
+    fn registered_store(&self) -> Option<&std::sync::Arc<DynStoreFn>> {
+        Some(&self.store)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self.value.as_ref()
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self.value.as_mut()
+    }
+}
+
+/// The `Send + Sync` Cacheables, which can be stored in [`Cache`].
+trait CacheableExt: Cacheable + Send + Sync + Sized {
     /// Get the lower 64 bit of Cacheable's TypeId.
     fn type_id_usize() -> usize {
-        unsafe { transmute::<TypeId, (u64, u64)>(TypeId::of::<Self>()).1 as usize }
+        type_id_usize::<Self>()
     }
     /// Retrieve Cacheable from the cache.
     fn retrieve_from<const G: usize, const L: usize>(
@@ -406,9 +5200,13 @@ trait CacheableExt: Cacheable + Sized {
     where
         Self: Default,
     {
-        let type_id = Self::type_id_usize();
-        let group = type_id % G;
-        cache.groups[group].retrieve()
+        loop {
+            match cache.group::<Self>().retrieve(&cache.ctx) {
+                // migrated concurrently, see `Cache::migrate()`
+                Err(CacheError::Missing) => continue,
+                res => return res,
+            }
+        }
     }
     /// Retrieve mut Cacheable from the cache.
     fn retrieve_mut_from<const G: usize, const L: usize>(
@@ -417,13 +5215,640 @@ trait CacheableExt: Cacheable + Sized {
     where
         Self: Default,
     {
-        let type_id = Self::type_id_usize();
-        let group = type_id % G;
-        cache.groups[group].retrieve_mut()
+        loop {
+            match cache.group::<Self>().retrieve_mut(&cache.ctx) {
+                // migrated concurrently, see `Cache::migrate()`
+                Err(CacheError::Missing) => continue,
+                res => return res,
+            }
+        }
+    }
+}
+
+impl<T> CacheableExt for T where T: Cacheable + Send + Sync + Sized {}
+
+/// Get the lower 64 bit of T's TypeId, 0 is reserved for empty `CacheLine`s.
+/// Mixed with the namespace accessed on this thread if any, see [`Cache::scope()`].
+pub(crate) fn type_id_usize<T: Any>() -> usize {
+    crate::namespace::id(type_id_of(TypeId::of::<T>()))
+}
+
+pub(crate) fn type_id_of(type_id: TypeId) -> usize {
+    unsafe { transmute::<TypeId, (u64, u64)>(type_id).1 as usize }
+}
+
+/// The `Cacheable`s loaded at most once, see [`Cache::get_or_init()`],
+/// with the evicted values retained to be put back instead of loading again.
+#[derive(Default)]
+struct OnceTypes(std::sync::Mutex<HashMap<usize, Option<Box<DynValue>>>>);
+
+impl std::fmt::Debug for OnceTypes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("OnceTypes")
+            .field(&self.lock().len())
+            .finish()
+    }
+}
+
+impl OnceTypes {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<usize, Option<Box<DynValue>>>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn insert(&self, type_id: usize) {
+        self.lock().entry(type_id).or_default();
+    }
+
+    fn contains(&self, type_id: usize) -> bool {
+        self.lock().contains_key(&type_id)
+    }
+
+    /// Retain the evicted value.
+    fn retain(&self, type_id: usize, value: Box<DynValue>) {
+        self.lock().insert(type_id, Some(value));
+    }
+
+    /// Take the retained value.
+    fn take(&self, type_id: usize) -> Option<Box<DynValue>> {
+        self.lock().get_mut(&type_id)?.take()
+    }
+}
+
+/// The token buckets limiting the loads on cache misses, the shared one and one for each `Cacheable`,
+/// see [`CacheBuilder::load_rate()`].
+#[derive(Debug, Default)]
+struct Limiter(std::sync::Mutex<(Option<Bucket>, HashMap<usize, Bucket>)>);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn full((_, burst): (f64, usize)) -> Self {
+        Self {
+            tokens: burst as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Refill at `per_second` up to `burst`, return whether a token is left.
+    fn refill(&mut self, (per_second, burst): (f64, usize)) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(burst as f64);
+        self.refilled_at = now;
+        self.tokens >= 1.0
+    }
+}
+
+impl Limiter {
+    /// Take a token from the shared bucket limited by `shared` and the one of `type_id` limited by `own`,
+    /// return false without taking any if either is empty.
+    fn permit(
+        &self,
+        type_id: usize,
+        own: Option<(f64, usize)>,
+        shared: Option<(f64, usize)>,
+    ) -> bool {
+        if own.is_none() && shared.is_none() {
+            return true;
+        }
+        let mut buckets = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let (shared_bucket, own_buckets) = &mut *buckets;
+        let mut taken = vec![];
+        if let Some(rate) = shared {
+            taken.push((
+                shared_bucket.get_or_insert_with(|| Bucket::full(rate)),
+                rate,
+            ));
+        }
+        if let Some(rate) = own {
+            let bucket = own_buckets
+                .entry(type_id)
+                .or_insert_with(|| Bucket::full(rate));
+            taken.push((bucket, rate));
+        }
+        let permitted = taken.iter_mut().fold(true, |permitted, (bucket, rate)| {
+            bucket.refill(*rate) && permitted
+        });
+        if permitted {
+            taken
+                .iter_mut()
+                .for_each(|(bucket, _)| bucket.tokens -= 1.0);
+        }
+        permitted
+    }
+}
+
+/// The error of a load abandoned past its deadline, see [`CacheBuilder::load_timeout()`].
+#[derive(Debug, thiserror::Error)]
+#[error("loading timed out")]
+struct LoadTimeout;
+
+impl From<LoadTimeout> for std::io::Error {
+    fn from(timeout: LoadTimeout) -> Self {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, timeout)
+    }
+}
+
+/// A semaphore bounding the loads running at once, see [`CacheBuilder::max_loads()`].
+#[derive(Debug, Default)]
+struct Loads {
+    running: std::sync::Mutex<usize>,
+    done: std::sync::Condvar,
+}
+
+impl Loads {
+    /// Wait until fewer than `limit` loads are running, for at most `timeout` if given,
+    /// then count this one until the permit is dropped. None if unlimited.
+    fn permit(
+        self: &std::sync::Arc<Self>,
+        limit: Option<usize>,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<Option<Permit>> {
+        let Some(limit) = limit else {
+            return Ok(None);
+        };
+        let running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        let full = |running: &mut usize| *running >= limit;
+        let mut running = match timeout {
+            Some(timeout) => {
+                let (running, waited) = self
+                    .done
+                    .wait_timeout_while(running, timeout, full)
+                    .unwrap_or_else(|e| e.into_inner());
+                if waited.timed_out() {
+                    return Err(LoadTimeout.into());
+                }
+                running
+            }
+            None => self
+                .done
+                .wait_while(running, full)
+                .unwrap_or_else(|e| e.into_inner()),
+        };
+        *running += 1;
+        Ok(Some(Permit(self.clone())))
+    }
+}
+
+/// A load counted by [`Loads`] until dropped.
+struct Permit(std::sync::Arc<Loads>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut running = self.0.running.lock().unwrap_or_else(|e| e.into_inner());
+        *running -= 1;
+        self.0.done.notify_one();
+    }
+}
+
+/// The consecutive load failures of each `Cacheable`, and when its circuit opened,
+/// see [`CacheBuilder::circuit_breaker()`].
+#[derive(Debug, Default)]
+struct Breakers(std::sync::Mutex<HashMap<usize, (usize, Option<Instant>)>>);
+
+impl Breakers {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<usize, (usize, Option<Instant>)>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether the circuit of the `Cacheable` opened within `cooldown`.
+    fn is_open(&self, type_id: usize, cooldown: Duration) -> bool {
+        self.lock()
+            .get(&type_id)
+            .and_then(|&(_, opened_at)| opened_at)
+            .is_some_and(|opened_at| opened_at.elapsed() < cooldown)
+    }
+
+    /// Record a load of the `Cacheable`, opening its circuit once failed `failures` times in a row.
+    fn record(&self, type_id: usize, ok: bool, failures: usize) {
+        let mut breakers = self.lock();
+        if ok {
+            breakers.remove(&type_id);
+            return;
+        }
+        let (failed, opened_at) = breakers.entry(type_id).or_default();
+        *failed += 1;
+        if *failed >= failures {
+            *opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// The subscribers of the updates of each `Cacheable`, see [`Cache::watch()`].
+#[derive(Debug, Default)]
+struct Watchers(std::sync::Mutex<HashMap<usize, Vec<SyncSender<()>>>>);
+
+impl Watchers {
+    fn subscribe(&self, type_id: usize) -> Receiver<()> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        let mut watchers = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        watchers.entry(type_id).or_default().push(tx);
+        rx
+    }
+
+    /// Notify the subscribers of the `Cacheable`, forgetting the disconnected ones.
+    /// A pending notification is not repeated.
+    fn notify(&self, type_id: usize) {
+        let mut watchers = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(txs) = watchers.get_mut(&type_id) else {
+            return;
+        };
+        txs.retain(|tx| !matches!(tx.try_send(()), Err(TrySendError::Disconnected(_))));
+        if txs.is_empty() {
+            watchers.remove(&type_id);
+        }
+    }
+}
+
+/// The number of epochs of [`Replicas`], each shared by the `Cacheable`s hashed into it.
+const REPLICA_EPOCHS: usize = 64;
+
+/// A unique id of each cache among the replicas of a thread.
+static REPLICAS_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// A copy of a `Cacheable` kept by a thread, see [`Cache::get_replica()`].
+struct Replica {
+    /// The full `TypeId`, since the truncated ones of different `Cacheable`s may be the same.
+    type_id: TypeId,
+    epoch: usize,
+    value: std::sync::Arc<dyn Any + Send + Sync>,
+}
+
+thread_local! {
+    /// The replicas of this thread by the id of their cache, the most recently kept first.
+    static REPLICAS: std::cell::RefCell<HashMap<usize, VecDeque<Replica>>> = Default::default();
+}
+
+/// The epochs outdating the replicas kept by threads, see [`CacheBuilder::read_replicas()`].
+#[derive(Debug)]
+struct Replicas {
+    id: usize,
+    epochs: [std::sync::atomic::AtomicUsize; REPLICA_EPOCHS],
+}
+
+impl Default for Replicas {
+    fn default() -> Self {
+        Self {
+            id: REPLICAS_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            epochs: std::array::from_fn(|_| std::sync::atomic::AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl Replicas {
+    fn epoch(&self, type_id: usize) -> usize {
+        self.epochs[type_id % REPLICA_EPOCHS].load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    fn bump(&self, type_id: usize) {
+        self.epochs[type_id % REPLICA_EPOCHS].fetch_add(1, std::sync::atomic::Ordering::Release);
+    }
+
+    fn bump_all(&self) {
+        for epoch in self.epochs.iter() {
+            epoch.fetch_add(1, std::sync::atomic::Ordering::Release);
+        }
+    }
+
+    /// The replica of T of this thread if still up to date.
+    fn lookup<T: Any + Send + Sync>(&self) -> Option<std::sync::Arc<T>> {
+        let epoch = self.epoch(type_id_usize::<T>());
+        REPLICAS.with_borrow(|replicas| {
+            let replica = replicas
+                .get(&self.id)?
+                .iter()
+                .find(|r| r.type_id == TypeId::of::<T>() && r.epoch == epoch)?;
+            replica.value.clone().downcast().ok()
+        })
+    }
+
+    /// Keep the replica of T in this thread, replacing the outdated one and the oldest one beyond `capacity`.
+    fn keep<T: Any + Send + Sync>(&self, epoch: usize, value: std::sync::Arc<T>, capacity: usize) {
+        let type_id = TypeId::of::<T>();
+        REPLICAS.with_borrow_mut(|replicas| {
+            let kept = replicas.entry(self.id).or_default();
+            kept.retain(|r| r.type_id != type_id);
+            if capacity == 0 {
+                return;
+            }
+            kept.truncate(capacity - 1);
+            kept.push_front(Replica {
+                type_id,
+                epoch,
+                value,
+            });
+        });
+    }
+}
+
+impl Drop for Replicas {
+    fn drop(&mut self) {
+        // only the replicas of the dropping thread, the others are dropped with their threads,
+        // and after the borrow, in case a replica owns a cache
+        let replicas = REPLICAS.try_with(|replicas| {
+            replicas
+                .try_borrow_mut()
+                .ok()
+                .and_then(|mut replicas| replicas.remove(&self.id))
+        });
+        drop(replicas);
+    }
+}
+
+/// The `Cacheable`s derived from others, see [`Cache::depends()`].
+#[derive(Debug, Default)]
+struct Deps {
+    /// Whether any dependency is declared, to skip the lock otherwise.
+    declared: std::sync::atomic::AtomicBool,
+    graph: std::sync::Mutex<DepGraph>,
+}
+
+#[derive(Debug, Default)]
+struct DepGraph {
+    /// The `Cacheable`s each one is derived from.
+    dependencies: HashMap<usize, Vec<usize>>,
+    /// The `Cacheable`s derived from each one.
+    dependents: HashMap<usize, Vec<usize>>,
+    /// The `Cacheable`s whose dependencies changed since they were loaded.
+    stale: HashSet<usize>,
+}
+
+impl Deps {
+    fn lock(&self) -> std::sync::MutexGuard<'_, DepGraph> {
+        self.graph.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn insert(&self, dependent: usize, dependency: usize) {
+        let mut graph = self.lock();
+        let dependencies = graph.dependencies.entry(dependent).or_default();
+        if !dependencies.contains(&dependency) {
+            dependencies.push(dependency);
+            graph
+                .dependents
+                .entry(dependency)
+                .or_default()
+                .push(dependent);
+        }
+        self.declared
+            .store(true, std::sync::atomic::Ordering::Release);
+    }
+
+    fn declared(&self) -> bool {
+        self.declared.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    fn invalidate(&self, type_id: usize) {
+        self.lock().stale.insert(type_id);
+    }
+
+    /// Invalidate the `Cacheable`s derived from the one, directly or not.
+    fn invalidate_dependents(&self, type_id: usize) {
+        if !self.declared() {
+            return;
+        }
+        let mut graph = self.lock();
+        let mut pending = graph.dependents.get(&type_id).cloned().unwrap_or_default();
+        while let Some(dependent) = pending.pop() {
+            if graph.stale.insert(dependent) {
+                pending.extend(graph.dependents.get(&dependent).into_iter().flatten());
+            }
+        }
+    }
+
+    fn is_stale(&self, type_id: usize) -> bool {
+        self.declared() && self.lock().stale.contains(&type_id)
+    }
+
+    /// Take the invalidation of the `Cacheable`, returning its dependencies, direct or not,
+    /// each after its own dependencies.
+    fn take_stale(&self, type_id: usize) -> Option<Vec<usize>> {
+        if !self.declared() {
+            return None;
+        }
+        let mut graph = self.lock();
+        if !graph.stale.remove(&type_id) {
+            return None;
+        }
+        let mut order = vec![];
+        graph.visit(type_id, &mut HashSet::new(), &mut order);
+        order.pop();
+        Some(order)
+    }
+
+    /// All the `Cacheable`s with dependencies declared, each after its dependencies.
+    fn order(&self) -> Vec<usize> {
+        if !self.declared() {
+            return vec![];
+        }
+        let graph = self.lock();
+        let mut visited = HashSet::new();
+        let mut order = vec![];
+        for &type_id in graph.dependencies.keys() {
+            graph.visit(type_id, &mut visited, &mut order);
+        }
+        order
+    }
+}
+
+impl DepGraph {
+    /// Push the dependencies of the `Cacheable` not visited yet, then itself, breaking cycles anywhere.
+    fn visit(&self, type_id: usize, visited: &mut HashSet<usize>, order: &mut Vec<usize>) {
+        if !visited.insert(type_id) {
+            return;
+        }
+        for &dependency in self.dependencies.get(&type_id).into_iter().flatten() {
+            self.visit(dependency, visited, order);
+        }
+        order.push(type_id);
+    }
+}
+
+/// Dirty `Cacheable`s evicted but not yet written back, see [`CacheBuilder::write_buffer()`].
+#[derive(Debug)]
+struct WriteBuffer {
+    capacity: usize,
+    pending: std::sync::Mutex<Pending>,
+    changed: std::sync::Condvar,
+}
+
+#[derive(Default)]
+struct Pending {
+    values: VecDeque<(usize, Box<DynValue>)>,
+    /// The type ids of the `Cacheable`s being written back in background.
+    storing: Vec<usize>,
+    closed: bool,
+}
+
+impl std::fmt::Debug for Pending {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pending")
+            .field("values", &self.values.len())
+            .field("storing", &self.storing)
+            .field("closed", &self.closed)
+            .finish()
+    }
+}
+
+impl WriteBuffer {
+    /// Create the buffer with `workers` threads writing back the buffered `Cacheable`s until closed.
+    fn spawn(
+        capacity: usize,
+        workers: usize,
+        backend: Option<SharedBackend>,
+    ) -> (
+        std::sync::Arc<Self>,
+        std::sync::Arc<Hook<StoreErrorHandler>>,
+    ) {
+        let buffer = std::sync::Arc::new(Self {
+            capacity,
+            pending: Default::default(),
+            changed: Default::default(),
+        });
+        let on_store_error = std::sync::Arc::new(Hook::<StoreErrorHandler>::default());
+        for _ in 0..workers.max(1) {
+            let (drain, handler) = (buffer.clone(), on_store_error.clone());
+            let backend = backend.clone();
+            std::thread::spawn(move || {
+                while let Some((type_id, value)) = drain.next() {
+                    let stored = value.store_since(None, backend.as_ref());
+                    if let Err(e) = stored.as_ref() {
+                        warn!(
+                            "failed to write {} back in background: {e}",
+                            value.type_name()
+                        );
+                        if let Some(handler) = handler.get().as_deref() {
+                            handler(value.type_name(), e);
+                        }
+                    }
+                    drain.done(type_id, stored.err().map(|_| value));
+                }
+            });
+        }
+        (buffer, on_store_error)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Buffer the value, or give it back if full.
+    fn push(&self, type_id: usize, value: Box<DynValue>) -> Result<(), Box<DynValue>> {
+        let mut pending = self.lock();
+        if pending.closed || pending.values.len() >= self.capacity {
+            return Err(value);
+        }
+        pending.values.push_back((type_id, value));
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Buffer the value again after a failed write-back, regardless of the capacity.
+    fn put_back(&self, type_id: usize, value: Box<DynValue>) {
+        self.lock().values.push_front((type_id, value));
+    }
+
+    /// Take the buffered value out, waiting for its write-back in background if any.
+    fn take(&self, type_id: usize) -> Option<Box<DynValue>> {
+        let mut pending = self.lock();
+        while pending.storing.contains(&type_id) {
+            pending = self
+                .changed
+                .wait(pending)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        let i = pending.values.iter().position(|(t, _)| *t == type_id)?;
+        pending.values.remove(i).map(|(_, value)| value)
+    }
+
+    /// Take all the buffered values out, waiting for the write-back in background if any.
+    fn take_all(&self) -> VecDeque<(usize, Box<DynValue>)> {
+        let mut pending = self.lock();
+        while !pending.storing.is_empty() {
+            pending = self
+                .changed
+                .wait(pending)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        std::mem::take(&mut pending.values)
+    }
+
+    /// The next value to write back in background, None if closed.
+    fn next(&self) -> Option<(usize, Box<DynValue>)> {
+        let mut pending = self.lock();
+        loop {
+            if pending.closed {
+                return None;
+            }
+            // never write back the same `Cacheable` in two threads at once
+            let storing = &pending.storing;
+            if let Some(i) = pending
+                .values
+                .iter()
+                .position(|(t, _)| !storing.contains(t))
+            {
+                let (type_id, value) = pending.values.remove(i).unwrap();
+                pending.storing.push(type_id);
+                return Some((type_id, value));
+            }
+            pending = self
+                .changed
+                .wait(pending)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// The write-back in background is done, the value is given back if failed.
+    fn done(&self, type_id: usize, failed: Option<Box<DynValue>>) {
+        let mut pending = self.lock();
+        pending.storing.retain(|&t| t != type_id);
+        let retry = failed.is_some();
+        if let Some(value) = failed {
+            pending.values.push_back((type_id, value));
+        }
+        self.changed.notify_all();
+        if retry {
+            // do not spin on a failing storage
+            drop(
+                self.changed
+                    .wait_timeout(pending, Duration::from_millis(100)),
+            );
+        }
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+/// A callback which can be registered at runtime.
+struct Hook<F: ?Sized>(std::sync::RwLock<Option<Box<F>>>);
+
+impl<F: ?Sized> Default for Hook<F> {
+    fn default() -> Self {
+        Self(std::sync::RwLock::new(None))
     }
 }
 
-impl<T> CacheableExt for T where T: Cacheable + Sized {}
+impl<F: ?Sized> std::fmt::Debug for Hook<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Hook").field(&self.get().is_some()).finish()
+    }
+}
+
+impl<F: ?Sized> Hook<F> {
+    fn get(&self) -> std::sync::RwLockReadGuard<'_, Option<Box<F>>> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set(&self, f: Box<F>) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = Some(f);
+    }
+}
 
 #[cfg(loom)]
 #[derive(Debug)]
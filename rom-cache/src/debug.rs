@@ -0,0 +1,88 @@
+//! Describe the state of a [`Cache`] as JSON, e.g. for an internal `/debug/cache` endpoint.
+//! Enabled by feature `serde_json`.
+//!
+//! ```ignore
+//! # use rom_cache::Cache;
+//! async fn debug_cache(State(cache): State<Cache<16, 4>>) -> Json<serde_json::Value> {
+//!     Json(cache.debug_json().unwrap_or_default())
+//! }
+//! ```
+//!
+//! The values are hidden unless the `Cacheable` opts in by [`Cacheable::debug_json()`].
+
+use crate::cache::{Cache, EntryInfo};
+#[cfg(doc)]
+use crate::cache::{Cacheable, GroupReport};
+use crate::CacheResult;
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime};
+
+impl<const G: usize, const L: usize> Cache<G, L> {
+    /// Describe the cache as JSON: each `CacheGroup` with its statistics (see [`GroupReport`]),
+    /// and its occupied `CacheLine`s with the type names, LRU positions and flags.
+    /// The value of a `Cacheable` is included if it opts in by [`Cacheable::debug_json()`] and is not being written.
+    ///
+    /// Each `CacheGroup` is locked in turn, so it is a snapshot of each group but not of the whole cache.
+    pub fn debug_json(&self) -> CacheResult<Value> {
+        let report = self.tuning_report()?;
+        let mut groups = Vec::with_capacity(G);
+        for (g, stats) in report.groups.iter().enumerate() {
+            let mut lines = vec![];
+            self.inspect(g, |info, flags, value| {
+                let mut line = entry(&info);
+                line["flags"] = json!({
+                    "dirty": info.dirty,
+                    "writing": flags.writing,
+                    "in_use": flags.in_use,
+                    "stale": flags.stale,
+                    "once": flags.once,
+                    "hot": flags.hot,
+                });
+                if let Some(value) = value.and_then(|value| value.debug_json()) {
+                    line["value"] = value;
+                }
+                lines.push(line);
+            })?;
+            groups.push(json!({
+                "index": g,
+                "hits": stats.hits,
+                "misses": stats.misses,
+                "conflicts": stats.conflicts,
+                "occupied": stats.occupied,
+                "mean_reuse_distance": stats.mean_reuse_distance,
+                "lfu": stats.lfu,
+                "types": stats.types,
+                "lines": lines,
+            }));
+        }
+        let (hits, misses) = report.groups.iter().fold((0, 0), |(hits, misses), g| {
+            (hits + g.hits, misses + g.misses)
+        });
+        Ok(json!({
+            "shape": { "groups": G, "lines": L },
+            "hits": hits,
+            "misses": misses,
+            "collisions": report.collisions,
+            "groups": groups,
+        }))
+    }
+}
+
+/// The [`EntryInfo`] of a `CacheLine` as JSON, with the durations in milliseconds,
+/// and the times in milliseconds since the UNIX epoch.
+fn entry(info: &EntryInfo) -> Value {
+    let millis = |d: Duration| d.as_millis() as u64;
+    let since_epoch = |t: SystemTime| t.duration_since(SystemTime::UNIX_EPOCH).ok().map(millis);
+    json!({
+        "line": info.line,
+        "type_name": info.type_name,
+        "namespace": info.namespace.as_deref(),
+        "lru": info.lru,
+        "priority": info.priority,
+        "weight": info.weight,
+        "age_ms": info.age.map(millis),
+        "ttl_ms": info.ttl.map(millis),
+        "accessed_at_ms": info.accessed_at.and_then(since_epoch),
+        "dirtied_at_ms": info.dirtied_at.and_then(since_epoch),
+    })
+}
@@ -11,7 +11,7 @@ pub enum CacheError {
     /// Cache is missing even loaded. This happens due to concurrency.
     #[error("Cache is missing.")]
     Missing,
-    /// Lock poisoned due to LockGuard-holder panic.
+    /// Lock poisoned due to LockGuard-holder panic. Never returned with the `parking_lot` feature.
     #[error("Lock poisoned")]
     Poisoned,
     /// The CacheLine chosen to evict is locked. Consider dropping lock you get, trying again or increasing the capacity of the cache.
@@ -20,6 +20,53 @@ pub enum CacheError {
     /// The CacheLine is locked.
     #[error("The CacheLine is locked.")]
     Locked,
+    /// The CacheLine is dirty.
+    #[error("The CacheLine is dirty.")]
+    Dirty,
+    /// The cache is read only, so that no mutable access is allowed.
+    #[error("The cache is read only.")]
+    ReadOnly,
+    /// The `Cacheable` is not registered by [`Cache::register()`](crate::Cache::register()).
+    #[error("The Cacheable is not registered.")]
+    Unregistered,
+    /// More registered `Cacheable`s are assigned to the same `CacheGroup` than it can hold.
+    #[error("{type_name} collides with {colliding:?} in the same CacheGroup, consider increasing the capacity of the cache.")]
+    Collision {
+        /// The type name of the `Cacheable` being registered.
+        type_name: &'static str,
+        /// The type names of the registered `Cacheable`s in the same `CacheGroup`.
+        colliding: Vec<&'static str>,
+    },
+    /// The `CacheLine` found for a `Cacheable` holds another type, or the same one of another namespace
+    /// (see [`Cache::scope()`](crate::Cache::scope())), since only the lower 64 bits of the `TypeId`s,
+    /// mixed with the hash of the namespace, are compared to find it.
+    #[error("{found} is cached in place of {expected}, their type ids collide.")]
+    TypeMismatch {
+        /// The type name of the `Cacheable` requested.
+        expected: &'static str,
+        /// The type name of the `Cacheable` cached.
+        found: &'static str,
+    },
+    /// The `Cacheable` missed is loaded too often, and no stale one is cached to serve instead,
+    /// see [`CacheBuilder::load_rate()`](crate::CacheBuilder::load_rate()).
+    #[error("Loading {type_name} is rate limited.")]
+    RateLimited {
+        /// The type name of the `Cacheable` missed.
+        type_name: &'static str,
+    },
+    /// Loading the `Cacheable` missed failed repeatedly, so it is not loaded until the cooldown ends,
+    /// and no stale one is cached to serve instead, see [`CacheBuilder::circuit_breaker()`](crate::CacheBuilder::circuit_breaker()).
+    #[error("Loading {type_name} failed repeatedly, the circuit is open.")]
+    CircuitOpen {
+        /// The type name of the `Cacheable` missed.
+        type_name: &'static str,
+    },
+    /// Loading the `Cacheable` took longer than allowed, see [`CacheBuilder::load_timeout()`](crate::CacheBuilder::load_timeout()).
+    #[error("Loading {type_name} timed out.")]
+    Timeout {
+        /// The type name of the `Cacheable` being loaded.
+        type_name: &'static str,
+    },
 }
 
 /// A specialized `Result` type for this crate.
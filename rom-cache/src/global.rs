@@ -0,0 +1,129 @@
+//! Process-wide caches, initialized lazily on first use.
+//!
+//! The free functions access the default process-wide `Cache<GROUPS, LINES>`,
+//! which can be configured by [`init()`] before first used:
+//!
+//! ```ignore
+//! # use rom_cache::{global, Cache};
+//! global::init(Cache::builder().max_dirty(4).build()).ok();
+//! global::get_mut::<String>().unwrap().push_str("hello");
+//! assert_eq!(*global::get::<String>().unwrap(), "hello");
+//! ```
+//!
+//! Declare a [`GlobalCache`] for another shape or configuration:
+//!
+//! ```ignore
+//! # use rom_cache::{global::GlobalCache, Cache};
+//! static CACHE: GlobalCache<4, 2> = GlobalCache::with(|| Cache::builder().read_only(true).build());
+//! CACHE.get::<String>().unwrap();
+//! ```
+
+use crate::cache::{Cache, CacheMut, CacheRef, Cacheable};
+use crate::CacheResult;
+use std::ops::Deref;
+use std::sync::OnceLock;
+
+/// The number of `CacheGroup`s of the default process-wide cache.
+pub const GROUPS: usize = 16;
+/// The number of `CacheLine`s in each `CacheGroup` of the default process-wide cache.
+pub const LINES: usize = 4;
+
+static GLOBAL: GlobalCache<GROUPS, LINES> = GlobalCache::new();
+
+/// A [`Cache`] to be put in a `static`, created by `init` on first use and never dropped.
+/// It dereferences to the `Cache`.
+///
+/// Dirty `Cacheable`s are written back on eviction, by [`Cache::flush_all_registered()`]
+/// or [`Cache::flush_on_drop()`] guards, but not at exit, since statics are never dropped.
+pub struct GlobalCache<const G: usize, const L: usize> {
+    cache: OnceLock<Cache<G, L>>,
+    init: fn() -> Cache<G, L>,
+}
+
+impl<const G: usize, const L: usize> GlobalCache<G, L> {
+    /// A global cache created by `Cache::default()`.
+    pub const fn new() -> Self {
+        Self::with(Cache::default)
+    }
+
+    /// A global cache created by `init`, e.g. building it with [`Cache::builder()`].
+    pub const fn with(init: fn() -> Cache<G, L>) -> Self {
+        Self {
+            cache: OnceLock::new(),
+            init,
+        }
+    }
+
+    /// Use `cache` instead of the one created by `init`,
+    /// returning it back if the global cache has been used already.
+    pub fn init(&self, cache: Cache<G, L>) -> Result<(), Cache<G, L>> {
+        self.cache.set(cache)
+    }
+
+    /// The cache, created on first use.
+    pub fn cache(&self) -> &Cache<G, L> {
+        self.cache.get_or_init(self.init)
+    }
+
+    /// Write back all dirty `CacheLine`s and empty the ones not being used, and remove the injected faults,
+    /// so that tests sharing the global cache start over. Nothing happens if not used yet.
+    #[cfg(feature = "test-util")]
+    pub fn reset(&self) -> Result<(), Vec<(String, std::io::Error)>> {
+        let Some(cache) = self.cache.get() else {
+            return Ok(());
+        };
+        cache.faults().clear();
+        cache.bump_generation()?;
+        // stale anyway, only fails if poisoned
+        cache.purge_clean().ok();
+        Ok(())
+    }
+}
+
+impl<const G: usize, const L: usize> Default for GlobalCache<G, L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const G: usize, const L: usize> Deref for GlobalCache<G, L> {
+    type Target = Cache<G, L>;
+
+    fn deref(&self) -> &Self::Target {
+        self.cache()
+    }
+}
+
+impl<const G: usize, const L: usize> std::fmt::Debug for GlobalCache<G, L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GlobalCache")
+            .field("cache", &self.cache.get())
+            .finish()
+    }
+}
+
+/// Use `cache` as the default process-wide cache, see [`GlobalCache::init()`].
+pub fn init(cache: Cache<GROUPS, LINES>) -> Result<(), Cache<GROUPS, LINES>> {
+    GLOBAL.init(cache)
+}
+
+/// The default process-wide cache, created by `Cache::default()` on first use unless [`init()`]ed.
+pub fn cache() -> &'static Cache<GROUPS, LINES> {
+    GLOBAL.cache()
+}
+
+/// [`Cache::get()`] from the default process-wide cache.
+pub fn get<T: Cacheable + Send + Sync + Default>() -> CacheResult<CacheRef<'static, T>> {
+    cache().get()
+}
+
+/// [`Cache::get_mut()`] from the default process-wide cache.
+pub fn get_mut<T: Cacheable + Send + Sync + Default>() -> CacheResult<CacheMut<'static, T>> {
+    cache().get_mut()
+}
+
+/// [`GlobalCache::reset()`] the default process-wide cache.
+#[cfg(feature = "test-util")]
+pub fn reset() -> Result<(), Vec<(String, std::io::Error)>> {
+    GLOBAL.reset()
+}
@@ -5,10 +5,71 @@
     elided_lifetimes_in_paths
 )]
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
-#![cfg_attr(feature = "nightly", feature(trait_upcasting))]
 
+/// `log::warn!` with feature `log`, or nothing.
+macro_rules! warn {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::warn!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+/// `log::debug!` with feature `log`, or nothing.
+macro_rules! debug {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        ::log::debug!($($arg)+);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+pub mod alloc;
+#[cfg(all(feature = "axum", not(loom)))]
+pub mod axum;
+pub mod backend;
+#[cfg(all(feature = "bevy", not(loom)))]
+pub mod bevy;
+pub mod builder;
 pub mod cache;
+#[cfg(feature = "serde_json")]
+pub mod debug;
 pub mod error;
+#[cfg(not(loom))]
+pub mod global;
+pub mod local;
+#[cfg(not(loom))]
+pub mod memo;
+pub mod namespace;
+pub mod numa;
+#[cfg(all(feature = "cgroup", target_os = "linux", not(loom)))]
+mod pressure;
+pub mod scope;
+#[cfg(not(loom))]
+pub mod service;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod txn;
 
-pub use cache::{Cache, Cacheable};
+pub use alloc::AllocHook;
+pub use backend::{Backend, StoreBatch};
+pub use builder::CacheBuilder;
+pub use cache::{
+    Cache, Cacheable, ConflictPolicy, EntryInfo, EvictionEvent, GroupReport, LongHeldEvent,
+    MemoryPressure, OwnedCacheRef, RefreshPolicy, ThrashEvent, TuningReport,
+};
 pub use error::*;
+pub use local::LocalCache;
+#[cfg(not(loom))]
+pub use memo::CachedFn;
+pub use namespace::{Namespace, Quota};
+pub use scope::ScopedCache;
+#[cfg(not(loom))]
+pub use service::CacheService;
+pub use txn::Txn;
@@ -0,0 +1,243 @@
+//! Single-threaded cache data structure
+
+use crate::cache::{age, find_slot, type_id_usize, CacheSlot, DynCacheable, Shape};
+use crate::error::CacheResult;
+use crate::{CacheError, Cacheable};
+use std::any::TypeId;
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+/// A single-threaded cache storage structure, without any atomic or lock.
+/// - G: the number of cache groups
+/// - L: the number of cache lines in each group
+///
+/// It works the same as [`Cache`](crate::Cache), but `Cacheable`s need not be `Send + Sync`,
+/// and the `LocalCache` itself is neither `Send` nor `Sync`.
+#[derive(Default, Debug, Clone)]
+pub struct LocalCache<const G: usize, const L: usize> {
+    inner: Rc<LocalCacheInner<G, L>>,
+}
+
+impl<const G: usize, const L: usize> LocalCache<G, L> {
+    /// Retrieve a Cacheable from the cache. Use Default if `Cacheable::load()` failed.
+    /// - CacheError::Busy: cache miss, the CacheLine chosen to evict is being used,
+    ///   or called from `Cacheable::load()` or `Cacheable::store()` of a `Cacheable` in the same group.
+    /// - CacheError::Locked: cache hit, but the CacheLine for T is being written.
+    /// - CacheError::TypeMismatch: the CacheLine found holds another type, whose truncated type id is the same.
+    pub fn get<T: Cacheable + Default>(&self) -> CacheResult<LocalRef<'_, T>> {
+        let line = self.inner.load::<T>()?;
+        let value = line.value.try_borrow().map_err(|_| CacheError::Locked)?;
+        let inner = Ref::filter_map(value, downcast_ref)
+            .map_err(|value| mismatch::<T>(value.as_deref()))?;
+        Ok(LocalRef { inner })
+    }
+
+    /// Retrieve a mut Cacheable from the cache. Use Default if `Cacheable::load()` failed.
+    /// - CacheError::Busy: cache miss, the CacheLine chosen to evict is being used,
+    ///   or called from `Cacheable::load()` or `Cacheable::store()` of a `Cacheable` in the same group.
+    /// - CacheError::Locked: cache hit, but the CacheLine for T is being read or written.
+    /// - CacheError::TypeMismatch: the CacheLine found holds another type, whose truncated type id is the same.
+    pub fn get_mut<T: Cacheable + Default>(&self) -> CacheResult<LocalMut<'_, T>> {
+        let line = self.inner.load::<T>()?;
+        let value = line
+            .value
+            .try_borrow_mut()
+            .map_err(|_| CacheError::Locked)?;
+        let inner = RefMut::filter_map(value, downcast_mut)
+            .map_err(|value| mismatch::<T>(value.as_deref()))?;
+        Ok(LocalMut {
+            inner,
+            dirty: &line.dirty,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct LocalCacheInner<const G: usize, const L: usize> {
+    groups: [LocalGroup<L>; G],
+}
+
+impl<const G: usize, const L: usize> Default for LocalCacheInner<G, L> {
+    fn default() -> Self {
+        let () = Shape::<G, L>::VALID;
+        let groups = (0..G).map(|_| LocalGroup::default()).collect::<Vec<_>>();
+        Self {
+            groups: groups.try_into().unwrap(),
+        }
+    }
+}
+
+impl<const G: usize, const L: usize> LocalCacheInner<G, L> {
+    /// load Cacheable into LocalLine and update LRU
+    fn load<T: Cacheable + Default>(&self) -> CacheResult<&LocalValue> {
+        let group = match T::GROUP_HINT {
+            Some(hint) => &self.groups[hint % G],
+            None => &self.groups[type_id_usize::<T>() % G],
+        };
+        // `T::load()` and `store()` run with the lines borrowed, so accessing the group from them fails
+        let mut lines = group.lines.try_borrow_mut().map_err(|_| CacheError::Busy)?;
+        let values = &group.values;
+        let slot = find_slot::<T, _>(lines.iter().map(|l| (l.type_id, l.priority, l.lru)));
+        let (i, lru) = match slot {
+            Some(CacheSlot::Hit(i)) => {
+                if let Some((type_id, found)) = lines[i].exact {
+                    if type_id != TypeId::of::<T>() {
+                        return Err(CacheError::TypeMismatch {
+                            expected: std::any::type_name::<T>(),
+                            found,
+                        });
+                    }
+                }
+                (i, lines[i].lru)
+            }
+            Some(CacheSlot::Empty(i)) => {
+                *values[i].value.borrow_mut() = Some(Box::new(lines[i].fill::<T>()));
+                (i, usize::MAX)
+            }
+            Some(CacheSlot::Evict(i)) => {
+                let mut value = values[i]
+                    .value
+                    .try_borrow_mut()
+                    .map_err(|_| CacheError::Busy)?;
+                if values[i].dirty.get() {
+                    value.as_deref().unwrap().store()?;
+                    values[i].dirty.set(false);
+                }
+                let lru = lines[i].lru;
+                *value = Some(Box::new(lines[i].fill::<T>()));
+                (i, lru)
+            }
+            None => unreachable!(),
+        };
+        age(lines.iter_mut().map(|l| &mut l.lru), lru);
+        lines[i].lru = 0;
+        Ok(&values[i])
+    }
+}
+
+#[derive(Debug)]
+struct LocalGroup<const L: usize> {
+    lines: RefCell<[LocalLine; L]>,
+    values: [LocalValue; L],
+}
+
+impl<const L: usize> Default for LocalGroup<L> {
+    fn default() -> Self {
+        let lines = (0..L).map(|_| LocalLine::default()).collect::<Vec<_>>();
+        let values = (0..L).map(|_| LocalValue::default()).collect::<Vec<_>>();
+        Self {
+            lines: RefCell::new(lines.try_into().unwrap()),
+            values: values.try_into().unwrap(),
+        }
+    }
+}
+
+impl<const L: usize> Drop for LocalGroup<L> {
+    fn drop(&mut self) {
+        for value in self.values.iter() {
+            if value.dirty.get() {
+                let value = value.value.borrow();
+                let value = value.as_deref().unwrap();
+                if let Err(e) = value.store() {
+                    warn!("failed to write {} back: {e}", value.type_name());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LocalLine {
+    lru: usize,
+    type_id: usize,
+    /// The whole `TypeId` and the name of the `Cacheable`, `type_id` is truncated.
+    exact: Option<(TypeId, &'static str)>,
+    priority: u8,
+}
+
+impl LocalLine {
+    /// Load T for this `LocalLine`.
+    fn fill<T: Cacheable + Default>(&mut self) -> T {
+        self.type_id = type_id_usize::<T>();
+        self.exact = Some((TypeId::of::<T>(), std::any::type_name::<T>()));
+        self.priority = T::PRIORITY;
+        T::load()
+            .inspect_err(|e| {
+                warn!(
+                    "failed to load {}, using the default: {e}",
+                    std::any::type_name::<T>()
+                )
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+struct LocalValue {
+    dirty: Cell<bool>,
+    value: RefCell<Option<Box<dyn DynCacheable>>>,
+}
+
+impl std::fmt::Debug for LocalValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalValue")
+            .field("dirty", &self.dirty)
+            .finish()
+    }
+}
+
+fn downcast_ref<T: Cacheable>(value: &Option<Box<dyn DynCacheable>>) -> Option<&T> {
+    value.as_deref()?.as_any().downcast_ref::<T>()
+}
+
+fn downcast_mut<T: Cacheable>(value: &mut Option<Box<dyn DynCacheable>>) -> Option<&mut T> {
+    value.as_deref_mut()?.as_any_mut().downcast_mut::<T>()
+}
+
+/// The `LocalLine` found holds another type than T, whose truncated type id is the same.
+fn mismatch<T: Cacheable>(found: Option<&dyn DynCacheable>) -> CacheError {
+    CacheError::TypeMismatch {
+        expected: std::any::type_name::<T>(),
+        found: found.map_or("nothing", |value| value.type_name()),
+    }
+}
+
+/// An immutable ref wrapper to a cacheable object in [`LocalCache`].
+///
+/// `LocalCache::get_mut::<T>()` will return `CacheError::Locked` before this ref dropped.
+pub struct LocalRef<'a, T> {
+    inner: Ref<'a, T>,
+}
+
+impl<T> Deref for LocalRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// A mutable ref wrapper to a cacheable object in [`LocalCache`].
+///
+/// `LocalCache::get::<T>()` and `LocalCache::get_mut::<T>()`
+/// will return `CacheError::Locked` before this mut ref dropped.
+pub struct LocalMut<'a, T> {
+    inner: RefMut<'a, T>,
+    dirty: &'a Cell<bool>,
+}
+
+impl<T> Deref for LocalMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for LocalMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.dirty.set(true);
+        &mut self.inner
+    }
+}
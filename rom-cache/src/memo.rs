@@ -0,0 +1,161 @@
+//! Memoization of function results in a [`Cache`].
+//!
+//! [`memo!`](crate::memo!) declares a function whose results are cached by its arguments,
+//! in the default process-wide cache or in the given one:
+//!
+//! ```ignore
+//! # use rom_cache::memo;
+//! memo! {
+//!     fn fib(n: u64) -> u64 {
+//!         if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+//!     }
+//! }
+//! static CACHE: GlobalCache<4, 2> = GlobalCache::new();
+//! memo! {
+//!     CACHE;
+//!     pub fn parse(src: String) -> Vec<Token> { lex(&src) }
+//! }
+//! ```
+//!
+//! A [`CachedFn`] memoizes a `fn` pointer explicitly.
+//!
+//! The results are kept in memory only: they are never written back, nor loaded from a
+//! [`Backend`](crate::Backend), since a function is only identified by its address, which differs between processes.
+//! They share the eviction and the limits of the cache, and are computed again once evicted.
+
+use crate::cache::{Cache, Cacheable};
+use crate::global::{GROUPS, LINES};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// The results of the functions from `Args` to `R`, keyed by the function and its arguments.
+/// A single `Cacheable` for each signature, loaded empty and never written back,
+/// so that the results are dropped when it is evicted.
+struct Memo<Args, R>(HashMap<(usize, Args), R>);
+
+impl<Args, R> Default for Memo<Args, R> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl<Args: 'static, R: 'static> Cacheable for Memo<Args, R> {
+    fn load() -> std::io::Result<Self> {
+        Ok(Self::default())
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn weight(&self) -> usize {
+        std::mem::size_of::<Self>() + self.0.capacity() * std::mem::size_of::<((usize, Args), R)>()
+    }
+}
+
+/// A `fn(Args) -> R` whose results are cached in a `Cache<G, L>` by the hash of `Args`.
+///
+/// The results of all the functions with the same signature share a `CacheLine`,
+/// evicted, limited by [`CacheBuilder::max_bytes()`](crate::CacheBuilder::max_bytes())
+/// and forked or absorbed as a whole, the same as any other `Cacheable`.
+///
+/// The cache is not locked while `f` runs, so that `f` may call itself recursively.
+/// Concurrent misses of the same arguments thus compute the result more than once.
+pub struct CachedFn<Args, R, const G: usize = GROUPS, const L: usize = LINES> {
+    cache: Cache<G, L>,
+    f: fn(Args) -> R,
+}
+
+impl<Args, R, const G: usize, const L: usize> CachedFn<Args, R, G, L>
+where
+    Args: Hash + Eq + Clone + Send + Sync + 'static,
+    R: Clone + Send + Sync + 'static,
+{
+    /// Memoize `f` in `cache`, in memory only.
+    pub fn new(cache: &Cache<G, L>, f: fn(Args) -> R) -> Self {
+        Self {
+            cache: cache.clone(),
+            f,
+        }
+    }
+
+    /// The result of `f(args)`, cached unless computed for the same `args` already.
+    ///
+    /// Caching is best effort: if the results cannot be accessed, e.g. being written by another thread
+    /// or the cache is read only, `f` is called without caching.
+    pub fn call(&self, args: Args) -> R {
+        let key = (self.f as usize, args);
+        if let Some(res) = self
+            .cache
+            .with(|memo: &Memo<Args, R>| memo.0.get(&key).cloned())
+            .ok()
+            .flatten()
+        {
+            return res;
+        }
+        let res = (self.f)(key.1.clone());
+        if let Ok(mut memo) = self.cache.get_mut::<Memo<Args, R>>() {
+            memo.0.insert(key, res.clone());
+        }
+        res
+    }
+
+    /// Drop the cached result of `f(args)`, returning it if cached.
+    pub fn forget(&self, args: &Args) -> crate::CacheResult<Option<R>> {
+        let key = (self.f as usize, args.clone());
+        Ok(self.cache.get_mut::<Memo<Args, R>>()?.0.remove(&key))
+    }
+
+    /// The cache holding the results.
+    pub fn cache(&self) -> &Cache<G, L> {
+        &self.cache
+    }
+}
+
+impl<Args, R, const G: usize, const L: usize> std::fmt::Debug for CachedFn<Args, R, G, L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachedFn")
+            .field("cache", &self.cache)
+            .field("f", &std::any::type_name::<fn(Args) -> R>())
+            .finish()
+    }
+}
+
+/// Declare a function whose results are cached by a [`CachedFn`](crate::memo::CachedFn),
+/// in the default process-wide cache, or in the `Cache` (or `GlobalCache`) given before `;`.
+///
+/// The arguments must be `Hash + Eq + Clone` and the result `Clone`, all `Send + Sync + 'static`.
+/// They are passed to the `CachedFn` as a tuple, so `fn f(a: A) -> R` shares the results of `fn((A,)) -> R`.
+/// Generic functions and methods are not supported.
+///
+/// ```ignore
+/// memo! {
+///     fn fib(n: u64) -> u64 {
+///         if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! memo {
+    (
+        $cache:expr;
+        $(#[$attr:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block
+    ) => {
+        $(#[$attr])*
+        $vis fn $name($($arg: $ty),*) -> $ret {
+            fn compute(($($arg,)*): ($($ty,)*)) -> $ret $body
+            $crate::memo::CachedFn::new(&$cache, compute).call(($($arg,)*))
+        }
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block
+    ) => {
+        $crate::memo! {
+            *$crate::global::cache();
+            $(#[$attr])*
+            $vis fn $name($($arg: $ty),*) -> $ret $body
+        }
+    };
+}
@@ -0,0 +1,317 @@
+//! Namespaced entries of a [`Cache`], e.g. one namespace per tenant.
+//!
+//! A `Cacheable` is identified by its type, so a cache holds one per type. Accessed through
+//! [`Cache::scope()`], it is identified by its type and the namespace instead, so that each tenant
+//! has its own, sharing the capacity of the cache with the others:
+//!
+//! ```ignore
+//! # use rom_cache::Cache;
+//! let cache: Cache<16, 4> = Cache::default();
+//! cache.scope("acme").get_mut::<Usage>()?.requests += 1;
+//! cache.drop_scope("acme")?;
+//! ```
+//!
+//! [`Cacheable::load()`] and [`Cacheable::store()`] tell the namespaces apart by [`current()`],
+//! and a `Cacheable` with a [`Cacheable::KEY`] is stored under `namespace/KEY` in the `Backend`, with the namespace
+//! percent-encoded but ASCII alphanumerics, `-` and `_`, e.g. `%2E%2E%2Fx` for `../x`, and `%` for the empty one.
+//! Dependencies, watchers and `Cache::get_or_init()` apply to the namespaced entries only if
+//! declared while accessing the namespace.
+//!
+//! A [`Quota`] keeps a busy namespace from evicting the entries of the others, see [`CacheBuilder::scope_quota()`].
+
+use crate::backend::SharedBackend;
+use crate::cache::{Cache, CacheMut, CacheRef, Cacheable, DynCacheable, DynValue};
+#[cfg(doc)]
+use crate::CacheBuilder;
+use crate::CacheResult;
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+    /// The hash of `CURRENT`, 0 if none, read on every access of the cache.
+    static HASH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The namespace accessed on this thread, None if accessing the cache directly.
+/// Set while loading and storing the `Cacheable`s of a namespace, also in background.
+pub fn current() -> Option<Arc<str>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// A handle to the entries of a namespace in a [`Cache`], see the [module-level documentation](self).
+#[derive(Debug)]
+pub struct Namespace<'c, const G: usize, const L: usize> {
+    cache: &'c Cache<G, L>,
+    name: Arc<str>,
+}
+
+impl<const G: usize, const L: usize> Cache<G, L> {
+    /// Access the `Cacheable`s of `namespace`, see [`Namespace`].
+    pub fn scope(&self, namespace: impl Into<Arc<str>>) -> Namespace<'_, G, L> {
+        Namespace {
+            cache: self,
+            name: namespace.into(),
+        }
+    }
+
+    /// Write back and drop every cached `Cacheable` of `namespace` not being used, see [`Cache::retain()`].
+    pub fn drop_scope(&self, namespace: &str) -> Result<usize, Vec<(String, std::io::Error)>> {
+        self.retain(|info| info.namespace.as_deref() != Some(namespace))
+    }
+
+    /// Limit `namespace` by `quota` instead of the one set by [`CacheBuilder::scope_quota()`], None to restore it.
+    /// It is enforced on the next access of the namespace.
+    pub fn set_scope_quota(&self, namespace: &str, quota: Option<Quota>) {
+        self.scopes().set_quota(namespace, quota);
+    }
+}
+
+/// The limits of each namespace, see [`CacheBuilder::scope_quota()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// The most `CacheLine`s held by the namespace, unlimited if None.
+    pub lines: Option<usize>,
+    /// The most total weight (see [`Cacheable::weight()`]) of the namespace, unlimited if None.
+    pub bytes: Option<usize>,
+}
+
+/// The `CacheLine`s held by each namespace, with the quotas set by [`Cache::set_scope_quota()`].
+#[derive(Debug, Default)]
+pub(crate) struct Usages(Mutex<HashMap<Arc<str>, Usage>>);
+
+#[derive(Debug, Default)]
+struct Usage {
+    lines: usize,
+    bytes: usize,
+    quota: Option<Quota>,
+}
+
+impl Usages {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Arc<str>, Usage>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A `CacheLine` of `weight` is filled with a `Cacheable` of `namespace`.
+    pub(crate) fn add(&self, namespace: &Arc<str>, weight: usize) {
+        let mut usages = self.lock();
+        let usage = usages.entry(namespace.clone()).or_default();
+        usage.lines += 1;
+        usage.bytes += weight;
+    }
+
+    /// A `CacheLine` of `weight` held by `namespace` is emptied, or refilled.
+    pub(crate) fn remove(&self, namespace: &str, weight: usize) {
+        let mut usages = self.lock();
+        let Some(usage) = usages.get_mut(namespace) else {
+            return;
+        };
+        usage.lines = usage.lines.saturating_sub(1);
+        usage.bytes = usage.bytes.saturating_sub(weight);
+        if usage.lines == 0 && usage.quota.is_none() {
+            usages.remove(namespace);
+        }
+    }
+
+    fn set_quota(&self, namespace: &str, quota: Option<Quota>) {
+        let mut usages = self.lock();
+        match (usages.get_mut(namespace), quota) {
+            (Some(usage), quota) => usage.quota = quota,
+            (None, Some(quota)) => {
+                let usage = Usage {
+                    quota: Some(quota),
+                    ..Default::default()
+                };
+                usages.insert(namespace.into(), usage);
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// Whether `namespace` holds as many `CacheLine`s as its quota allows, `default` if not set.
+    pub(crate) fn full(&self, namespace: &str, default: Option<Quota>) -> bool {
+        let usages = self.lock();
+        let Some(usage) = usages.get(namespace) else {
+            return false;
+        };
+        let quota = usage.quota.or(default).unwrap_or_default();
+        quota.lines.is_some_and(|lines| usage.lines >= lines)
+    }
+
+    /// Whether `namespace` exceeds its quota, `default` if not set.
+    pub(crate) fn over(&self, namespace: &str, default: Option<Quota>) -> bool {
+        let usages = self.lock();
+        let Some(usage) = usages.get(namespace) else {
+            return false;
+        };
+        let quota = usage.quota.or(default).unwrap_or_default();
+        quota.lines.is_some_and(|lines| usage.lines > lines)
+            || quota.bytes.is_some_and(|bytes| usage.bytes > bytes)
+    }
+}
+
+impl<'c, const G: usize, const L: usize> Namespace<'c, G, L> {
+    /// Retrieve T of the namespace, see [`Cache::get()`].
+    pub fn get<T: Cacheable + Send + Sync + Default>(&self) -> CacheResult<CacheRef<'c, T>> {
+        self.enter(|| self.cache.get())
+    }
+
+    /// Retrieve T of the namespace mutably, see [`Cache::get_mut()`].
+    pub fn get_mut<T: Cacheable + Send + Sync + Default>(&self) -> CacheResult<CacheMut<'c, T>> {
+        self.enter(|| self.cache.get_mut())
+    }
+
+    /// Whether T of the namespace is cached, see [`Cache::contains()`].
+    pub fn contains<T: Cacheable + Send + Sync>(&self) -> CacheResult<bool> {
+        self.enter(|| self.cache.contains::<T>())
+    }
+
+    /// Run `f` accessing the namespace, so that every access of the cache in `f` is namespaced,
+    /// e.g. [`Cache::get_or_init()`] or [`Cache::watch()`].
+    pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _entered = enter(Some(self.name.clone()));
+        f()
+    }
+
+    /// The name of the namespace.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Set the namespace of this thread until dropped.
+struct Entered(Option<Arc<str>>, usize);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+        HASH.with(|hash| hash.set(self.1));
+    }
+}
+
+fn enter(namespace: Option<Arc<str>>) -> Entered {
+    let hash = namespace.as_deref().map_or(0, hash_of);
+    let previous = CURRENT.with(|current| current.replace(namespace));
+    Entered(previous, HASH.with(|h| h.replace(hash)))
+}
+
+fn hash_of(namespace: &str) -> usize {
+    // not randomized, the same in every thread
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    namespace.hash(&mut hasher);
+    (hasher.finish() as usize).max(1)
+}
+
+/// The id of the `Cacheable` of `type_id` in the namespace accessed on this thread.
+pub(crate) fn id(type_id: usize) -> usize {
+    match HASH.with(Cell::get) {
+        0 => type_id,
+        hash => (type_id ^ hash).max(1),
+    }
+}
+
+/// The id of the cached `value`, in its namespace if any.
+pub(crate) fn id_of(value: &DynValue, type_id: usize) -> usize {
+    match value.namespace() {
+        Some(namespace) => (type_id ^ hash_of(namespace)).max(1),
+        None => type_id,
+    }
+}
+
+/// The key of the `Cacheable` in the `Backend`, prefixed by the namespace accessed on this thread.
+pub(crate) fn key(key: &str) -> Cow<'_, str> {
+    match current() {
+        Some(namespace) => Cow::Owned(format!("{}/{key}", encode(&namespace))),
+        None => Cow::Borrowed(key),
+    }
+}
+
+/// Whether `namespace` is the one accessed on this thread.
+pub(crate) fn is_current(namespace: Option<&str>) -> bool {
+    CURRENT.with(|current| current.borrow().as_deref() == namespace)
+}
+
+/// Percent-encode `namespace` into a single path component, which is never empty, `.` or `..`,
+/// so that it stays within a [`Dir`](crate::backend::Dir).
+fn encode(namespace: &str) -> String {
+    if namespace.is_empty() {
+        return "%".to_string();
+    }
+    let mut encoded = String::with_capacity(namespace.len());
+    for byte in namespace.bytes() {
+        match byte {
+            b'0'..=b'9' | b'A'..=b'Z' | b'a'..=b'z' | b'-' | b'_' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Tag `value` with the namespace accessed on this thread, so that it is stored within the namespace.
+pub(crate) fn tag(value: Box<DynValue>) -> Box<DynValue> {
+    match current() {
+        Some(namespace) => Box::new(Tagged { namespace, value }),
+        None => value,
+    }
+}
+
+/// Run `f` accessing `namespace`, e.g. in background.
+pub(crate) fn within<R>(namespace: Option<Arc<str>>, f: impl FnOnce() -> R) -> R {
+    let _entered = enter(namespace);
+    f()
+}
+
+/// A cached value of a namespace, which stores itself within the namespace.
+struct Tagged {
+    namespace: Arc<str>,
+    value: Box<DynValue>,
+}
+
+impl DynCacheable for Tagged {
+    fn store(&self) -> std::io::Result<()> {
+        within(Some(self.namespace.clone()), || self.value.store())
+    }
+
+    fn store_since(
+        &self,
+        since: Option<&dyn Any>,
+        backend: Option<&SharedBackend>,
+    ) -> std::io::Result<()> {
+        within(Some(self.namespace.clone()), || {
+            self.value.store_since(since, backend)
+        })
+    }
+
+    fn type_name(&self) -> &'static str {
+        self.value.type_name()
+    }
+
+    fn weight(&self) -> usize {
+        self.value.weight()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self.value.as_any()
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self.value.as_any_mut()
+    }
+
+    fn namespace(&self) -> Option<&Arc<str>> {
+        Some(&self.namespace)
+    }
+
+    fn encode_keyed(&self) -> Option<(String, std::io::Result<Vec<u8>>)> {
+        within(Some(self.namespace.clone()), || self.value.encode_keyed())
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn debug_json(&self) -> Option<serde_json::Value> {
+        self.value.debug_json()
+    }
+}
@@ -0,0 +1,96 @@
+//! NUMA-aware placement of `CacheGroup`s, see [`CacheBuilder::numa_nodes()`](crate::CacheBuilder::numa_nodes()).
+//!
+//! The `CacheGroup`s of a cache with `n` nodes are split into `n` contiguous ranges, the range `i` belonging to node `i`.
+//! A `Cacheable` is placed in a group of the node of the thread accessing it first.
+//!
+//! The node of a thread is the one set by [`set_thread_node()`], otherwise the node of the CPU it runs on
+//! with feature `numa` on Linux, otherwise 0.
+//! With feature `numa` on Linux, the pages holding the `CacheLine`s of each group are also moved to its node.
+
+use std::cell::Cell;
+use std::ops::Range;
+
+thread_local! {
+    static THREAD_NODE: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Place the `Cacheable`s first accessed by this thread in the `CacheGroup`s of `node`,
+/// e.g. the node the thread is pinned to. None to detect it again.
+pub fn set_thread_node(node: Option<usize>) {
+    THREAD_NODE.with(|n| n.set(node));
+}
+
+/// The node of this thread, see the [module-level documentation](self).
+pub fn current_node() -> usize {
+    THREAD_NODE.with(Cell::get).unwrap_or_else(cpu_node)
+}
+
+/// The `CacheGroup`s of `node` among `groups` spread over `nodes`, never empty.
+pub(crate) fn groups(groups: usize, nodes: usize, node: usize) -> Range<usize> {
+    let nodes = nodes.clamp(1, groups);
+    let node = node % nodes;
+    node * groups / nodes..(node + 1) * groups / nodes
+}
+
+#[cfg(all(feature = "numa", target_os = "linux"))]
+fn cpu_node() -> usize {
+    let (mut cpu, mut node) = (0u32, 0u32);
+    // SAFETY: getcpu writes the two u32s, the cache argument is unused since Linux 2.6.24
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_getcpu,
+            &mut cpu as *mut u32,
+            &mut node as *mut u32,
+            std::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    if res == 0 {
+        node as usize
+    } else {
+        0
+    }
+}
+
+#[cfg(not(all(feature = "numa", target_os = "linux")))]
+fn cpu_node() -> usize {
+    0
+}
+
+/// Move the whole pages within `len` bytes from `addr` to `node`, preferred for later allocations too.
+/// Best effort, e.g. it fails if the node does not exist.
+#[cfg(all(feature = "numa", target_os = "linux"))]
+pub(crate) fn bind(addr: *const u8, len: usize, node: usize) -> std::io::Result<()> {
+    const MPOL_PREFERRED: libc::c_int = 1;
+    const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+    const MASK_BITS: usize = 1024;
+    const WORD: usize = libc::c_ulong::BITS as usize;
+    if node >= MASK_BITS {
+        return Err(std::io::ErrorKind::InvalidInput.into());
+    }
+    // SAFETY: sysconf has no preconditions
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let start = (addr as usize).next_multiple_of(page);
+    let end = (addr as usize + len) / page * page;
+    if start >= end {
+        return Ok(());
+    }
+    let mut mask = [0 as libc::c_ulong; MASK_BITS / WORD];
+    mask[node / WORD] |= 1 << (node % WORD);
+    // SAFETY: the pages belong to the cache, mbind only changes where they reside
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            start,
+            end - start,
+            MPOL_PREFERRED,
+            mask.as_ptr(),
+            MASK_BITS + 1,
+            MPOL_MF_MOVE,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
@@ -0,0 +1,30 @@
+//! Memory pressure of the cgroup of the process, see [`CacheBuilder::cgroup_pressure()`](crate::CacheBuilder::cgroup_pressure()).
+
+use crate::MemoryPressure;
+use std::path::PathBuf;
+
+/// The memory pressure of the cgroup (v2) of the process, None if below 80% of the limit or unlimited.
+pub(crate) fn cgroup() -> Option<MemoryPressure> {
+    let dir = cgroup_dir()?;
+    let read = |file: &str| std::fs::read_to_string(dir.join(file)).ok();
+    let current = read("memory.current")?.trim().parse::<u64>().ok()?;
+    // "max" if unlimited
+    let max = read("memory.max")?.trim().parse::<u64>().ok()?;
+    level(current, max)
+}
+
+/// The directory of the cgroup (v2) of the process, from the `0::<path>` entry of `/proc/self/cgroup`.
+fn cgroup_dir() -> Option<PathBuf> {
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+    Some(PathBuf::from("/sys/fs/cgroup").join(path.trim_start_matches('/')))
+}
+
+fn level(current: u64, max: u64) -> Option<MemoryPressure> {
+    match current as f64 / max.max(1) as f64 {
+        ratio if ratio >= 0.95 => Some(MemoryPressure::Critical),
+        ratio if ratio >= 0.9 => Some(MemoryPressure::Moderate),
+        ratio if ratio >= 0.8 => Some(MemoryPressure::Low),
+        _ => None,
+    }
+}
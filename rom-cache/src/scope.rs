@@ -0,0 +1,110 @@
+//! Task-scoped caches layered over a shared [`Cache`].
+//!
+//! A per-request working set loaded into a shared cache evicts the entries shared by all requests.
+//! A [`ScopedCache`] keeps it apart in a small cache of its own instead, and merges only its modifications
+//! back into the shared one when the scope ends:
+//!
+//! ```ignore
+//! # use rom_cache::{scope::ScopedCache, Cache};
+//! let shared: Cache<16, 4> = Cache::default();
+//! let scope: ScopedCache<16, 4> = ScopedCache::new(&shared);
+//! scope.get_mut::<Session>()?.visits += 1;
+//! scope.merge()?;
+//! ```
+
+use crate::cache::{Cache, CacheMut, CacheRef, Cacheable, ConflictPolicy};
+use crate::CacheResult;
+
+/// A cache of `SG` groups of `SL` lines layered over a shared parent `Cache<G, L>`.
+///
+/// A `Cacheable` already cached by the parent is accessed there, otherwise it is loaded into the scope,
+/// leaving the LRU of the parent alone. When merged or dropped, the clean `Cacheable`s of the scope are dropped,
+/// and the dirty ones are moved into the parent with [`ConflictPolicy::PreferDirty`].
+///
+/// A `Cacheable` loaded into the scope does not observe the later modifications made through the parent.
+#[derive(Debug)]
+pub struct ScopedCache<const G: usize, const L: usize, const SG: usize = 1, const SL: usize = 4> {
+    parent: Cache<G, L>,
+    scope: Cache<SG, SL>,
+    merged: bool,
+}
+
+impl<const G: usize, const L: usize, const SG: usize, const SL: usize> ScopedCache<G, L, SG, SL> {
+    /// A scope over `parent`, with a default `Cache<SG, SL>` of its own.
+    pub fn new(parent: &Cache<G, L>) -> Self {
+        Self::with(parent, Cache::default())
+    }
+
+    /// A scope over `parent`, using `scope` as its own cache, e.g. built by [`Cache::builder()`].
+    pub fn with(parent: &Cache<G, L>, scope: Cache<SG, SL>) -> Self {
+        Self {
+            parent: parent.clone(),
+            scope,
+            merged: false,
+        }
+    }
+
+    /// Retrieve T from the scope, or from the parent if cached there, loading it into the scope otherwise.
+    /// The errors are the same as [`Cache::get()`].
+    pub fn get<T: Cacheable + Send + Sync + Default>(&self) -> CacheResult<CacheRef<'_, T>> {
+        match self.in_parent::<T>()? {
+            true => self.parent.get(),
+            false => self.scope.get(),
+        }
+    }
+
+    /// Retrieve T mutably like [`ScopedCache::get()`], see [`Cache::get_mut()`].
+    /// T modified in the scope is merged into the parent when the scope ends.
+    pub fn get_mut<T: Cacheable + Send + Sync + Default>(&self) -> CacheResult<CacheMut<'_, T>> {
+        match self.in_parent::<T>()? {
+            true => self.parent.get_mut(),
+            false => self.scope.get_mut(),
+        }
+    }
+
+    /// The parent cache.
+    pub fn parent(&self) -> &Cache<G, L> {
+        &self.parent
+    }
+
+    /// The cache of the scope.
+    pub fn scope(&self) -> &Cache<SG, SL> {
+        &self.scope
+    }
+
+    /// End the scope, merging the dirty `Cacheable`s into the parent.
+    /// Every failure is returned with the type name, see [`Cache::absorb()`],
+    /// and the `Cacheable` is written back instead.
+    pub fn merge(mut self) -> Result<(), Vec<(String, std::io::Error)>> {
+        self.merged = true;
+        self.merge_scope()
+    }
+
+    /// Whether T is accessed in the parent, i.e. cached by the parent and not by the scope.
+    fn in_parent<T: Cacheable + Send + Sync>(&self) -> CacheResult<bool> {
+        Ok(!self.scope.contains::<T>()? && self.parent.contains::<T>()?)
+    }
+
+    fn merge_scope(&self) -> Result<(), Vec<(String, std::io::Error)>> {
+        if let Err(e) = self.scope.purge_clean() {
+            return Err(vec![("ScopedCache".to_string(), std::io::Error::other(e))]);
+        }
+        self.parent
+            .absorb(self.scope.clone(), ConflictPolicy::PreferDirty)
+    }
+}
+
+impl<const G: usize, const L: usize, const SG: usize, const SL: usize> Drop
+    for ScopedCache<G, L, SG, SL>
+{
+    /// Merge the dirty `Cacheable`s into the parent, reporting the failures to the handler
+    /// registered by [`Cache::on_store_error()`] of the parent.
+    fn drop(&mut self) {
+        if self.merged {
+            return;
+        }
+        if let Err(errors) = self.merge_scope() {
+            self.parent.report(errors);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! A [`Cache`] owned by a dedicated thread, accessed by message passing.
+//!
+//! The handles of a [`CacheService`] send each call to the owner thread, and receive owned results,
+//! so that no `CacheRef` or `CacheMut` outlives a call:
+//!
+//! ```ignore
+//! # use rom_cache::{service::CacheService, Cache};
+//! let service = CacheService::spawn(Cache::<16, 4>::default());
+//! let handle = service.clone();
+//! std::thread::spawn(move || handle.get_mut(|counter: &mut Counter| counter.0 += 1));
+//! let counter: Counter = service.get()?;
+//! service.flush()?;
+//! ```
+//!
+//! The calls are served one by one, so a slow `load` or `store` delays the others.
+
+use crate::cache::{Cache, Cacheable};
+use crate::global::{GROUPS, LINES};
+use crate::CacheResult;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::{channel, sync_channel, Sender};
+
+/// A call to be run with the cache on the owner thread.
+type Job<const G: usize, const L: usize> = Box<dyn FnOnce(&Cache<G, L>) + Send>;
+
+/// A handle to a [`Cache`] owned by a dedicated thread, see the [module-level documentation](self).
+///
+/// Clone it to share the cache. The thread exits once every handle is dropped, dropping the cache,
+/// which writes back the dirty `Cacheable`s unless other clones of the `Cache` are alive.
+#[derive(Debug, Clone)]
+pub struct CacheService<const G: usize = GROUPS, const L: usize = LINES> {
+    jobs: Sender<Job<G, L>>,
+}
+
+impl<const G: usize, const L: usize> CacheService<G, L> {
+    /// Move `cache` to a new thread owning it, and return the first handle to it.
+    pub fn spawn(cache: Cache<G, L>) -> Self {
+        let (jobs, rx) = channel::<Job<G, L>>();
+        std::thread::Builder::new()
+            .name("rom-cache-service".into())
+            .spawn(move || {
+                for job in rx {
+                    job(&cache);
+                }
+            })
+            .expect("failed to spawn the cache service");
+        Self { jobs }
+    }
+
+    /// Run `f` with the cache on the owner thread, and return its result.
+    /// A panic in `f` is resumed on this thread, and the service keeps serving the others.
+    ///
+    /// Do not keep what `f` retrieves beyond it, e.g. by [`Cache::get_owned()`],
+    /// which may block the following calls.
+    pub fn call<R: Send + 'static>(&self, f: impl FnOnce(&Cache<G, L>) -> R + Send + 'static) -> R {
+        let (tx, rx) = sync_channel(1);
+        let job: Job<G, L> = Box::new(move |cache| {
+            let res = std::panic::catch_unwind(AssertUnwindSafe(|| f(cache)));
+            tx.send(res).ok();
+        });
+        // the owner thread never stops while a handle is alive, since the panics are caught
+        self.jobs.send(job).expect("the cache service stopped");
+        match rx.recv().expect("the cache service stopped") {
+            Ok(res) => res,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+
+    /// A copy of T retrieved by [`Cache::get()`].
+    pub fn get<T: Cacheable + Send + Sync + Default + Clone>(&self) -> CacheResult<T> {
+        self.call(|cache| cache.get::<T>().map(|t| T::clone(&t)))
+    }
+
+    /// Read T retrieved by [`Cache::get()`] with `f`, without copying it.
+    pub fn read<T: Cacheable + Send + Sync + Default, R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&T) -> R + Send + 'static,
+    ) -> CacheResult<R> {
+        self.call(|cache| cache.get::<T>().map(|t| f(&t)))
+    }
+
+    /// Modify T retrieved by [`Cache::get_mut()`] with `f`.
+    pub fn get_mut<T: Cacheable + Send + Sync + Default, R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut T) -> R + Send + 'static,
+    ) -> CacheResult<R> {
+        self.call(|cache| cache.get_mut::<T>().map(|mut t| f(&mut t)))
+    }
+
+    /// Write back all dirty `CacheLine`s, returning every failure with the type name of the `Cacheable`,
+    /// see [`Cache::close()`].
+    pub fn flush(&self) -> Result<(), Vec<(String, std::io::Error)>> {
+        self.call(|cache| cache.clone().close())
+    }
+}
@@ -0,0 +1,403 @@
+//! Utilities for testing code built on this crate. Enabled by feature `test-util`.
+//!
+//! [`MockCacheable<T>`] wraps a value `T` into a [`Cacheable`], whose storage is kept in memory.
+//! Every `load` and `store` is recorded, and delays or failures can be scripted.
+//!
+//! ```ignore
+//! # use rom_cache::{Cache, test_util::MockCacheable};
+//! MockCacheable::<String>::fail_next_loads(1);
+//! let cache: Cache<1, 1> = Cache::default();
+//! cache.get::<MockCacheable<String>>().unwrap();  // load failed, default value used
+//! MockCacheable::<String>::assert_loads(1);
+//! ```
+//!
+//! Instead of sleeping for a background `load` or `store`, wait for it by [`MockCacheable::wait_stores()`],
+//! or hold the `store`s by [`MockCacheable::hold_stores()`] to observe them in progress.
+//!
+//! The records of `MockCacheable<T>` are global for each `T`.
+//! Use distinct `T`s for tests running concurrently, or [`MockCacheable::reset()`] between them.
+//!
+//! [`Faults`] injects delays and failures into the `load` and `store` calls of any `Cacheable`,
+//! made by a live [`Cache`](crate::Cache) instance:
+//!
+//! ```ignore
+//! # use rom_cache::Cache;
+//! let cache: Cache<1, 1> = Cache::default();
+//! cache.faults().fail_next_stores::<String>(2);
+//! cache.faults().set_load_delay::<u64>(Duration::from_millis(50));
+//! ```
+//!
+//! With feature `proptest`, [`prop`] checks a cache against a reference model with randomized operations.
+
+#[cfg(feature = "proptest")]
+pub mod prop;
+
+use crate::Cacheable;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// A [`Cacheable`] wrapper recording `load` and `store` calls, see [module level docs](self).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MockCacheable<T> {
+    /// The wrapped value.
+    pub value: T,
+}
+
+impl<T> MockCacheable<T> {
+    /// Wrap a value.
+    pub fn new(value: T) -> Self {
+        Self { value }
+    }
+}
+
+impl<T> Deref for MockCacheable<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for MockCacheable<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+impl<T> Cacheable for MockCacheable<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    fn load() -> io::Result<Self> {
+        let (delay, fail) = Self::with_state(|state| {
+            state.loads += 1;
+            (state.load_delay, take_failure(&mut state.load_failures))
+        });
+        std::thread::sleep(delay);
+        let res = match fail {
+            true => Err(io::Error::other("scripted load failure")),
+            false => Self::stored()
+                .map(Self::new)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "nothing stored")),
+        };
+        Self::with_state(|state| state.loads_done += 1);
+        res
+    }
+
+    fn store(&self) -> io::Result<()> {
+        let (delay, fail) = Self::with_state(|state| {
+            state.stores += 1;
+            (state.store_delay, take_failure(&mut state.store_failures))
+        });
+        std::thread::sleep(delay);
+        Self::with_state(|state| state.held += 1);
+        Self::wait_until(None, |state| !state.holding);
+        Self::with_state(|state| {
+            state.held -= 1;
+            state.stores_done += 1;
+            match fail {
+                true => Err(io::Error::other("scripted store failure")),
+                false => {
+                    state.storage = Some(self.value.clone());
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    fn snapshot(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
+
+impl<T> MockCacheable<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    /// Number of `load` calls, including the failed ones.
+    pub fn loads() -> usize {
+        Self::with_state(|state| state.loads)
+    }
+
+    /// Number of `store` calls, including the failed ones.
+    pub fn stores() -> usize {
+        Self::with_state(|state| state.stores)
+    }
+
+    /// The value in the mocked storage, `None` if nothing stored.
+    pub fn stored() -> Option<T> {
+        Self::with_state(|state| state.storage.clone())
+    }
+
+    /// Put a value into the mocked storage, which will be returned by the following `load`s.
+    pub fn set_stored(value: T) {
+        Self::with_state(|state| state.storage = Some(value));
+    }
+
+    /// Sleep for `delay` in each `load`.
+    pub fn set_load_delay(delay: Duration) {
+        Self::with_state(|state| state.load_delay = delay);
+    }
+
+    /// Sleep for `delay` in each `store`.
+    pub fn set_store_delay(delay: Duration) {
+        Self::with_state(|state| state.store_delay = delay);
+    }
+
+    /// Make the next `n` `load`s fail.
+    pub fn fail_next_loads(n: usize) {
+        Self::with_state(|state| state.load_failures = n);
+    }
+
+    /// Make the next `n` `store`s fail.
+    pub fn fail_next_stores(n: usize) {
+        Self::with_state(|state| state.store_failures = n);
+    }
+
+    /// Block the following `store`s until [`MockCacheable::release_stores()`], e.g. to observe them in progress.
+    pub fn hold_stores() {
+        Self::with_state(|state| state.holding = true);
+    }
+
+    /// Let the held and the following `store`s finish.
+    pub fn release_stores() {
+        Self::with_state(|state| state.holding = false);
+    }
+
+    /// Wait until `n` `store`s are held by [`MockCacheable::hold_stores()`] at once, false if timed out.
+    pub fn wait_held_stores(n: usize, timeout: Duration) -> bool {
+        Self::wait_until(Some(timeout), |state| state.held >= n)
+    }
+
+    /// Wait until `n` `load` calls returned, including the failed ones, false if timed out.
+    pub fn wait_loads(n: usize, timeout: Duration) -> bool {
+        Self::wait_until(Some(timeout), |state| state.loads_done >= n)
+    }
+
+    /// Wait until `n` `store` calls returned, including the failed ones, false if timed out.
+    pub fn wait_stores(n: usize, timeout: Duration) -> bool {
+        Self::wait_until(Some(timeout), |state| state.stores_done >= n)
+    }
+
+    /// Clear the records, the storage and the scripts.
+    pub fn reset() {
+        Self::with_state(|state| *state = MockState::default());
+    }
+
+    /// Assert the number of `load` calls.
+    #[track_caller]
+    pub fn assert_loads(n: usize) {
+        assert_eq!(
+            Self::loads(),
+            n,
+            "unexpected number of loads of {}",
+            std::any::type_name::<T>()
+        );
+    }
+
+    /// Assert the number of `store` calls.
+    #[track_caller]
+    pub fn assert_stores(n: usize) {
+        assert_eq!(
+            Self::stores(),
+            n,
+            "unexpected number of stores of {}",
+            std::any::type_name::<T>()
+        );
+    }
+
+    /// Assert the value in the mocked storage.
+    #[track_caller]
+    pub fn assert_stored(value: &T)
+    where
+        T: PartialEq + std::fmt::Debug,
+    {
+        assert_eq!(Self::stored().as_ref(), Some(value));
+    }
+
+    fn mock() -> Arc<Mock<T>> {
+        states()
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Arc::new(Mock::<T>::default()))
+            .clone()
+            .downcast::<Mock<T>>()
+            .unwrap()
+    }
+
+    fn with_state<R>(f: impl FnOnce(&mut MockState<T>) -> R) -> R {
+        let mock = Self::mock();
+        let res = f(&mut mock.state.lock().unwrap_or_else(|e| e.into_inner()));
+        mock.changed.notify_all();
+        res
+    }
+
+    /// Wait until `f` holds, at most `timeout` if given. Return whether it holds.
+    fn wait_until(timeout: Option<Duration>, f: impl Fn(&MockState<T>) -> bool) -> bool {
+        let mock = Self::mock();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = mock.state.lock().unwrap_or_else(|e| e.into_inner());
+        while !f(&state) {
+            let Some(deadline) = deadline else {
+                state = mock.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+                continue;
+            };
+            let Some(timeout) = deadline.checked_duration_since(Instant::now()) else {
+                return false;
+            };
+            state = match mock.changed.wait_timeout(state, timeout) {
+                Ok((state, _)) => state,
+                Err(e) => e.into_inner().0,
+            };
+        }
+        true
+    }
+}
+
+/// The records of a `MockCacheable<T>`, notifying the waiters of every change.
+#[derive(Debug)]
+struct Mock<T> {
+    state: Mutex<MockState<T>>,
+    changed: Condvar,
+}
+
+impl<T> Default for Mock<T> {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(MockState::default()),
+            changed: Condvar::new(),
+        }
+    }
+}
+
+type AnyState = Arc<dyn std::any::Any + Send + Sync>;
+
+fn states() -> &'static Mutex<HashMap<TypeId, AnyState>> {
+    static STATES: OnceLock<Mutex<HashMap<TypeId, AnyState>>> = OnceLock::new();
+    STATES.get_or_init(Default::default)
+}
+
+fn take_failure(failures: &mut usize) -> bool {
+    if *failures > 0 {
+        *failures -= 1;
+        true
+    } else {
+        false
+    }
+}
+
+#[derive(Debug)]
+struct MockState<T> {
+    loads: usize,
+    stores: usize,
+    loads_done: usize,
+    stores_done: usize,
+    /// Whether to hold the `store`s, and how many are held.
+    holding: bool,
+    held: usize,
+    storage: Option<T>,
+    load_delay: Duration,
+    store_delay: Duration,
+    load_failures: usize,
+    store_failures: usize,
+}
+
+impl<T> Default for MockState<T> {
+    fn default() -> Self {
+        Self {
+            loads: 0,
+            stores: 0,
+            loads_done: 0,
+            stores_done: 0,
+            holding: false,
+            held: 0,
+            storage: None,
+            load_delay: Duration::ZERO,
+            store_delay: Duration::ZERO,
+            load_failures: 0,
+            store_failures: 0,
+        }
+    }
+}
+
+/// Delays and failures injected into the `load` and `store` calls made by a [`Cache`](crate::Cache),
+/// see [`Cache::faults()`](crate::Cache::faults()).
+///
+/// A failed `load` makes the cache fall back to the default value,
+/// a failed `store` surfaces as `CacheError::Io`.
+#[derive(Debug, Default)]
+pub struct Faults {
+    scripts: Mutex<HashMap<TypeId, FaultScript>>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct FaultScript {
+    load_delay: Duration,
+    store_delay: Duration,
+    load_failures: usize,
+    store_failures: usize,
+}
+
+impl Faults {
+    /// Make the next `n` `load`s of T fail.
+    pub fn fail_next_loads<T: Cacheable>(&self, n: usize) {
+        self.with_script::<T>(|script| script.load_failures = n);
+    }
+
+    /// Make the next `n` `store`s of T fail.
+    pub fn fail_next_stores<T: Cacheable>(&self, n: usize) {
+        self.with_script::<T>(|script| script.store_failures = n);
+    }
+
+    /// Sleep for `delay` before each `load` of T.
+    pub fn set_load_delay<T: Cacheable>(&self, delay: Duration) {
+        self.with_script::<T>(|script| script.load_delay = delay);
+    }
+
+    /// Sleep for `delay` before each `store` of T.
+    pub fn set_store_delay<T: Cacheable>(&self, delay: Duration) {
+        self.with_script::<T>(|script| script.store_delay = delay);
+    }
+
+    /// Remove all injected faults.
+    pub fn clear(&self) {
+        self.scripts().clear();
+    }
+
+    pub(crate) fn before_load(&self, type_id: TypeId) -> io::Result<()> {
+        let (delay, fail) = match self.scripts().get_mut(&type_id) {
+            Some(script) => (script.load_delay, take_failure(&mut script.load_failures)),
+            None => return Ok(()),
+        };
+        std::thread::sleep(delay);
+        match fail {
+            true => Err(io::Error::other("injected load failure")),
+            false => Ok(()),
+        }
+    }
+
+    pub(crate) fn before_store(&self, type_id: TypeId) -> io::Result<()> {
+        let (delay, fail) = match self.scripts().get_mut(&type_id) {
+            Some(script) => (script.store_delay, take_failure(&mut script.store_failures)),
+            None => return Ok(()),
+        };
+        std::thread::sleep(delay);
+        match fail {
+            true => Err(io::Error::other("injected store failure")),
+            false => Ok(()),
+        }
+    }
+
+    fn with_script<T: Cacheable>(&self, f: impl FnOnce(&mut FaultScript)) {
+        f(self.scripts().entry(TypeId::of::<T>()).or_default());
+    }
+
+    fn scripts(&self) -> std::sync::MutexGuard<'_, HashMap<TypeId, FaultScript>> {
+        self.scripts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
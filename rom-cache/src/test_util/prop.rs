@@ -0,0 +1,248 @@
+//! Property testing helpers. Enabled by feature `proptest`.
+//!
+//! [`ops()`] generates randomized sequences of [`Op`]s over a set of [`Slots`],
+//! and [`check()`] runs them against a [`Cache`] and a [`Model`] (the reference implementation) in lockstep,
+//! failing on the first divergence. [`run()`] does both and shrinks the failing sequence.
+//!
+//! ```ignore
+//! # use rom_cache::{Cache, test_util::{MockCacheable, prop}};
+//! type Slots = (MockCacheable<A>, MockCacheable<B>, MockCacheable<C>);
+//! prop::run::<Slots, 1, 2>(64, 0..32, Cache::default).unwrap();
+//! ```
+//!
+//! The storage of each [`Slot`] is reset before each sequence,
+//! so the types must not be shared with tests running concurrently.
+
+use super::MockCacheable;
+use crate::{Cache, Cacheable};
+use proptest::collection::{vec, SizeRange};
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestError, TestRunner};
+use std::fmt::Debug;
+
+/// An operation applied to a [`Cache`] and a [`Model`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `get` the slot of the index, whose value must be the expected one.
+    Get(usize),
+    /// `get_mut` the slot of the index and write the seed into it.
+    GetMut(usize, u64),
+    /// Write back every dirty slot, by dropping a [`Cache::flush_on_drop()`] guard.
+    Flush,
+    /// Invalidate every slot by [`Cache::bump_generation()`].
+    Invalidate,
+}
+
+/// A `Cacheable` whose value can be derived from a seed, and whose storage can be inspected.
+pub trait Slot: Cacheable + Send + Sync + Default + PartialEq + Debug {
+    /// Overwrite the value with the one derived from `seed`, deterministically.
+    fn write(&mut self, seed: u64);
+    /// The value in the storage, `None` if nothing stored.
+    fn stored() -> Option<Self>;
+    /// Clear the storage.
+    fn reset();
+}
+
+impl<T> Slot for MockCacheable<T>
+where
+    T: From<u64> + Clone + Default + PartialEq + Debug + Send + Sync + 'static,
+{
+    fn write(&mut self, seed: u64) {
+        self.value = T::from(seed);
+    }
+
+    fn stored() -> Option<Self> {
+        MockCacheable::<T>::stored().map(Self::new)
+    }
+
+    fn reset() {
+        MockCacheable::<T>::reset();
+    }
+}
+
+/// A set of [`Slot`]s addressed by index, implemented for tuples of up to 8 `Slot`s.
+pub trait Slots {
+    /// The number of slots.
+    const LEN: usize;
+    /// Clear the storage of every slot.
+    fn reset();
+    /// Apply a `Get` or `GetMut` to the slot of `index`, comparing the value read with `expected`.
+    fn access<const G: usize, const L: usize>(
+        cache: &Cache<G, L>,
+        index: usize,
+        write: Option<u64>,
+        expected: Option<u64>,
+    ) -> Result<(), TestCaseError>;
+    /// Compare the storage of the slot of `index` with `expected`.
+    fn check_stored(index: usize, expected: Option<u64>) -> Result<(), TestCaseError>;
+}
+
+macro_rules! impl_slots {
+    ($len:literal; $($idx:tt $t:ident),+) => {
+        impl<$($t: Slot),+> Slots for ($($t,)+) {
+            const LEN: usize = $len;
+
+            fn reset() {
+                $($t::reset();)+
+            }
+
+            fn access<const G: usize, const L: usize>(
+                cache: &Cache<G, L>,
+                index: usize,
+                write: Option<u64>,
+                expected: Option<u64>,
+            ) -> Result<(), TestCaseError> {
+                match index {
+                    $($idx => access::<$t, G, L>(cache, write, expected),)+
+                    _ => panic!("slot {index} out of {}", Self::LEN),
+                }
+            }
+
+            fn check_stored(index: usize, expected: Option<u64>) -> Result<(), TestCaseError> {
+                match index {
+                    $($idx => check_stored::<$t>(expected),)+
+                    _ => panic!("slot {index} out of {}", Self::LEN),
+                }
+            }
+        }
+    };
+}
+
+impl_slots!(1; 0 A);
+impl_slots!(2; 0 A, 1 B);
+impl_slots!(3; 0 A, 1 B, 2 C);
+impl_slots!(4; 0 A, 1 B, 2 C, 3 D);
+impl_slots!(5; 0 A, 1 B, 2 C, 3 D, 4 E);
+impl_slots!(6; 0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+impl_slots!(7; 0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 H);
+impl_slots!(8; 0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 H, 7 I);
+
+fn expected<T: Slot>(seed: Option<u64>) -> T {
+    let mut value = T::default();
+    if let Some(seed) = seed {
+        value.write(seed);
+    }
+    value
+}
+
+fn access<T: Slot, const G: usize, const L: usize>(
+    cache: &Cache<G, L>,
+    write: Option<u64>,
+    seed: Option<u64>,
+) -> Result<(), TestCaseError> {
+    let fail = |e| TestCaseError::fail(format!("{}: {e}", std::any::type_name::<T>()));
+    match write {
+        Some(new) => {
+            let mut value = cache.get_mut::<T>().map_err(fail)?;
+            prop_assert_eq!(&*value, &expected::<T>(seed));
+            value.write(new);
+        }
+        None => prop_assert_eq!(&*cache.get::<T>().map_err(fail)?, &expected::<T>(seed)),
+    }
+    Ok(())
+}
+
+fn check_stored<T: Slot>(seed: Option<u64>) -> Result<(), TestCaseError> {
+    // A slot never written may be stored or not, depending on evictions.
+    if seed.is_some() {
+        prop_assert_eq!(T::stored(), Some(expected::<T>(seed)));
+    }
+    Ok(())
+}
+
+/// The reference implementation: every slot keeps the last seed written into it,
+/// whatever evictions, write-backs and invalidations happened in between.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Model {
+    seeds: Vec<Option<u64>>,
+}
+
+impl Model {
+    /// A model of `slots` slots holding the default values.
+    pub fn new(slots: usize) -> Self {
+        Self {
+            seeds: vec![None; slots],
+        }
+    }
+
+    /// Apply `op`, returning the seed the slot accessed should hold before it, if any.
+    pub fn apply(&mut self, op: Op) -> Option<u64> {
+        match op {
+            Op::Get(index) => self.seeds[index],
+            Op::GetMut(index, seed) => self.seeds[index].replace(seed),
+            Op::Flush | Op::Invalidate => None,
+        }
+    }
+
+    /// The last seed written into the slot of `index`, `None` if it holds the default value.
+    pub fn seed(&self, index: usize) -> Option<u64> {
+        self.seeds[index]
+    }
+}
+
+/// A strategy generating an [`Op`] over `slots` slots.
+pub fn op(slots: usize) -> impl Strategy<Value = Op> {
+    assert!(slots > 0, "no slot to operate on");
+    prop_oneof![
+        4 => (0..slots).prop_map(Op::Get),
+        4 => (0..slots, any::<u64>()).prop_map(|(index, seed)| Op::GetMut(index, seed)),
+        1 => Just(Op::Flush),
+        1 => Just(Op::Invalidate),
+    ]
+}
+
+/// A strategy generating sequences of [`Op`]s over `slots` slots, with lengths in `len`.
+pub fn ops(slots: usize, len: impl Into<SizeRange>) -> impl Strategy<Value = Vec<Op>> {
+    vec(op(slots), len)
+}
+
+/// Run `ops` against `cache` and a [`Model`] of S, failing on the first divergence.
+///
+/// The storage of S is reset first.
+/// After each `Flush` and `Invalidate`, and after the `cache` is closed in the end,
+/// the storage of every slot written must hold its last value.
+pub fn check<S: Slots, const G: usize, const L: usize>(
+    cache: Cache<G, L>,
+    ops: &[Op],
+) -> Result<(), TestCaseError> {
+    S::reset();
+    let mut model = Model::new(S::LEN);
+    let check_all_stored =
+        |model: &Model| (0..S::LEN).try_for_each(|i| S::check_stored(i, model.seed(i)));
+    for &op in ops {
+        let expected = model.apply(op);
+        match op {
+            Op::Get(index) => S::access(&cache, index, None, expected)?,
+            Op::GetMut(index, seed) => S::access(&cache, index, Some(seed), expected)?,
+            Op::Flush => {
+                drop(cache.flush_on_drop());
+                check_all_stored(&model)?;
+            }
+            Op::Invalidate => {
+                cache
+                    .bump_generation()
+                    .map_err(|e| TestCaseError::fail(format!("{e:?}")))?;
+                check_all_stored(&model)?;
+            }
+        }
+    }
+    cache
+        .close()
+        .map_err(|e| TestCaseError::fail(format!("{e:?}")))?;
+    check_all_stored(&model)
+}
+
+/// Generate `cases` sequences of [`Op`]s with lengths in `len`, and [`check()`] each against a new cache,
+/// returning the minimal failing sequence if any.
+pub fn run<S: Slots, const G: usize, const L: usize>(
+    cases: u32,
+    len: impl Into<SizeRange>,
+    new_cache: impl Fn() -> Cache<G, L>,
+) -> Result<(), TestError<Vec<Op>>> {
+    let mut runner = TestRunner::new(Config {
+        cases,
+        failure_persistence: None,
+        ..Config::default()
+    });
+    runner.run(&ops(S::LEN, len), |ops| check::<S, G, L>(new_cache(), &ops))
+}
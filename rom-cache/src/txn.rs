@@ -0,0 +1,185 @@
+//! Multi-guard transactions over [`Cache`].
+//!
+//! Holding several guards at once by hand is prone to livelock, e.g. two threads keep retrying
+//! `get_mut::<A>()` and `get_mut::<B>()` in opposite orders. A [`Txn`] acquires all its guards
+//! in a fixed order (by `CacheGroup`, then by type), releases them all and retries on transient
+//! `CacheError::Busy` or `CacheError::Locked`. The `CacheGroup` of each `Cacheable` is resolved
+//! as by `Cache::get()`, i.e. after [`Cache::migrate()`], [`Cacheable::GROUP_HINT`] and the placement.
+//!
+//! ```ignore
+//! # use rom_cache::Cache;
+//! let cache: Cache<8, 2> = Cache::default();
+//! cache.txn().read::<A>().write::<B>().run(|a, b| b.value += a.value)?;
+//! ```
+
+use crate::cache::{type_id_usize, Cache, CacheMut, CacheRef};
+use crate::error::CacheResult;
+use crate::{CacheError, Cacheable};
+use std::marker::PhantomData;
+
+/// A transaction over a [`Cache`], see [module level docs](self).
+///
+/// At most 4 `Cacheable`s can be accessed in one transaction,
+/// and each of them should appear only once, or the transaction always fails with `CacheError::Locked`.
+pub struct Txn<'c, const G: usize, const L: usize, A> {
+    cache: &'c Cache<G, L>,
+    retries: usize,
+    _accesses: PhantomData<A>,
+}
+
+impl<const G: usize, const L: usize> Cache<G, L> {
+    /// Start a transaction, see [`Txn`].
+    pub fn txn(&self) -> Txn<'_, G, L, ()> {
+        Txn {
+            cache: self,
+            retries: 64,
+            _accesses: PhantomData,
+        }
+    }
+}
+
+impl<'c, const G: usize, const L: usize, A> Txn<'c, G, L, A> {
+    /// Retry at most `retries` times (64 by default) before giving up with the last transient error.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    fn push<B>(self) -> Txn<'c, G, L, B> {
+        Txn {
+            cache: self.cache,
+            retries: self.retries,
+            _accesses: PhantomData,
+        }
+    }
+}
+
+/// Shared access to T in a [`Txn`].
+pub struct Read<T>(PhantomData<T>);
+
+/// Exclusive access to T in a [`Txn`], which marks T dirty.
+pub struct Write<T>(PhantomData<T>);
+
+/// An access to a `Cacheable` in a [`Txn`], implemented by [`Read`] and [`Write`].
+pub trait Access: private::Sealed {
+    /// The guard held during the transaction.
+    type Guard<'a>;
+    /// The reference passed to the transaction.
+    type Ref<'g>;
+
+    /// The key to sort the acquisitions by, the `CacheGroup` T is retrieved from first.
+    fn key<const G: usize, const L: usize>(cache: &Cache<G, L>) -> (usize, usize);
+    /// Acquire the guard without blocking.
+    fn acquire<const G: usize, const L: usize>(cache: &Cache<G, L>)
+        -> CacheResult<Self::Guard<'_>>;
+    /// Borrow the reference from the guard.
+    fn borrow<'g>(guard: &'g mut Self::Guard<'_>) -> Self::Ref<'g>;
+}
+
+mod private {
+    pub trait Sealed {}
+    impl<T> Sealed for super::Read<T> {}
+    impl<T> Sealed for super::Write<T> {}
+}
+
+impl<T: Cacheable + Send + Sync + Default> Access for Read<T> {
+    type Guard<'a> = CacheRef<'a, T>;
+    type Ref<'g> = &'g T;
+
+    fn key<const G: usize, const L: usize>(cache: &Cache<G, L>) -> (usize, usize) {
+        (cache.home_of::<T>(), type_id_usize::<T>())
+    }
+
+    fn acquire<const G: usize, const L: usize>(
+        cache: &Cache<G, L>,
+    ) -> CacheResult<Self::Guard<'_>> {
+        cache.get::<T>()
+    }
+
+    fn borrow<'g>(guard: &'g mut Self::Guard<'_>) -> Self::Ref<'g> {
+        guard
+    }
+}
+
+impl<T: Cacheable + Send + Sync + Default> Access for Write<T> {
+    type Guard<'a> = CacheMut<'a, T>;
+    type Ref<'g> = &'g mut T;
+
+    fn key<const G: usize, const L: usize>(cache: &Cache<G, L>) -> (usize, usize) {
+        (cache.home_of::<T>(), type_id_usize::<T>())
+    }
+
+    fn acquire<const G: usize, const L: usize>(
+        cache: &Cache<G, L>,
+    ) -> CacheResult<Self::Guard<'_>> {
+        cache.get_mut::<T>()
+    }
+
+    fn borrow<'g>(guard: &'g mut Self::Guard<'_>) -> Self::Ref<'g> {
+        guard
+    }
+}
+
+macro_rules! impl_push {
+    ($($A:ident),*) => {
+        impl<'c, const G: usize, const L: usize, $($A: Access),*> Txn<'c, G, L, ($($A,)*)> {
+            /// Read T in the transaction.
+            pub fn read<T: Cacheable + Send + Sync + Default>(self) -> Txn<'c, G, L, ($($A,)* Read<T>,)> {
+                self.push()
+            }
+
+            /// Write T in the transaction.
+            pub fn write<T: Cacheable + Send + Sync + Default>(self) -> Txn<'c, G, L, ($($A,)* Write<T>,)> {
+                self.push()
+            }
+        }
+    };
+}
+
+impl_push!();
+impl_push!(A0);
+impl_push!(A0, A1);
+impl_push!(A0, A1, A2);
+
+macro_rules! impl_run {
+    ($($A:ident $g:ident $i:tt),+) => {
+        impl<'c, const G: usize, const L: usize, $($A: Access),+> Txn<'c, G, L, ($($A,)+)> {
+            /// Acquire all the guards and run `f` with them, releasing them afterwards.
+            /// - CacheError::Busy or CacheError::Locked: still failed after retrying.
+            /// - Other errors are returned immediately.
+            pub fn run<R>(self, f: impl FnOnce($($A::Ref<'_>),+) -> R) -> CacheResult<R> {
+                let mut retries = 0;
+                loop {
+                    // resolved on each attempt, as `Cache::migrate()` may move them meanwhile
+                    let mut order = [$(($A::key(self.cache), $i)),+];
+                    order.sort_unstable();
+                    $(let mut $g = None;)+
+                    let mut failed = None;
+                    for &(_, i) in order.iter() {
+                        let res = match i {
+                            $($i => $A::acquire(self.cache).map(|guard| $g = Some(guard)),)+
+                            _ => unreachable!(),
+                        };
+                        if let Err(e) = res {
+                            failed = Some(e);
+                            break;
+                        }
+                    }
+                    match failed {
+                        None => return Ok(f($($A::borrow($g.as_mut().unwrap())),+)),
+                        Some(CacheError::Busy | CacheError::Locked) if retries < self.retries => {
+                            retries += 1;
+                            std::thread::yield_now();
+                        }
+                        Some(e) => return Err(e),
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_run!(A0 g0 0);
+impl_run!(A0 g0 0, A1 g1 1);
+impl_run!(A0 g0 0, A1 g1 1, A2 g2 2);
+impl_run!(A0 g0 0, A1 g1 1, A2 g2 2, A3 g3 3);
@@ -0,0 +1,2167 @@
+#![cfg(not(loom))]
+
+use rom_cache::test_util::MockCacheable;
+use rom_cache::{Cache, CacheError, Cacheable};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static STORED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Default)]
+struct Data<const N: usize> {
+    inner: usize,
+}
+
+impl<const N: usize> Cacheable for Data<N> {
+    fn load() -> std::io::Result<Self> {
+        Ok(Self { inner: N })
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        STORED.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Important {
+    inner: usize,
+}
+
+impl Cacheable for Important {
+    const PRIORITY: u8 = 1;
+
+    fn load() -> std::io::Result<Self> {
+        Ok(Self { inner: 42 })
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn read_only() {
+    let cache: Cache<1, 2> = Cache::read_only();
+    assert_eq!(cache.get::<Data<1>>().unwrap().inner, 1);
+    assert!(matches!(
+        cache.get_mut::<Data<1>>(),
+        Err(CacheError::ReadOnly)
+    ));
+}
+
+#[test]
+fn priority() {
+    let cache: Cache<1, 2> = Cache::default();
+    cache.get_mut::<Important>().unwrap().inner = 0;
+    cache.get::<Data<1>>().unwrap();
+    cache.get::<Data<2>>().unwrap();
+    // `Data<1>` is evicted even though `Important` is less recently used.
+    assert_eq!(cache.get::<Important>().unwrap().inner, 0);
+}
+
+#[test]
+fn max_dirty() {
+    type A = MockCacheable<u8>;
+    type B = MockCacheable<u16>;
+    type C = MockCacheable<u32>;
+    let cache: Cache<1, 4> = Cache::builder().max_dirty(1).build();
+    cache.get_mut::<A>().unwrap().value = 1;
+    cache.get_mut::<B>().unwrap().value = 2;
+    A::assert_stores(0);
+    cache.get::<C>().unwrap();
+    A::assert_stores(1);
+    A::assert_stored(&1);
+    B::assert_stores(0);
+    // a failed write-back never fails the access, and is tried again on the next one
+    cache.faults().fail_next_stores::<B>(1);
+    cache.get_mut::<A>().unwrap().value = 3;
+    cache.get::<C>().unwrap();
+    B::assert_stores(0);
+    cache.get::<C>().unwrap();
+    B::assert_stores(1);
+    B::assert_stored(&2);
+}
+
+#[test]
+fn faults() {
+    let cache: Cache<1, 1> = Cache::default();
+    cache.faults().fail_next_stores::<Data<3>>(1);
+    cache.get_mut::<Data<3>>().unwrap().inner = 0;
+    assert!(matches!(cache.get::<Data<4>>(), Err(CacheError::Io(_))));
+    cache.get::<Data<4>>().unwrap();
+}
+
+#[test]
+fn close() {
+    let cache: Cache<2, 2> = Cache::default();
+    cache.faults().fail_next_stores::<Data<5>>(1);
+    cache.get_mut::<Data<5>>().unwrap().inner = 0;
+    cache.get_mut::<Data<6>>().unwrap().inner = 0;
+    let errors = cache.close().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].0.ends_with("Data<5>"));
+}
+
+#[test]
+fn on_store_error() {
+    use std::sync::{Arc, Mutex};
+
+    let failed = Arc::new(Mutex::new(vec![]));
+    let cache: Cache<1, 1> = Cache::default();
+    let f = failed.clone();
+    cache.on_store_error(move |name, _| f.lock().unwrap().push(name.to_string()));
+    cache.faults().fail_next_stores::<Data<7>>(1);
+    cache.get_mut::<Data<7>>().unwrap().inner = 0;
+    drop(cache);
+    assert_eq!(failed.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn local_cache() {
+    use rom_cache::LocalCache;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct NotSend(Rc<usize>);
+
+    impl Cacheable for NotSend {
+        fn load() -> std::io::Result<Self> {
+            Ok(Self(Rc::new(1)))
+        }
+
+        fn store(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let cache: LocalCache<1, 1> = LocalCache::default();
+    let a = cache.get::<NotSend>().unwrap();
+    assert_eq!(*a.0, 1);
+    assert!(matches!(
+        cache.get_mut::<NotSend>(),
+        Err(CacheError::Locked)
+    ));
+    assert!(matches!(cache.get::<Data<8>>(), Err(CacheError::Busy)));
+    drop(a);
+    cache.get_mut::<NotSend>().unwrap().0 = Rc::new(2);
+    assert_eq!(*cache.get::<NotSend>().unwrap().0, 2);
+    cache.get::<Data<8>>().unwrap();
+    assert_eq!(*cache.get::<NotSend>().unwrap().0, 1);
+
+    // a `load` accessing the same cache gets an error instead of a panic
+    thread_local! {
+        static LOCAL: LocalCache<1, 2> = LocalCache::default();
+    }
+
+    #[derive(Default)]
+    struct Reentrant(bool);
+
+    impl Cacheable for Reentrant {
+        fn load() -> std::io::Result<Self> {
+            let busy = LOCAL.with(|cache| matches!(cache.get::<Data<8>>(), Err(CacheError::Busy)));
+            Ok(Self(busy))
+        }
+    }
+
+    assert!(LOCAL.with(|cache| cache.get::<Reentrant>().unwrap().0));
+}
+
+#[test]
+fn bump_generation() {
+    type A = MockCacheable<i8>;
+    let cache: Cache<1, 2> = Cache::default();
+    cache.get_mut::<A>().unwrap().value = 1;
+    A::assert_loads(1);
+    cache.bump_generation().unwrap();
+    A::assert_stores(1);
+    assert_eq!(cache.get::<A>().unwrap().value, 1);
+    A::assert_loads(2);
+}
+
+#[test]
+fn refresh() {
+    use rom_cache::RefreshPolicy;
+
+    type A = MockCacheable<i16>;
+    let cache: Cache<1, 1> = Cache::default();
+    cache.get_mut::<A>().unwrap().value = 1;
+    A::set_stored(2);
+    assert!(matches!(
+        cache.refresh::<A>(RefreshPolicy::Error),
+        Err(CacheError::Dirty)
+    ));
+    cache
+        .refresh::<A>(RefreshPolicy::Merge(Box::new(|a, b| a.value += b.value)))
+        .unwrap();
+    assert_eq!(cache.get::<A>().unwrap().value, 3);
+    cache.refresh::<A>(RefreshPolicy::Overwrite).unwrap();
+    assert_eq!(cache.get::<A>().unwrap().value, 2);
+}
+
+#[test]
+#[cfg_attr(miri, ignore = "timing-sensitive")]
+fn stale_while_revalidate() {
+    use std::time::Duration;
+
+    type A = MockCacheable<i32>;
+    A::set_stored(1);
+    let cache: Cache<1, 1> = Cache::builder()
+        .ttl(Duration::from_millis(10))
+        .stale_while_revalidate(true)
+        .build();
+    assert_eq!(cache.get::<A>().unwrap().value, 1);
+    A::set_stored(2);
+    // expired, served stale while loaded again in background
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(cache.get::<A>().unwrap().value, 1);
+    assert!(A::wait_loads(2, Duration::from_secs(5)));
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while cache.get::<A>().unwrap().value != 2 {
+        assert!(std::time::Instant::now() < deadline, "not revalidated");
+        std::thread::yield_now();
+    }
+}
+
+#[test]
+fn negative_ttl() {
+    use std::time::Duration;
+
+    type A = MockCacheable<i64>;
+    let cache: Cache<1, 1> = Cache::builder()
+        .negative_ttl(Duration::from_secs(60))
+        .build();
+    cache.get::<A>().unwrap();
+    cache.get::<Data<9>>().unwrap();
+    cache.get::<A>().unwrap();
+    A::assert_loads(1);
+}
+
+#[test]
+fn singleflight() {
+    use std::time::Duration;
+
+    type A = MockCacheable<u64>;
+    A::set_load_delay(Duration::from_millis(50));
+    let cache: Cache<1, 1> = Cache::default();
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| cache.get::<A>().unwrap().value);
+        }
+    });
+    A::assert_loads(1);
+}
+
+#[test]
+fn doorkeeper() {
+    type A = MockCacheable<char>;
+    type B = MockCacheable<bool>;
+    let cache: Cache<1, 1> = Cache::builder().doorkeeper(16).build();
+    cache.get::<A>().unwrap();
+    // the first miss of B is served without evicting A
+    **cache.get_mut::<B>().unwrap() = true;
+    B::assert_stored(&true);
+    cache.get::<A>().unwrap();
+    A::assert_loads(1);
+    // the second miss of B is admitted
+    assert!(**cache.get::<B>().unwrap());
+    assert!(**cache.get::<B>().unwrap());
+    B::assert_loads(2);
+}
+
+#[test]
+fn for_each() {
+    type A = MockCacheable<String>;
+    let cache: Cache<4, 4> = Cache::default();
+    cache.get::<A>().unwrap();
+    cache.get_mut::<Data<10>>().unwrap().inner += 1;
+    let _writing = cache.get_mut::<MockCacheable<()>>().unwrap();
+    let mut entries = vec![];
+    cache
+        .for_each(|info, value| {
+            assert_eq!(value.is::<Data<10>>(), info.dirty);
+            entries.push(info.type_name);
+        })
+        .unwrap();
+    entries.sort_unstable();
+    assert_eq!(
+        entries,
+        [
+            std::any::type_name::<Data<10>>(),
+            std::any::type_name::<A>()
+        ]
+    );
+}
+
+#[test]
+fn register() {
+    type A = MockCacheable<u128>;
+    let cache: Cache<1, 2> = Cache::default();
+    cache.register::<A>().unwrap();
+    cache.register::<Data<11>>().unwrap();
+    cache.register::<A>().unwrap();
+    assert!(matches!(
+        cache.register::<Data<12>>(),
+        Err(CacheError::Collision { .. })
+    ));
+    assert_eq!(cache.registered().len(), 2);
+    cache.warm_all().unwrap();
+    A::assert_loads(1);
+    cache.get_mut::<A>().unwrap().value = 1;
+    cache.flush_all_registered().unwrap();
+    A::assert_stored(&1);
+}
+
+#[test]
+fn get_mut_or_insert_with() {
+    type A = MockCacheable<i128>;
+    let cache: Cache<1, 1> = Cache::default();
+    assert_eq!(cache.get_mut_or_insert_with(|| A::new(1)).unwrap().value, 1);
+    assert_eq!(cache.get_mut_or_insert_with(|| A::new(2)).unwrap().value, 1);
+    A::assert_loads(0);
+    cache.get::<Data<13>>().unwrap();
+    A::assert_stored(&1);
+}
+
+#[test]
+fn get_or_init() {
+    type B = MockCacheable<Vec<u8>>;
+    B::set_load_delay(std::time::Duration::from_millis(20));
+    let cache: Cache<1, 1> = Cache::default();
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| cache.get_or_init::<B>().map(drop));
+        }
+    });
+    B::assert_loads(1);
+    cache.get::<Data<14>>().unwrap();
+    cache.bump_generation().unwrap();
+    cache.get_or_init::<B>().unwrap();
+    cache.get::<B>().unwrap();
+    B::assert_loads(1);
+}
+
+#[test]
+fn txn() {
+    type A = MockCacheable<isize>;
+    type B = MockCacheable<usize>;
+    A::set_stored(1);
+    let cache: Cache<2, 2> = Cache::default();
+    // ordered by the groups they are migrated to
+    cache.migrate::<A>(1).unwrap();
+    cache.migrate::<B>(0).unwrap();
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for _ in 0..100 {
+                cache
+                    .txn()
+                    .read::<A>()
+                    .write::<B>()
+                    .run(|a, b| b.value += a.value as usize)
+                    .unwrap();
+            }
+        });
+        s.spawn(|| {
+            for _ in 0..100 {
+                cache
+                    .txn()
+                    .write::<B>()
+                    .read::<A>()
+                    .run(|b, a| b.value += a.value as usize)
+                    .unwrap();
+            }
+        });
+    });
+    assert_eq!(cache.get::<B>().unwrap().value, 200);
+}
+
+#[test]
+fn snapshot_reads() {
+    type A = MockCacheable<f32>;
+    A::set_stored(1.0);
+    let cache: Cache<1, 1> = Cache::builder().snapshot_reads(true).build();
+    let mut a = cache.get_mut::<A>().unwrap();
+    a.value = 2.0;
+    assert_eq!(cache.get::<A>().unwrap().value, 1.0);
+    drop(a);
+    assert_eq!(cache.get::<A>().unwrap().value, 2.0);
+    // the committed one is read while still held
+    let mut a = cache.get_mut::<A>().unwrap();
+    a.value = 3.0;
+    a.commit().unwrap();
+    a.value = 4.0;
+    assert_eq!(cache.get::<A>().unwrap().value, 3.0);
+    drop(a);
+    A::assert_stored(&3.0);
+}
+
+#[test]
+fn warm_from() {
+    type A = MockCacheable<f64>;
+    let path = std::env::temp_dir().join(format!("rom_cache_warm_{}", std::process::id()));
+    let cache: Cache<2, 2> = Cache::default();
+    cache.register::<A>().unwrap();
+    cache.get::<A>().unwrap();
+    cache.get::<Data<15>>().unwrap();
+    cache.save_warm_set(&path).unwrap();
+    drop(cache);
+
+    let cache: Cache<2, 2> = Cache::default();
+    cache.register::<A>().unwrap();
+    cache.warm_from(&path).unwrap();
+    A::assert_loads(2);
+    cache.get::<A>().unwrap();
+    A::assert_loads(2);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rebalance() {
+    type A = MockCacheable<[u8; 1]>;
+    type B = MockCacheable<[u8; 2]>;
+    let cache: Cache<2, 1> = Cache::default();
+    cache.migrate::<A>(0).unwrap();
+    cache.migrate::<B>(0).unwrap();
+    for _ in 0..2 {
+        cache.get::<A>().unwrap();
+        cache.get::<B>().unwrap();
+    }
+    A::assert_loads(2);
+    // `B` is moved to the empty group
+    assert!(cache.rebalance().unwrap().is_some());
+    for _ in 0..2 {
+        cache.get::<A>().unwrap();
+        cache.get::<B>().unwrap();
+    }
+    A::assert_loads(3);
+    B::assert_loads(3);
+}
+
+#[test]
+fn resize_lines() {
+    type A = MockCacheable<[u8; 3]>;
+    type B = MockCacheable<[u8; 4]>;
+    let cache: Cache<1, 2> = Cache::default();
+    cache.get::<A>().unwrap();
+    cache.get_mut::<B>().unwrap().value = [1; 4];
+    // `B` in the second line is written back and dropped
+    cache.resize_lines(1).unwrap();
+    B::assert_stored(&[1; 4]);
+    cache.get::<A>().unwrap();
+    cache.get::<B>().unwrap();
+    cache.get::<A>().unwrap();
+    A::assert_loads(2);
+    B::assert_loads(2);
+    cache.resize_lines(2).unwrap();
+    cache.get::<B>().unwrap();
+    cache.get::<A>().unwrap();
+    cache.get::<B>().unwrap();
+    A::assert_loads(2);
+    B::assert_loads(3);
+}
+
+#[test]
+fn purge_clean() {
+    type A = MockCacheable<[u8; 5]>;
+    let cache: Cache<1, 4> = Cache::default();
+    cache.get::<A>().unwrap();
+    cache.get_mut::<Data<17>>().unwrap().inner = 0;
+    let _reading = cache.get::<Data<18>>().unwrap();
+    assert_eq!(cache.purge_clean().unwrap(), 1);
+    cache.get::<A>().unwrap();
+    A::assert_loads(2);
+    A::assert_stores(0);
+}
+
+#[test]
+fn max_bytes() {
+    let cache: Cache<1, 4> = Cache::builder()
+        .max_bytes(2 * std::mem::size_of::<Data<0>>())
+        .build();
+    cache.get::<Data<19>>().unwrap();
+    cache.get_mut::<Data<20>>().unwrap().inner = 0;
+    // The cold clean `Data<19>` is evicted before the dirty `Data<20>`.
+    cache.get::<Data<21>>().unwrap();
+    cache.get::<Data<22>>().unwrap();
+    let mut cached = vec![];
+    cache
+        .for_each(|_, value| cached.push(value.downcast_ref::<Data<20>>().is_some()))
+        .unwrap();
+    assert_eq!(cached.len(), 2);
+    assert!(cached.contains(&true));
+    assert_eq!(cache.bytes(), 2 * std::mem::size_of::<Data<0>>());
+    // failing to write back the only one to evict never fails the access, and exceeds the budget
+    let _held = cache.get::<Data<22>>().unwrap();
+    cache.faults().fail_next_stores::<Data<20>>(1);
+    cache.get::<Data<73>>().unwrap();
+    assert_eq!(cache.bytes(), 3 * std::mem::size_of::<Data<0>>());
+}
+
+#[test]
+fn timestamps() {
+    let cache: Cache<1, 2> = Cache::default();
+    let before = std::time::SystemTime::now();
+    cache.get::<Data<23>>().unwrap();
+    cache.get_mut::<Data<24>>().unwrap().inner = 0;
+    let mut dirtied = vec![];
+    cache
+        .for_each(|info, _| {
+            assert!(info.accessed_at.unwrap() >= before);
+            dirtied.push(info.dirtied_at.is_some());
+        })
+        .unwrap();
+    dirtied.sort();
+    assert_eq!(dirtied, [false, true]);
+}
+
+#[derive(Default)]
+struct Expiring;
+
+static EXPIRING_LOADS: AtomicUsize = AtomicUsize::new(0);
+
+impl Cacheable for Expiring {
+    const EXPIRE_AFTER: Option<std::time::Duration> = Some(std::time::Duration::ZERO);
+
+    fn load() -> std::io::Result<Self> {
+        EXPIRING_LOADS.fetch_add(1, Ordering::Relaxed);
+        Ok(Self)
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn expire_after() {
+    // No TTL is configured, but `Expiring` expires immediately.
+    let cache: Cache<1, 2> = Cache::default();
+    cache.get::<Expiring>().unwrap();
+    cache.get::<Expiring>().unwrap();
+    assert_eq!(EXPIRING_LOADS.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn evictions() {
+    type A = MockCacheable<[u8; 6]>;
+    let cache: Cache<1, 1> = Cache::default();
+    let evictions = cache.evictions();
+    cache.get_mut::<A>().unwrap().value = [1; 6];
+    A::fail_next_stores(1);
+    assert!(cache.get::<Data<25>>().is_err());
+    cache.get::<Data<25>>().unwrap();
+    cache.get::<Data<26>>().unwrap();
+    let events = evictions
+        .try_iter()
+        .map(|e| (e.dirty, e.stored))
+        .collect::<Vec<_>>();
+    assert_eq!(events, [(true, false), (true, true), (false, false)]);
+}
+
+#[test]
+fn watch() {
+    type A = MockCacheable<[u8; 7]>;
+    let cache: Cache<1, 2> = Cache::default();
+    let updates = cache.watch::<A>();
+    cache.get::<A>().unwrap();
+    assert!(updates.try_recv().is_ok());
+    let _ = cache.get_mut::<A>().unwrap().value;
+    assert!(updates.try_recv().is_err());
+    cache.get_mut::<A>().unwrap().value = [1; 7];
+    cache.get_mut::<A>().unwrap().value = [2; 7];
+    // Coalesced into one notification.
+    assert!(updates.try_recv().is_ok());
+    assert!(updates.try_recv().is_err());
+}
+
+#[derive(Default)]
+struct World {
+    player: (usize, Vec<usize>),
+}
+
+impl Cacheable for World {
+    fn load() -> std::io::Result<Self> {
+        Ok(Self {
+            player: (1, vec![2]),
+        })
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn project() {
+    let cache: Cache<1, 1> = Cache::default();
+    let world = cache.get::<World>().unwrap();
+    let hp = rom_cache::project!(world.player.0);
+    assert_eq!(*hp, 1);
+    assert!(matches!(cache.get_mut::<World>(), Err(CacheError::Locked)));
+    drop(hp);
+    let world = cache.get_mut::<World>().unwrap();
+    let mut items = rom_cache::project!(mut world.player.1);
+    items.push(3);
+    drop(items);
+    assert_eq!(cache.get::<World>().unwrap().player.1, [2, 3]);
+    cache.for_each(|info, _| assert!(info.dirty)).unwrap();
+}
+
+#[test]
+fn fork() {
+    type A = MockCacheable<[u8; 8]>;
+    let cache: Cache<1, 2> = Cache::default();
+    cache.get_mut::<A>().unwrap().value = [1; 8];
+    cache.get::<Data<27>>().unwrap();
+    let fork = cache.fork().unwrap();
+    fork.get_mut::<A>().unwrap().value = [2; 8];
+    assert_eq!(cache.get::<A>().unwrap().value, [1; 8]);
+    assert_eq!(fork.get::<A>().unwrap().value, [2; 8]);
+    // `A` is copied, `Data<27>` is not.
+    A::assert_loads(1);
+    let mut forked = 0;
+    fork.for_each(|info, _| {
+        assert!(info.dirty);
+        forked += 1;
+    })
+    .unwrap();
+    assert_eq!(forked, 1);
+}
+
+#[test]
+fn absorb() {
+    type A = MockCacheable<[u8; 9]>;
+    type B = MockCacheable<[u8; 10]>;
+    let live: Cache<1, 2> = Cache::default();
+    live.get::<A>().unwrap();
+    live.get_mut::<B>().unwrap().value = [1; 10];
+    let staging: Cache<1, 2> = Cache::default();
+    staging.get_mut::<A>().unwrap().value = [2; 9];
+    staging.get::<B>().unwrap();
+    live.absorb(staging, rom_cache::ConflictPolicy::PreferDirty)
+        .unwrap();
+    // The dirty ones win, and the discarded ones are never written back.
+    assert_eq!(live.get::<A>().unwrap().value, [2; 9]);
+    assert_eq!(live.get::<B>().unwrap().value, [1; 10]);
+    A::assert_loads(2);
+    A::assert_stores(0);
+    B::assert_stores(0);
+
+    // a dirty one replaced is written back first
+    let staging: Cache<1, 2> = Cache::default();
+    staging.get_mut::<B>().unwrap().value = [3; 10];
+    live.absorb(staging, rom_cache::ConflictPolicy::PreferDirty)
+        .unwrap();
+    B::assert_stores(1);
+    assert_eq!(B::stored().unwrap(), [1; 10]);
+    assert_eq!(live.get::<B>().unwrap().value, [3; 10]);
+}
+
+#[test]
+fn warm_from_restores_recency() {
+    type A = MockCacheable<[u8; 11]>;
+    type B = MockCacheable<[u8; 12]>;
+    let path = std::env::temp_dir().join(format!("rom_cache_recency_{}", std::process::id()));
+    let cache: Cache<1, 2> = Cache::default();
+    cache.register::<A>().unwrap();
+    cache.register::<B>().unwrap();
+    cache.get::<A>().unwrap();
+    cache.get::<B>().unwrap();
+    cache.get::<A>().unwrap();
+    cache.save_warm_set(&path).unwrap();
+    drop(cache);
+
+    let cache: Cache<1, 2> = Cache::default();
+    cache.register::<A>().unwrap();
+    cache.register::<B>().unwrap();
+    cache.warm_from(&path).unwrap();
+    // `B` is still the least recently used, and evicted first.
+    cache.get::<Data<28>>().unwrap();
+    cache.get::<A>().unwrap();
+    A::assert_loads(2);
+    B::assert_loads(2);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn get_dyn() {
+    let cache: Cache<1, 2> = Cache::default();
+    let type_id = std::any::TypeId::of::<Data<29>>();
+    assert!(matches!(
+        cache.get_dyn(type_id),
+        Err(CacheError::Unregistered)
+    ));
+    cache.register::<Data<29>>().unwrap();
+    let data = cache.get_dyn(type_id).unwrap();
+    assert_eq!(data.downcast_ref::<Data<29>>().unwrap().inner, 29);
+    assert!(matches!(
+        cache.get_mut::<Data<29>>(),
+        Err(CacheError::Locked)
+    ));
+}
+
+#[test]
+fn register_dyn() {
+    static LOADS: AtomicUsize = AtomicUsize::new(0);
+    static STORES: AtomicUsize = AtomicUsize::new(0);
+    struct Plugin(usize);
+    let cache: Cache<1, 1> = Cache::default();
+    let type_id = std::any::TypeId::of::<Plugin>();
+    cache
+        .register_dyn(
+            type_id,
+            "plugin",
+            || {
+                LOADS.fetch_add(1, Ordering::Relaxed);
+                Ok(Box::new(Plugin(7)))
+            },
+            |value| {
+                assert_eq!(value.downcast_ref::<Plugin>().unwrap().0, 7);
+                STORES.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            },
+        )
+        .unwrap();
+    cache.warm_all().unwrap();
+    let plugin = cache.get_dyn(type_id).unwrap();
+    assert_eq!(plugin.downcast_ref::<Plugin>().unwrap().0, 7);
+    drop(plugin);
+    assert_eq!(LOADS.load(Ordering::Relaxed), 1);
+    cache
+        .for_each(|info, _| assert_eq!(info.type_name, "plugin"))
+        .unwrap();
+    cache.get::<Data<30>>().unwrap();
+    cache.get_dyn(type_id).unwrap();
+    assert_eq!(LOADS.load(Ordering::Relaxed), 2);
+    assert_eq!(STORES.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn get_mut_dyn() {
+    static STORED: std::sync::Mutex<Option<usize>> = std::sync::Mutex::new(None);
+    struct Plugin(usize);
+    let cache: Cache<1, 2> = Cache::default();
+    let type_id = std::any::TypeId::of::<Plugin>();
+    cache
+        .register_dyn(
+            type_id,
+            "plugin",
+            || Ok(Box::new(Plugin(0))),
+            |value| {
+                *STORED.lock().unwrap() = Some(value.downcast_ref::<Plugin>().unwrap().0);
+                Ok(())
+            },
+        )
+        .unwrap();
+    cache
+        .get_mut_dyn(type_id)
+        .unwrap()
+        .downcast_mut::<Plugin>()
+        .unwrap()
+        .0 = 1;
+    cache.register::<Data<31>>().unwrap();
+    cache
+        .get_mut_dyn(std::any::TypeId::of::<Data<31>>())
+        .unwrap()
+        .downcast_mut::<Data<31>>()
+        .unwrap()
+        .inner = 0;
+    cache.get::<Data<32>>().unwrap();
+    assert_eq!(*STORED.lock().unwrap(), Some(1));
+}
+
+#[test]
+fn register_object() {
+    trait Asset: Send + Sync {
+        fn size(&self) -> usize;
+        fn grow(&mut self);
+    }
+    struct Png(usize);
+    impl Asset for Png {
+        fn size(&self) -> usize {
+            self.0
+        }
+        fn grow(&mut self) {
+            self.0 += 1;
+        }
+    }
+    struct Background;
+    struct Foreground;
+    let cache: Cache<2, 2> = Cache::default();
+    cache
+        .register_object::<Background, dyn Asset>(|| Ok(Box::new(Png(1))), |_| Ok(()))
+        .unwrap();
+    cache
+        .register_object::<Foreground, dyn Asset>(|| Ok(Box::new(Png(2))), |_| Ok(()))
+        .unwrap();
+    cache
+        .get_mut_object::<Background, dyn Asset>()
+        .unwrap()
+        .grow();
+    assert_eq!(
+        cache.get_object::<Background, dyn Asset>().unwrap().size(),
+        2
+    );
+    assert_eq!(
+        cache.get_object::<Foreground, dyn Asset>().unwrap().size(),
+        2
+    );
+}
+
+#[test]
+fn adapters() {
+    type A = MockCacheable<[u8; 13]>;
+    let cache: Cache<1, 4> = Cache::default();
+    let shared = cache.get::<std::sync::Arc<Data<33>>>().unwrap().clone();
+    assert_eq!(shared.inner, 33);
+    cache.get_mut::<Box<Data<34>>>().unwrap().inner = 0;
+    cache
+        .get_mut::<std::sync::RwLock<A>>()
+        .unwrap()
+        .get_mut()
+        .unwrap()
+        .value = [1; 13];
+    drop(cache);
+    A::assert_stored(&[1; 13]);
+}
+
+#[test]
+fn flush_on_drop() {
+    type A = MockCacheable<[u8; 14]>;
+    type B = MockCacheable<[u8; 15]>;
+    static FAILED: AtomicUsize = AtomicUsize::new(0);
+    let cache: Cache<1, 2> = Cache::default();
+    cache.on_store_error(|_, _| {
+        FAILED.fetch_add(1, Ordering::Relaxed);
+    });
+    {
+        let _guard = cache.flush_on_drop();
+        cache.get_mut::<A>().unwrap().value = [1; 14];
+        cache.get_mut::<B>().unwrap().value = [1; 15];
+        B::fail_next_stores(1);
+    }
+    A::assert_stored(&[1; 14]);
+    assert_eq!(FAILED.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn fully_associative() {
+    type A = MockCacheable<[u8; 16]>;
+    type B = MockCacheable<[u8; 17]>;
+    type C = MockCacheable<[u8; 18]>;
+    type D = MockCacheable<[u8; 19]>;
+    let cache: Cache<4, 1> = Cache::builder().fully_associative(true).build();
+    for _ in 0..3 {
+        cache.get::<A>().unwrap();
+        cache.get::<B>().unwrap();
+        cache.get::<C>().unwrap();
+        cache.get::<D>().unwrap();
+    }
+    A::assert_loads(1);
+    B::assert_loads(1);
+    C::assert_loads(1);
+    D::assert_loads(1);
+    cache.register::<A>().unwrap();
+    cache.register::<B>().unwrap();
+    cache.register::<C>().unwrap();
+    cache.register::<D>().unwrap();
+    assert!(matches!(
+        cache.register::<MockCacheable<[u8; 20]>>(),
+        Err(CacheError::Collision { .. })
+    ));
+}
+
+#[test]
+#[cfg_attr(miri, ignore = "timing-sensitive")]
+fn write_buffer() {
+    use std::time::Duration;
+    type A = MockCacheable<[u8; 23]>;
+    type B = MockCacheable<[u8; 24]>;
+    let cache: Cache<1, 1> = Cache::builder().write_buffer(4).build();
+    cache.get_mut::<A>().unwrap().value = [1; 23];
+    A::hold_stores();
+    // released late if the eviction waits for the store, failing below instead of hanging
+    std::thread::spawn(|| {
+        std::thread::sleep(Duration::from_secs(10));
+        A::release_stores();
+    });
+    // A is evicted without waiting for its store, which is still held
+    cache.get::<B>().unwrap();
+    assert!(A::wait_held_stores(1, Duration::from_secs(5)));
+    assert_eq!(A::stored(), None);
+    A::release_stores();
+    // A is written back before loaded again
+    assert_eq!(cache.get::<A>().unwrap().value, [1; 23]);
+    A::assert_stores(1);
+    A::assert_loads(2);
+}
+
+#[test]
+fn hot_tier() {
+    type A = MockCacheable<[u8; 25]>;
+    type B = MockCacheable<[u8; 26]>;
+    type C = MockCacheable<[u8; 27]>;
+    type D = MockCacheable<[u8; 28]>;
+    let cache: Cache<1, 2> = Cache::builder().hot_tier(1).build();
+    cache.get::<A>().unwrap();
+    // promoted on hit
+    cache.get::<A>().unwrap();
+    // a scan only evicts the cold tier
+    cache.get::<B>().unwrap();
+    cache.get::<C>().unwrap();
+    cache.get::<D>().unwrap();
+    cache.get::<A>().unwrap();
+    A::assert_loads(1);
+    D::assert_loads(1);
+}
+
+#[test]
+fn warm_up() {
+    type A = MockCacheable<[u8; 29]>;
+    type B = MockCacheable<[u8; 30]>;
+    let cache: Cache<2, 2> = Cache::default();
+    let results = rom_cache::warm_up!(cache, A, B);
+    assert_eq!(results[0].0, std::any::type_name::<A>());
+    assert!(results.iter().all(|(_, res)| res.is_ok()));
+    cache.get::<A>().unwrap();
+    cache.get::<B>().unwrap();
+    A::assert_loads(1);
+    B::assert_loads(1);
+}
+
+#[test]
+fn reserve() {
+    type A = MockCacheable<[u16; 1]>;
+    type B = MockCacheable<[u16; 2]>;
+    type C = MockCacheable<[u16; 3]>;
+    let cache: Cache<1, 2> = Cache::default();
+    cache.reserve::<A>().unwrap();
+    cache.reserve::<A>().unwrap();
+    A::assert_loads(0);
+    cache.get::<A>().unwrap();
+    cache.get::<B>().unwrap();
+    cache.get::<C>().unwrap();
+    cache.get::<B>().unwrap();
+    cache.get::<A>().unwrap();
+    A::assert_loads(1);
+    C::assert_loads(1);
+    B::assert_loads(2);
+    assert!(matches!(
+        cache.reserve::<B>(),
+        Err(CacheError::Collision { .. })
+    ));
+}
+
+#[test]
+fn group_hint() {
+    #[derive(Default)]
+    struct Hinted<const N: usize>;
+
+    impl<const N: usize> Cacheable for Hinted<N> {
+        const GROUP_HINT: Option<usize> = Some(N);
+
+        fn load() -> std::io::Result<Self> {
+            Ok(Self)
+        }
+
+        fn store(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let cache: Cache<2, 1> = Cache::default();
+    cache.register::<Hinted<0>>().unwrap();
+    cache.register::<Hinted<1>>().unwrap();
+    assert!(matches!(
+        cache.register::<Hinted<2>>(),
+        Err(CacheError::Collision { .. })
+    ));
+    let _a = cache.get::<Hinted<0>>().unwrap();
+    let _b = cache.get::<Hinted<1>>().unwrap();
+}
+
+#[test]
+fn thrashing() {
+    type A = MockCacheable<[u16; 4]>;
+    type B = MockCacheable<[u16; 5]>;
+    let cache: Cache<1, 1> = Cache::default();
+    let thrashing = cache.thrashing();
+    cache.get::<A>().unwrap();
+    cache.get::<B>().unwrap();
+    assert!(thrashing.try_recv().is_err());
+    cache.get::<A>().unwrap();
+    cache.get::<B>().unwrap();
+    let events = thrashing.try_iter().collect::<Vec<_>>();
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        events[0].types,
+        (std::any::type_name::<A>(), std::any::type_name::<B>())
+    );
+    assert_eq!(events[1].count, 3);
+}
+
+#[test]
+fn tuning_report() {
+    type A = MockCacheable<[u16; 6]>;
+    type B = MockCacheable<[u16; 7]>;
+    let cache: Cache<1, 1> = Cache::default();
+    cache.get::<A>().unwrap();
+    cache.get::<A>().unwrap();
+    cache.get::<B>().unwrap();
+    cache.get::<A>().unwrap();
+    let report = cache.tuning_report().unwrap();
+    assert_eq!(report.groups[0].hits, 1);
+    assert_eq!(report.groups[0].misses, 3);
+    assert_eq!(report.groups[0].conflicts, 2);
+    assert_eq!(report.groups[0].occupied, 1);
+    assert_eq!(report.recommended, (1, 2));
+    assert_eq!(report.collisions[0].1.len(), 2);
+}
+
+#[test]
+fn commit() {
+    type A = MockCacheable<[u16; 8]>;
+    let cache: Cache<1, 1> = Cache::default();
+    {
+        let mut a = cache.get_mut::<A>().unwrap();
+        a.value = [1; 8];
+        A::fail_next_stores(1);
+        assert!(a.commit().is_err());
+        a.commit().unwrap();
+        A::assert_stored(&[1; 8]);
+    }
+    // clean, not written back again
+    drop(cache);
+    A::assert_stores(2);
+}
+
+#[test]
+fn rollback() {
+    type A = MockCacheable<[u16; 9]>;
+    A::set_stored([1; 9]);
+    let cache: Cache<1, 1> = Cache::default();
+    let updates = cache.watch::<A>();
+    let mut a = cache.get_mut::<A>().unwrap();
+    a.value = [2; 9];
+    a.rollback();
+    // loaded, but never modified
+    assert!(updates.try_recv().is_ok());
+    assert!(updates.try_recv().is_err());
+    assert_eq!(cache.get::<A>().unwrap().value, [1; 9]);
+    drop(cache);
+    A::assert_loads(2);
+    A::assert_stores(0);
+    // restored from the snapshot of the committed one instead of loading again
+    let cache: Cache<1, 1> = Cache::builder().snapshot_reads(true).build();
+    let mut a = cache.get_mut::<A>().unwrap();
+    a.value = [3; 9];
+    a.commit().unwrap();
+    a.value = [4; 9];
+    a.rollback();
+    assert_eq!(cache.get::<A>().unwrap().value, [3; 9]);
+    drop(cache);
+    A::assert_loads(3);
+    A::assert_stores(1);
+}
+
+#[test]
+fn undo_redo() {
+    type A = MockCacheable<[u16; 10]>;
+    let cache: Cache<1, 1> = Cache::builder().history(2).build();
+    for i in 1..=3 {
+        cache.get_mut::<A>().unwrap().value = [i; 10];
+    }
+    assert!(cache.undo::<A>().unwrap());
+    assert_eq!(cache.get::<A>().unwrap().value, [2; 10]);
+    assert!(cache.undo::<A>().unwrap());
+    assert_eq!(cache.get::<A>().unwrap().value, [1; 10]);
+    // only 2 recorded
+    assert!(!cache.undo::<A>().unwrap());
+    assert!(cache.redo::<A>().unwrap());
+    assert_eq!(cache.get::<A>().unwrap().value, [2; 10]);
+    cache.get_mut::<A>().unwrap().value = [4; 10];
+    assert!(!cache.redo::<A>().unwrap());
+    assert!(cache.undo::<A>().unwrap());
+    assert_eq!(cache.get::<A>().unwrap().value, [2; 10]);
+}
+
+#[derive(Default, Clone)]
+struct Chunks([usize; 4]);
+
+static CHANGED: std::sync::Mutex<Vec<usize>> = std::sync::Mutex::new(Vec::new());
+
+impl Cacheable for Chunks {
+    const STORE_DELTA: bool = true;
+
+    fn load() -> std::io::Result<Self> {
+        Ok(Self::default())
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        CHANGED.lock().unwrap().extend(0..4);
+        Ok(())
+    }
+
+    fn store_delta(&self, since: &Self) -> std::io::Result<()> {
+        let changed = (0..4).filter(|&i| self.0[i] != since.0[i]);
+        CHANGED.lock().unwrap().extend(changed);
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Option<Self> {
+        Some(self.clone())
+    }
+}
+
+#[test]
+fn store_delta() {
+    let cache: Cache<1, 2> = Cache::builder().max_dirty(0).build();
+    cache.get_mut::<Chunks>().unwrap().0[1] = 1;
+    cache.get::<Data<35>>().unwrap();
+    assert_eq!(*CHANGED.lock().unwrap(), [1]);
+    // The baseline is refreshed after written back.
+    cache.get_mut::<Chunks>().unwrap().0[3] = 1;
+    cache.get::<Data<35>>().unwrap();
+    assert_eq!(*CHANGED.lock().unwrap(), [1, 3]);
+}
+
+#[test]
+#[cfg_attr(miri, ignore = "timing-sensitive")]
+fn store_workers() {
+    use std::time::Duration;
+    type A = MockCacheable<[u16; 11]>;
+    type B = MockCacheable<[u16; 12]>;
+    let cache: Cache<1, 1> = Cache::builder().write_buffer(4).store_workers(2).build();
+    A::hold_stores();
+    B::hold_stores();
+    cache.get_mut::<A>().unwrap().value = [1; 11];
+    cache.get_mut::<B>().unwrap().value = [1; 12];
+    cache.get::<Data<36>>().unwrap();
+    // A and B are written back at the same time
+    assert!(A::wait_held_stores(1, Duration::from_secs(5)));
+    assert!(B::wait_held_stores(1, Duration::from_secs(5)));
+    A::release_stores();
+    B::release_stores();
+    cache.close().unwrap();
+    A::assert_stores(1);
+    B::assert_stores(1);
+}
+
+#[test]
+#[cfg_attr(miri, ignore = "timing-sensitive")]
+fn sweep() {
+    use std::time::Duration;
+    type A = MockCacheable<[u16; 13]>;
+    type B = MockCacheable<[u16; 14]>;
+    let cache: Cache<1, 2> = Cache::builder().sweep(Duration::from_millis(10)).build();
+    cache.get_mut::<A>().unwrap().value = [1; 13];
+    // an empty line is left
+    std::thread::sleep(Duration::from_millis(100));
+    A::assert_stores(0);
+    cache.get::<B>().unwrap();
+    // A is evicted in background for the next miss
+    assert!(A::wait_stores(1, Duration::from_secs(5)));
+    A::assert_stores(1);
+    A::assert_stored(&[1; 13]);
+    let evictions = cache.evictions();
+    cache.get::<Data<37>>().unwrap();
+    assert!(evictions.try_recv().is_err());
+}
+
+#[test]
+fn type_mismatch() {
+    type A = MockCacheable<[u16; 15]>;
+    let cache: Cache<1, 1> = Cache::default();
+    // the line holds the runtime registered value of A's type id instead of A
+    cache
+        .register_dyn(
+            std::any::TypeId::of::<A>(),
+            "plugin",
+            || Ok(Box::new(0_usize)),
+            |_| Ok(()),
+        )
+        .unwrap();
+    cache.get_dyn(std::any::TypeId::of::<A>()).unwrap();
+    assert!(matches!(
+        cache.get::<A>(),
+        Err(CacheError::TypeMismatch {
+            found: "plugin",
+            ..
+        })
+    ));
+    assert!(matches!(
+        cache.get_mut::<A>(),
+        Err(CacheError::TypeMismatch { .. })
+    ));
+    // and the other way around
+    let cache: Cache<1, 1> = Cache::default();
+    cache
+        .register_dyn(
+            std::any::TypeId::of::<A>(),
+            "plugin",
+            || Ok(Box::new(0_usize)),
+            |_| Ok(()),
+        )
+        .unwrap();
+    cache.get::<A>().unwrap();
+    assert!(matches!(
+        cache.get_dyn(std::any::TypeId::of::<A>()),
+        Err(CacheError::TypeMismatch {
+            expected: "plugin",
+            ..
+        })
+    ));
+}
+
+#[test]
+#[cfg_attr(miri, ignore = "timing-sensitive")]
+fn watchdog() {
+    use std::time::Duration;
+    type A = MockCacheable<[u16; 16]>;
+    let cache: Cache<1, 1> = Cache::builder().watchdog(Duration::from_millis(20)).build();
+    let long_held = cache.long_held();
+    let a = cache.get_mut::<A>().unwrap();
+    let event = long_held.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(event.type_name, std::any::type_name::<A>());
+    assert!(event.mutable && event.held >= Duration::from_millis(20));
+    // reported once
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(long_held.try_recv().is_err());
+    // nor reported again once released
+    drop(a);
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(long_held.try_recv().is_err());
+    // the type-erased guards are watched as well, and forgotten once released
+    cache.register::<A>().unwrap();
+    let type_id = std::any::TypeId::of::<A>();
+    let a = cache.get_mut_dyn(type_id).unwrap();
+    let event = long_held.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(event.type_name, std::any::type_name::<A>());
+    assert!(event.mutable);
+    drop(a);
+    let a = cache.get_dyn(type_id).unwrap();
+    let event = long_held.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert!(!event.mutable);
+    drop(a);
+    drop(cache.get_mut_dyn(type_id).unwrap());
+    drop(cache.get_dyn(type_id).unwrap());
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(long_held.try_recv().is_err());
+}
+
+#[test]
+fn with() {
+    type A = MockCacheable<[u16; 18]>;
+    let cache: Cache<1, 1> = Cache::default();
+    cache.with_mut::<A, _>(|a| a.value = [1; 18]).unwrap();
+    assert_eq!(cache.with::<A, _>(|a| a.value[0]).unwrap(), 1);
+    // the guard is released after the closure
+    cache.get_mut::<A>().unwrap();
+    let a = cache.get::<A>().unwrap();
+    assert!(matches!(
+        cache.with_mut::<A, _>(|_| ()),
+        Err(CacheError::Locked)
+    ));
+    drop(a);
+    cache.get::<Data<38>>().unwrap();
+    A::assert_stored(&[1; 18]);
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Seed<const N: usize>(u64);
+
+impl<const N: usize> From<u64> for Seed<N> {
+    fn from(seed: u64) -> Self {
+        Self(seed)
+    }
+}
+
+#[test]
+fn prop_model() {
+    use rom_cache::test_util::prop;
+    type Slots = (
+        MockCacheable<Seed<0>>,
+        MockCacheable<Seed<1>>,
+        MockCacheable<Seed<2>>,
+    );
+    let cases = if cfg!(miri) { 4 } else { 64 };
+    prop::run::<Slots, 1, 2>(cases, 0..32, Cache::default).unwrap();
+    prop::run::<Slots, 1, 2>(cases / 4, 0..32, || Cache::builder().max_dirty(1).build()).unwrap();
+}
+
+#[test]
+fn adaptive_policy() {
+    // A is accessed every 3rd time among a scan over 6 others, evicted by LRU before reused
+    fn scan<const G: usize, const L: usize>(cache: &Cache<G, L>, rounds: usize) {
+        for _ in 0..rounds {
+            cache.get::<Data<39>>().unwrap();
+            cache.get::<Data<40>>().unwrap();
+            cache.get::<Data<41>>().unwrap();
+            cache.get::<Data<39>>().unwrap();
+            cache.get::<Data<42>>().unwrap();
+            cache.get::<Data<43>>().unwrap();
+            cache.get::<Data<39>>().unwrap();
+            cache.get::<Data<44>>().unwrap();
+            cache.get::<Data<45>>().unwrap();
+        }
+    }
+    let lru: Cache<1, 2> = Cache::default();
+    scan(&lru, 10);
+    let report = lru.tuning_report().unwrap();
+    assert_eq!(report.groups[0].hits, 0);
+    assert!(!report.groups[0].lfu);
+
+    let adaptive: Cache<1, 2> = Cache::builder().adaptive_policy(9).build();
+    scan(&adaptive, 10);
+    let report = adaptive.tuning_report().unwrap();
+    assert!(report.groups[0].lfu);
+    assert!(report.groups[0].hits >= 20);
+}
+
+#[test]
+fn alloc_hook() {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default, Clone)]
+    struct Tracker(Arc<Mutex<HashMap<&'static str, isize>>>);
+
+    impl rom_cache::AllocHook for Tracker {
+        fn alloc(&self, type_name: &'static str, layout: std::alloc::Layout) {
+            *self.0.lock().unwrap().entry(type_name).or_default() += layout.size() as isize;
+        }
+
+        fn dealloc(&self, type_name: &'static str, layout: std::alloc::Layout) {
+            *self.0.lock().unwrap().entry(type_name).or_default() -= layout.size() as isize;
+        }
+    }
+
+    const WORD: isize = std::mem::size_of::<usize>() as isize;
+    let tracker = Tracker::default();
+    let bytes = |type_name: &str| tracker.0.lock().unwrap().get(type_name).copied();
+    let cache: Cache<1, 1> = Cache::builder().alloc_hook(tracker.clone()).build();
+    assert!(bytes(std::any::type_name::<Cache<1, 1>>()).unwrap() > 0);
+    cache.get::<Data<46>>().unwrap();
+    assert_eq!(bytes(std::any::type_name::<Data<46>>()), Some(WORD));
+    cache.get::<Data<47>>().unwrap();
+    assert_eq!(bytes(std::any::type_name::<Data<46>>()), Some(0));
+    assert_eq!(bytes(std::any::type_name::<Data<47>>()), Some(WORD));
+    drop(cache);
+    assert!(tracker.0.lock().unwrap().values().all(|&n| n == 0));
+}
+
+#[test]
+fn global() {
+    use rom_cache::global::{self, GlobalCache};
+    type A = MockCacheable<[u16; 19]>;
+    global::get_mut::<A>().unwrap().value = [1; 19];
+    assert_eq!(global::get::<A>().unwrap().value, [1; 19]);
+    global::reset().unwrap();
+    A::assert_stored(&[1; 19]);
+    assert_eq!(global::get::<A>().unwrap().value, [1; 19]);
+    A::assert_loads(2);
+
+    static READ_ONLY: GlobalCache<1, 1> =
+        GlobalCache::with(|| Cache::builder().read_only(true).build());
+    assert!(matches!(
+        READ_ONLY.get_mut::<A>(),
+        Err(CacheError::ReadOnly)
+    ));
+    assert!(READ_ONLY.init(Cache::default()).is_err());
+}
+
+#[test]
+fn get_owned() {
+    fn assert_send<T: Send>(_: &T) {}
+    let cache: Cache<1, 1> = Cache::default();
+    let owned = cache.get_owned::<Data<48>>().unwrap();
+    assert_send(&owned);
+    drop(cache);
+    let handle = std::thread::spawn(move || owned.inner);
+    assert_eq!(handle.join().unwrap(), 48);
+}
+
+#[test]
+fn axum_extractor() {
+    use axum_core::extract::FromRequestParts;
+    use axum_core::response::IntoResponse;
+    use rom_cache::axum::{CacheRejection, CachedRef};
+    use std::future::Future;
+    use std::task::{Context, Poll, Waker};
+
+    fn extract<T: Cacheable + Send + Sync + Default>(
+        cache: &Cache<1, 1>,
+    ) -> Result<CachedRef<T, 1, 1>, CacheRejection> {
+        let mut parts = http::Request::new(()).into_parts().0;
+        let mut fut = std::pin::pin!(CachedRef::<T, 1, 1>::from_request_parts(&mut parts, cache));
+        match fut.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(res) => res,
+            Poll::Pending => unreachable!("the extraction never waits"),
+        }
+    }
+
+    type A = MockCacheable<[u16; 20]>;
+    A::reset();
+    let cache: Cache<1, 1> = Cache::default();
+    assert_eq!(extract::<A>(&cache).ok().unwrap().value, [0; 20]);
+    let held = cache.get_mut::<A>().unwrap();
+    let res = extract::<A>(&cache).err().unwrap().into_response();
+    assert_eq!(res.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers()[http::header::RETRY_AFTER], "1");
+    drop(held);
+    assert!(extract::<A>(&cache).is_ok());
+}
+
+#[test]
+fn bevy_system_params() {
+    use bevy_ecs::world::World;
+    use rom_cache::bevy::{Cached, CachedMut};
+
+    type A = MockCacheable<[u16; 21]>;
+    A::reset();
+    let mut world = World::new();
+    world.insert_resource(Cache::<1, 2>::default());
+    let read = world.register_system(|a: Cached<A, 1, 2>| (a.is_changed(), a.get().unwrap().value));
+    let write = world.register_system(|mut a: CachedMut<A, 1, 2>| {
+        a.get_mut().unwrap().value = [1; 21];
+    });
+    assert_eq!(world.run_system(read).unwrap(), (true, [0; 21]));
+    // loaded by the first run
+    assert_eq!(world.run_system(read).unwrap(), (true, [0; 21]));
+    assert_eq!(world.run_system(read).unwrap(), (false, [0; 21]));
+    world.run_system(write).unwrap();
+    assert_eq!(world.run_system(read).unwrap(), (true, [1; 21]));
+    assert_eq!(world.run_system(read).unwrap(), (false, [1; 21]));
+    world
+        .remove_resource::<Cache<1, 2>>()
+        .unwrap()
+        .close()
+        .unwrap();
+    A::assert_stored(&[1; 21]);
+}
+
+#[test]
+fn scoped_cache() {
+    use rom_cache::ScopedCache;
+    type A = MockCacheable<[u16; 22]>;
+    type B = MockCacheable<[u16; 23]>;
+    A::reset();
+    B::reset();
+    let parent: Cache<1, 2> = Cache::default();
+    parent.get::<Data<48>>().unwrap();
+    let scope: ScopedCache<1, 2> = ScopedCache::new(&parent);
+    assert_eq!(scope.get::<Data<48>>().unwrap().inner, 48);
+    assert!(!scope.scope().contains::<Data<48>>().unwrap());
+    scope.get_mut::<A>().unwrap().value = [1; 22];
+    scope.get::<B>().unwrap();
+    assert!(!parent.contains::<A>().unwrap());
+    assert!(!parent.contains::<B>().unwrap());
+    scope.merge().unwrap();
+    assert!(parent.contains::<A>().unwrap());
+    assert!(!parent.contains::<B>().unwrap());
+    assert!(parent.contains::<Data<48>>().unwrap());
+    assert_eq!(parent.get::<A>().unwrap().value, [1; 22]);
+    A::assert_loads(1);
+    A::assert_stores(0);
+
+    // merged when dropped
+    let scope: ScopedCache<1, 2, 1, 1> = ScopedCache::with(&parent, Cache::default());
+    scope.get_mut::<B>().unwrap().value = [2; 23];
+    drop(scope);
+    assert_eq!(parent.get::<B>().unwrap().value, [2; 23]);
+    B::assert_loads(2);
+}
+
+#[test]
+fn depends() {
+    type Source = MockCacheable<[u16; 24]>;
+    static SOURCE_STORES: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+    #[derive(Default)]
+    struct Derived(u16);
+
+    impl Cacheable for Derived {
+        fn load() -> std::io::Result<Self> {
+            Ok(Self(Source::stored().map_or(0, |v| v[0])))
+        }
+
+        fn store(&self) -> std::io::Result<()> {
+            SOURCE_STORES.store(Source::stores(), Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    Source::reset();
+    let cache: Cache<1, 2> = Cache::default();
+    cache.depends::<Derived, Source>();
+    assert_eq!(cache.get::<Derived>().unwrap().0, 0);
+    cache.get_mut::<Source>().unwrap().value = [7; 24];
+    assert!(!cache.contains::<Derived>().unwrap());
+    // the source is written back before reloading
+    assert_eq!(cache.get::<Derived>().unwrap().0, 7);
+    Source::assert_stores(1);
+    assert!(cache.contains::<Derived>().unwrap());
+    assert!(cache.contains::<Source>().unwrap());
+    // the stale one is never handed out mutably either
+    cache.get_mut::<Source>().unwrap().value = [9; 24];
+    let derived = cache.get_mut_or_insert_with(|| Derived(9)).unwrap();
+    assert_eq!(derived.0, 9);
+    drop(derived);
+    Source::assert_stores(2);
+
+    cache.get_mut::<Derived>().unwrap().0 = 1;
+    cache.get_mut::<Source>().unwrap().value = [8; 24];
+    cache.close().unwrap();
+    assert_eq!(SOURCE_STORES.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn ttl_jitter() {
+    use std::time::Duration;
+    let ttl = Duration::from_secs(100);
+    let cache: Cache<1, 8> = Cache::builder().ttl(ttl).ttl_jitter(0.5).build();
+    let results = rom_cache::warm_up!(
+        cache,
+        Data<49>,
+        Data<50>,
+        Data<51>,
+        Data<52>,
+        Data<53>,
+        Data<54>,
+        Data<55>,
+        Data<56>
+    );
+    assert!(results.iter().all(|(_, res)| res.is_ok()));
+    let mut ttls = vec![];
+    cache
+        .for_each(|info, _| ttls.push(info.ttl.unwrap()))
+        .unwrap();
+    assert_eq!(ttls.len(), 8);
+    assert!(ttls.iter().all(|&t| t > ttl / 2 && t <= ttl));
+    ttls.dedup();
+    assert!(ttls.len() > 1);
+}
+
+#[test]
+fn memo_async() {
+    use std::future::Future;
+    use std::sync::atomic::AtomicBool;
+    use std::task::{Context, Poll, Waker};
+    type A = MockCacheable<[u16; 25]>;
+    A::reset();
+    let cache: Cache<1, 1> = Cache::default();
+    let computed = AtomicUsize::new(0);
+    let ready = AtomicBool::new(false);
+    let compute = || {
+        computed.fetch_add(1, Ordering::Relaxed);
+        std::future::poll_fn(|_| match ready.load(Ordering::Relaxed) {
+            true => Poll::Ready(Ok(A::new([3; 25]))),
+            false => Poll::Pending,
+        })
+    };
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut leader = std::pin::pin!(cache.memo_async(compute));
+    let mut follower = std::pin::pin!(cache.memo_async(compute));
+    assert!(leader.as_mut().poll(&mut cx).is_pending());
+    assert!(follower.as_mut().poll(&mut cx).is_pending());
+    ready.store(true, Ordering::Relaxed);
+    let Poll::Ready(Ok(value)) = leader.as_mut().poll(&mut cx) else {
+        panic!("not computed");
+    };
+    assert_eq!(value.value, [3; 25]);
+    drop(value);
+    let Poll::Ready(Ok(value)) = follower.as_mut().poll(&mut cx) else {
+        panic!("not shared");
+    };
+    assert_eq!(value.value, [3; 25]);
+    drop(value);
+    assert_eq!(computed.load(Ordering::Relaxed), 1);
+    drop(cache.flush_on_drop());
+    A::assert_stored(&[3; 25]);
+    A::assert_loads(0);
+}
+
+#[test]
+fn memo() {
+    use rom_cache::global::GlobalCache;
+    use rom_cache::CachedFn;
+
+    static CACHE: GlobalCache<1, 1> = GlobalCache::new();
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    rom_cache::memo! {
+        CACHE;
+        fn fib(n: u64) -> u64 {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            if n < 2 { n } else { fib(n - 1) + fib(n - 2) }
+        }
+    }
+    assert_eq!(fib(50), 12586269025);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 51);
+    assert_eq!(fib(50), 12586269025);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 51);
+
+    // functions of the same signature share a `CacheLine`, cached apart
+    let double = CachedFn::new(&CACHE, |(n,): (u64,)| n * 2);
+    assert_eq!(double.call((50,)), 100);
+    assert_eq!(fib(50), 12586269025);
+    assert_eq!(double.forget(&(50,)).unwrap(), Some(100));
+    assert_eq!(double.forget(&(50,)).unwrap(), None);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 51);
+
+    // evicted as a whole
+    CACHE.get::<Data<57>>().unwrap();
+    assert_eq!(fib(10), 55);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 62);
+}
+
+#[test]
+fn numa_nodes() {
+    use rom_cache::numa;
+
+    let cache: Cache<4, 1> = Cache::builder().numa_nodes(2).build();
+    numa::set_thread_node(Some(1));
+    cache.get::<Data<57>>().unwrap();
+    numa::set_thread_node(Some(0));
+    cache.get::<Data<58>>().unwrap();
+    // placed by the first access
+    cache.get::<Data<57>>().unwrap();
+    numa::set_thread_node(None);
+    let mut groups = vec![];
+    cache
+        .for_each(|info, _| groups.push((info.type_name, info.group)))
+        .unwrap();
+    groups.sort();
+    assert!(matches!(groups[..], [(_, 2..=3), (_, 0..=1)]));
+}
+
+#[test]
+fn read_replicas() {
+    use std::sync::Arc;
+    type A = MockCacheable<[u16; 26]>;
+
+    let cache: Cache<1, 2> = Cache::builder().read_replicas(2).build();
+    let first = cache.get_replica::<A>().unwrap();
+    assert!(Arc::ptr_eq(&first, &cache.get_replica::<A>().unwrap()));
+    A::assert_loads(1);
+    // served without touching the `CacheLine` being written
+    let mut a = cache.get_mut::<A>().unwrap();
+    assert!(Arc::ptr_eq(&first, &cache.get_replica::<A>().unwrap()));
+    a.value = [1; 26];
+    drop(a);
+    let second = cache.get_replica::<A>().unwrap();
+    assert_eq!(second.value, [1; 26]);
+    assert!(Arc::ptr_eq(&second, &cache.get_replica::<A>().unwrap()));
+    // each thread keeps its own
+    std::thread::scope(|s| {
+        s.spawn(|| assert!(!Arc::ptr_eq(&second, &cache.get_replica::<A>().unwrap())));
+    });
+    cache.bump_generation().unwrap();
+    assert!(!Arc::ptr_eq(&second, &cache.get_replica::<A>().unwrap()));
+
+    // copied each time without `CacheBuilder::read_replicas()`
+    let cache: Cache<1, 2> = Cache::default();
+    let first = cache.get_replica::<A>().unwrap();
+    assert!(!Arc::ptr_eq(&first, &cache.get_replica::<A>().unwrap()));
+}
+
+#[test]
+fn version() {
+    static VERSION: AtomicUsize = AtomicUsize::new(0);
+    static LOADS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default)]
+    struct Versioned(usize);
+
+    impl Cacheable for Versioned {
+        fn load() -> std::io::Result<Self> {
+            LOADS.fetch_add(1, Ordering::Relaxed);
+            Ok(Self(VERSION.load(Ordering::Relaxed)))
+        }
+
+        fn store(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        fn version() -> Option<u64> {
+            Some(VERSION.load(Ordering::Relaxed) as u64)
+        }
+    }
+
+    let cache: Cache<1, 2> = Cache::default();
+    assert_eq!(cache.get::<Versioned>().unwrap().0, 0);
+    assert_eq!(cache.get::<Versioned>().unwrap().0, 0);
+    assert_eq!(LOADS.load(Ordering::Relaxed), 1);
+    // written by someone else
+    VERSION.store(1, Ordering::Relaxed);
+    assert!(!cache.contains::<Versioned>().unwrap());
+    assert_eq!(cache.get::<Versioned>().unwrap().0, 1);
+    assert_eq!(cache.get::<Versioned>().unwrap().0, 1);
+    assert_eq!(LOADS.load(Ordering::Relaxed), 2);
+    assert!(cache.contains::<Versioned>().unwrap());
+}
+
+#[test]
+fn backend() {
+    use rom_cache::backend::{Dir, Memory};
+    use std::sync::Arc;
+
+    #[derive(Default)]
+    struct Counter(u32);
+
+    impl Cacheable for Counter {
+        const KEY: Option<&'static str> = Some("counter");
+
+        fn encode(&self) -> std::io::Result<Vec<u8>> {
+            Ok(self.0.to_le_bytes().to_vec())
+        }
+
+        fn decode(bytes: &[u8]) -> std::io::Result<Self> {
+            let bytes = bytes.try_into().map_err(std::io::Error::other)?;
+            Ok(Self(u32::from_le_bytes(bytes)))
+        }
+    }
+
+    let memory = Arc::new(Memory::default());
+    memory.insert("counter", 41u32.to_le_bytes());
+    let cache: Cache<1, 2> = Cache::with_backend(memory.clone());
+    cache.get_mut::<Counter>().unwrap().0 += 1;
+    cache.close().unwrap();
+    assert_eq!(memory.get("counter").unwrap(), 42u32.to_le_bytes());
+
+    // the same type against files
+    let dir = std::env::temp_dir().join(format!("rom-cache-backend-{}", std::process::id()));
+    let cache: Cache<1, 2> = Cache::with_backend(Dir::new(&dir));
+    assert_eq!(cache.get::<Counter>().unwrap().0, 0);
+    cache.get_mut::<Counter>().unwrap().0 = 7;
+    cache.close().unwrap();
+    let cache: Cache<1, 2> = Cache::with_backend(Dir::new(&dir));
+    assert_eq!(cache.get::<Counter>().unwrap().0, 7);
+    // namespaced in subdirectories, never outside of the directory
+    cache.scope("tenant").get_mut::<Counter>().unwrap().0 = 3;
+    cache.scope("../x").get_mut::<Counter>().unwrap().0 = 4;
+    cache.close().unwrap();
+    let read = |path: &str| std::fs::read(dir.join(path)).unwrap();
+    assert_eq!(read("tenant/counter"), 3u32.to_le_bytes());
+    assert_eq!(read("%2E%2E%2Fx/counter"), 4u32.to_le_bytes());
+    let cache: Cache<1, 2> = Cache::with_backend(Dir::new(&dir));
+    assert_eq!(cache.scope("../x").get::<Counter>().unwrap().0, 4);
+    drop(cache);
+    std::fs::remove_dir_all(dir).unwrap();
+
+    // without a backend, neither `load` nor `store` is implemented
+    let cache: Cache<1, 2> = Cache::default();
+    cache.get_mut::<Counter>().unwrap().0 = 1;
+    let errors = cache.close().unwrap_err();
+    assert_eq!(errors[0].1.kind(), std::io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn memory_pressure() {
+    use rom_cache::MemoryPressure;
+    type C = MockCacheable<[u16; 27]>;
+
+    let cache: Cache<1, 4> = Cache::default();
+    cache.get::<Data<57>>().unwrap();
+    cache.get_mut::<C>().unwrap().value = [1; 27];
+    cache.get::<Data<58>>().unwrap();
+    let held = cache.get::<Data<59>>().unwrap();
+    // only the colder half
+    assert_eq!(cache.on_memory_pressure(MemoryPressure::Low).unwrap(), 1);
+    assert!(!cache.contains::<Data<57>>().unwrap());
+    assert!(cache.contains::<Data<58>>().unwrap());
+    assert_eq!(
+        cache.on_memory_pressure(MemoryPressure::Moderate).unwrap(),
+        1
+    );
+    assert!(!cache.contains::<Data<58>>().unwrap());
+    assert!(cache.contains::<C>().unwrap());
+    C::assert_stores(0);
+    assert_eq!(
+        cache.on_memory_pressure(MemoryPressure::Critical).unwrap(),
+        1
+    );
+    C::assert_stored(&[1; 27]);
+    // being used
+    assert!(cache.contains::<Data<59>>().unwrap());
+    drop(held);
+}
+
+#[test]
+fn retain() {
+    type C = MockCacheable<[u16; 28]>;
+
+    let cache: Cache<1, 4> = Cache::default();
+    cache.get::<Data<60>>().unwrap();
+    cache.get_mut::<C>().unwrap().value = [1; 28];
+    let held = cache.get::<Data<61>>().unwrap();
+    let mut seen = vec![];
+    let dropped = cache
+        .retain(|info| {
+            seen.push(info.type_name);
+            assert!(info.age.is_some());
+            info.type_name == std::any::type_name::<Data<60>>()
+        })
+        .unwrap();
+    // being used
+    assert_eq!(dropped, 1);
+    assert_eq!(seen.len(), 2);
+    C::assert_stored(&[1; 28]);
+    assert!(!cache.contains::<C>().unwrap());
+    assert!(cache.contains::<Data<60>>().unwrap());
+    assert!(cache.contains::<Data<61>>().unwrap());
+    drop(held);
+    assert_eq!(cache.retain(|info| !info.dirty).unwrap(), 0);
+    assert_eq!(cache.retain(|_| false).unwrap(), 2);
+}
+
+#[test]
+fn namespace() {
+    use rom_cache::namespace::current;
+    static STORED: std::sync::Mutex<Vec<(Option<String>, usize)>> = std::sync::Mutex::new(vec![]);
+    #[derive(Default)]
+    struct Quota(usize);
+    impl Cacheable for Quota {
+        fn load() -> std::io::Result<Self> {
+            Ok(Self(match current().as_deref() {
+                Some("a") => 1,
+                Some(_) => 2,
+                None => 0,
+            }))
+        }
+
+        fn store(&self) -> std::io::Result<()> {
+            let namespace = current().map(|namespace| namespace.to_string());
+            STORED.lock().unwrap().push((namespace, self.0));
+            Ok(())
+        }
+    }
+
+    let cache: Cache<2, 4> = Cache::default();
+    let (a, b) = (cache.scope("a"), cache.scope("b"));
+    assert_eq!(a.get::<Quota>().unwrap().0, 1);
+    b.get_mut::<Quota>().unwrap().0 += 10;
+    assert_eq!(b.get::<Quota>().unwrap().0, 12);
+    assert_eq!(cache.get::<Quota>().unwrap().0, 0);
+    // rolled back to the one of the namespace
+    let mut quota = a.get_mut::<Quota>().unwrap();
+    quota.0 = 7;
+    quota.rollback();
+    assert_eq!(a.get::<Quota>().unwrap().0, 1);
+    let mut namespaces = vec![];
+    cache
+        .for_each(|info, _| namespaces.push(info.namespace.clone()))
+        .unwrap();
+    assert_eq!(namespaces.len(), 3);
+    assert_eq!(cache.drop_scope("b").unwrap(), 1);
+    assert!(!b.contains::<Quota>().unwrap());
+    assert!(a.contains::<Quota>().unwrap());
+    assert!(cache.contains::<Quota>().unwrap());
+    assert_eq!(*STORED.lock().unwrap(), [(Some("b".to_string()), 12)]);
+    assert_eq!(b.get::<Quota>().unwrap().0, 2);
+}
+
+#[test]
+fn scope_quota() {
+    use rom_cache::Quota;
+
+    let cache: Cache<1, 4> = Cache::builder()
+        .scope_quota(Quota {
+            lines: Some(2),
+            bytes: None,
+        })
+        .build();
+    let (quiet, noisy) = (cache.scope("quiet"), cache.scope("noisy"));
+    quiet.get::<Data<62>>().unwrap();
+    quiet.get::<Data<63>>().unwrap();
+    noisy.get::<Data<62>>().unwrap();
+    noisy.get::<Data<63>>().unwrap();
+    // evicts its own instead of the least recently used of the cache
+    noisy.get::<Data<64>>().unwrap();
+    assert!(quiet.contains::<Data<62>>().unwrap());
+    assert!(quiet.contains::<Data<63>>().unwrap());
+    assert!(!noisy.contains::<Data<62>>().unwrap());
+    assert!(noisy.contains::<Data<64>>().unwrap());
+    let quota = Quota {
+        lines: Some(1),
+        bytes: None,
+    };
+    cache.set_scope_quota("noisy", Some(quota));
+    noisy.get::<Data<64>>().unwrap();
+    assert!(!noisy.contains::<Data<63>>().unwrap());
+    let quota = Quota {
+        lines: None,
+        bytes: Some(std::mem::size_of::<Data<63>>()),
+    };
+    cache.set_scope_quota("quiet", Some(quota));
+    quiet.get::<Data<63>>().unwrap();
+    assert!(!quiet.contains::<Data<62>>().unwrap());
+    assert!(quiet.contains::<Data<63>>().unwrap());
+}
+
+#[test]
+fn load_rate() {
+    #[derive(Default)]
+    struct Limited;
+    impl Cacheable for Limited {
+        const LOAD_RATE: Option<(f64, usize)> = Some((0.0, 1));
+
+        fn load() -> std::io::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    let cache: Cache<1, 4> = Cache::builder().load_rate(0.0, 2).build();
+    cache.get::<Data<65>>().unwrap();
+    cache.get::<Data<66>>().unwrap();
+    assert!(matches!(
+        cache.get::<Data<67>>(),
+        Err(CacheError::RateLimited { .. })
+    ));
+    // hits are not limited
+    cache.get::<Data<65>>().unwrap();
+    // the stale one is served instead
+    cache.bump_generation().unwrap();
+    assert_eq!(cache.get::<Data<65>>().unwrap().inner, 65);
+
+    let cache: Cache<1, 1> = Cache::default();
+    cache.get::<Limited>().unwrap();
+    cache.get::<Data<67>>().unwrap();
+    assert!(matches!(
+        cache.get::<Limited>(),
+        Err(CacheError::RateLimited { .. })
+    ));
+}
+
+#[test]
+fn circuit_breaker() {
+    static LOADS: AtomicUsize = AtomicUsize::new(0);
+    #[derive(Default)]
+    struct Failing;
+    impl Cacheable for Failing {
+        fn load() -> std::io::Result<Self> {
+            LOADS.fetch_add(1, Ordering::Relaxed);
+            Err(std::io::ErrorKind::ConnectionRefused.into())
+        }
+    }
+
+    let cache: Cache<1, 1> = Cache::builder()
+        .circuit_breaker(2, std::time::Duration::from_secs(3600))
+        .build();
+    cache.get::<Failing>().unwrap();
+    cache.bump_generation().unwrap();
+    cache.get::<Failing>().unwrap();
+    assert_eq!(LOADS.load(Ordering::Relaxed), 2);
+    // open, the stale default is served
+    cache.bump_generation().unwrap();
+    cache.get::<Failing>().unwrap();
+    cache.get::<Data<68>>().unwrap();
+    assert!(matches!(
+        cache.get::<Failing>(),
+        Err(CacheError::CircuitOpen { .. })
+    ));
+    assert_eq!(LOADS.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn load_timeout() {
+    use std::time::Duration;
+    #[derive(Default)]
+    struct Hung;
+    impl Cacheable for Hung {
+        fn load() -> std::io::Result<Self> {
+            std::thread::sleep(Duration::from_millis(200));
+            Ok(Self)
+        }
+    }
+
+    let cache: Cache<1, 2> = Cache::builder()
+        .load_timeout(Duration::from_millis(10))
+        .build();
+    assert!(matches!(
+        cache.get::<Hung>(),
+        Err(CacheError::Timeout { .. })
+    ));
+    assert!(!cache.contains::<Hung>().unwrap());
+    assert_eq!(cache.get::<Data<69>>().unwrap().inner, 69);
+}
+
+#[test]
+fn max_loads() {
+    use std::time::Duration;
+    #[derive(Default)]
+    struct Slow;
+    impl Cacheable for Slow {
+        fn load() -> std::io::Result<Self> {
+            std::thread::sleep(Duration::from_millis(200));
+            Ok(Self)
+        }
+    }
+
+    let cache: Cache<1, 4> = Cache::builder()
+        .max_loads(1)
+        .load_timeout(Duration::from_millis(20))
+        .build();
+    assert!(matches!(
+        cache.get::<Slow>(),
+        Err(CacheError::Timeout { .. })
+    ));
+    // waits in line behind the abandoned load
+    assert!(matches!(
+        cache.get::<Data<70>>(),
+        Err(CacheError::Timeout { .. })
+    ));
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(cache.get::<Data<70>>().unwrap().inner, 70);
+}
+
+#[test]
+fn store_batch() {
+    use rom_cache::backend::Memory;
+    use rom_cache::{Backend, StoreBatch};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Db {
+        memory: Memory,
+        stores: AtomicUsize,
+        batches: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl Backend for Db {
+        fn load(&self, key: &str) -> std::io::Result<Option<Vec<u8>>> {
+            self.memory.load(key)
+        }
+
+        fn store(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+            self.stores.fetch_add(1, Ordering::Relaxed);
+            self.memory.store(key, bytes)
+        }
+    }
+
+    impl StoreBatch for Db {
+        fn store_batch(&self, entries: &[(&str, &[u8])]) -> std::io::Result<()> {
+            let mut keys = entries
+                .iter()
+                .map(|(key, _)| key.to_string())
+                .collect::<Vec<_>>();
+            keys.sort();
+            self.batches.lock().unwrap().push(keys);
+            self.memory.store_batch(entries)
+        }
+    }
+
+    #[derive(Default)]
+    struct Left(u8);
+    #[derive(Default)]
+    struct Right(u8);
+
+    impl Cacheable for Left {
+        const KEY: Option<&'static str> = Some("left");
+
+        fn encode(&self) -> std::io::Result<Vec<u8>> {
+            Ok(vec![self.0])
+        }
+    }
+
+    impl Cacheable for Right {
+        const KEY: Option<&'static str> = Some("right");
+
+        fn encode(&self) -> std::io::Result<Vec<u8>> {
+            Ok(vec![self.0])
+        }
+    }
+
+    let db = Arc::new(Db::default());
+    let cache: Cache<1, 4> = Cache::builder()
+        .backend(db.clone())
+        .store_batch(db.clone())
+        .build();
+    cache.get_mut::<Left>().unwrap().0 = 1;
+    cache.get_mut::<Right>().unwrap().0 = 2;
+    // without a key, still stored by itself
+    cache.get_mut::<Data<71>>().unwrap().inner = 71;
+    let stored = STORED.load(Ordering::Relaxed);
+    cache.close().unwrap();
+    assert_eq!(*db.batches.lock().unwrap(), [["left", "right"]]);
+    assert_eq!(db.stores.load(Ordering::Relaxed), 0);
+    assert_eq!(db.memory.get("right").unwrap(), [2]);
+    assert!(STORED.load(Ordering::Relaxed) > stored);
+}
+
+#[test]
+fn debug_json() {
+    #[derive(Default)]
+    struct Shown(u32);
+
+    impl Cacheable for Shown {
+        fn load() -> std::io::Result<Self> {
+            Ok(Self(7))
+        }
+
+        fn debug_json(&self) -> Option<serde_json::Value> {
+            Some(self.0.into())
+        }
+    }
+
+    let cache: Cache<2, 4> = Cache::default();
+    cache.get::<Shown>().unwrap();
+    let _held = cache.get_mut::<Data<72>>().unwrap();
+    let json = cache.debug_json().unwrap();
+    assert_eq!(json["shape"]["groups"], 2);
+    assert_eq!(json["misses"], 2);
+    let lines = json["groups"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .flat_map(|g| g["lines"].as_array().unwrap().clone())
+        .collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    let shown = lines
+        .iter()
+        .find(|l| l["type_name"].as_str().unwrap().ends_with("Shown"))
+        .unwrap();
+    assert_eq!(shown["value"], 7);
+    assert_eq!(shown["flags"]["dirty"], false);
+    // values are hidden unless opted in, and never read while being written
+    let held = lines
+        .iter()
+        .find(|l| l["flags"]["writing"] == true)
+        .unwrap();
+    assert!(held.get("value").is_none());
+}
+
+#[test]
+fn cache_service() {
+    use rom_cache::CacheService;
+
+    #[derive(Default, Clone)]
+    struct Counter(u32);
+
+    impl Cacheable for Counter {
+        fn load() -> std::io::Result<Self> {
+            Ok(Self(0))
+        }
+
+        fn store(&self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let service = CacheService::spawn(Cache::<2, 2>::default());
+    let handles = (0..4)
+        .map(|_| {
+            let service = service.clone();
+            std::thread::spawn(move || service.get_mut(|counter: &mut Counter| counter.0 += 1))
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap().unwrap();
+    }
+    assert_eq!(service.get::<Counter>().unwrap().0, 4);
+    assert_eq!(service.read(|counter: &Counter| counter.0 * 2).unwrap(), 8);
+    service.flush().unwrap();
+
+    // a panic is resumed on the caller, and the service keeps serving
+    let panicked = std::panic::catch_unwind(|| service.read(|_: &Counter| panic!("boom")));
+    assert!(panicked.is_err());
+    assert_eq!(service.get::<Counter>().unwrap().0, 4);
+}
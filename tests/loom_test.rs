@@ -72,3 +72,91 @@ fn loom_test() -> core::result::Result<(), Box<dyn std::error::Error>> {
     });
     Ok(())
 }
+
+loom::lazy_static! {
+    static ref COUNTER: AtomicUsize = AtomicUsize::new(0);
+}
+
+#[derive(Default)]
+struct Counter {
+    inner: usize,
+}
+
+impl Cacheable for Counter {
+    fn load() -> std::io::Result<Self>
+    where
+        Self: Sized,
+    {
+        let inner = COUNTER.load(Ordering::Acquire);
+        Ok(Self { inner })
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        COUNTER.store(self.inner, Ordering::Release);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Other;
+
+impl Cacheable for Other {
+    fn load() -> std::io::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self)
+    }
+
+    fn store(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+#[cfg_attr(not(loom), ignore = "loom only test")]
+fn loom_read_write() {
+    loom::model(|| {
+        let cache: Cache<1, 1> = Cache::default();
+        let writer = {
+            let cache = cache.clone();
+            loom::thread::spawn(move || match cache.get_mut::<Counter>() {
+                Ok(mut counter) => {
+                    counter.inner += 1;
+                    true
+                }
+                Err(_) => false,
+            })
+        };
+        if let Ok(counter) = cache.get::<Counter>() {
+            assert!(counter.inner <= 1);
+        }
+        let written = writer.join().unwrap();
+        let counter = cache.get::<Counter>().unwrap();
+        assert_eq!(counter.inner, written as usize);
+    });
+}
+
+#[test]
+#[cfg_attr(not(loom), ignore = "loom only test")]
+fn loom_write_evict() {
+    loom::model(|| {
+        let cache: Cache<1, 1> = Cache::default();
+        let writer = {
+            let cache = cache.clone();
+            loom::thread::spawn(move || match cache.get_mut::<Counter>() {
+                Ok(mut counter) => {
+                    counter.inner += 1;
+                    true
+                }
+                Err(_) => false,
+            })
+        };
+        // evicts Counter if it is cached and unused, writing it back
+        let evicted = cache.get::<Other>().is_ok();
+        let written = writer.join().unwrap();
+        assert!(evicted || written);
+        drop(cache);
+        assert_eq!(COUNTER.load(Ordering::Acquire), written as usize);
+    });
+}